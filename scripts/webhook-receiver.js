@@ -0,0 +1,60 @@
+import http from 'http';
+import crypto from 'crypto';
+
+// Local endpoint for trying out webhooks: point one at
+// http://127.0.0.1:8787/ (or PORT) and every delivery is printed here.
+// Set WEBHOOK_SECRET to the webhook's secret to check signatures; requests
+// that fail the check are answered with 401.
+const port = Number(process.env.PORT || 8787);
+const secret = process.env.WEBHOOK_SECRET || '';
+// Set FAIL_STATUS (for example 503) to answer with an error and watch retries.
+const failStatus = Number(process.env.FAIL_STATUS || 0);
+// Signed requests older (or newer) than this are treated as replays.
+const maxSkewSeconds = 5 * 60;
+
+// The signature is the HMAC-SHA256 of `<timestamp>.<raw body>`.
+function checkSignature(body, timestamp, header) {
+  if (!secret) return 'not checked';
+  if (!header) return 'MISSING';
+  if (!/^\d+$/.test(timestamp || '')) return 'MISSING TIMESTAMP';
+  if (Math.abs(Date.now() / 1000 - Number(timestamp)) > maxSkewSeconds) return 'STALE';
+
+  const expected =
+    'sha256=' +
+    crypto.createHmac('sha256', secret).update(`${timestamp}.`).update(body).digest('hex');
+  const valid =
+    header.length === expected.length &&
+    crypto.timingSafeEqual(Buffer.from(header), Buffer.from(expected));
+  return valid ? 'valid' : 'INVALID';
+}
+
+const server = http.createServer((req, res) => {
+  const chunks = [];
+  req.on('data', (chunk) => chunks.push(chunk));
+  req.on('end', () => {
+    const body = Buffer.concat(chunks);
+    const event = req.headers['x-widget-event'];
+    const delivery = req.headers['x-widget-delivery'];
+    const signature = checkSignature(
+      body,
+      req.headers['x-widget-timestamp'],
+      req.headers['x-widget-signature'],
+    );
+
+    console.log(`\n${new Date().toISOString()} ${req.method} ${req.url}`);
+    console.log(`event: ${event}  delivery: ${delivery}  signature: ${signature}`);
+    try {
+      console.log(JSON.stringify(JSON.parse(body.toString()), null, 2));
+    } catch {
+      console.log(body.toString());
+    }
+
+    const rejected = !['valid', 'not checked'].includes(signature);
+    res.writeHead(rejected ? 401 : failStatus || 204);
+    res.end();
+  });
+});
+
+server.listen(port, '127.0.0.1', () => {
+  console.log(`Webhook receiver listening on http://127.0.0.1:${port}/`);
+});
@@ -0,0 +1,459 @@
+//! Local control API, served under `/api/v1` by the embedded axum server.
+//! Every request needs the per-install key, sent as `Authorization: Bearer
+//! <key>` or `X-Api-Key: <key>`. Errors come back as `{"error": "..."}`.
+//!
+//! `spotify-widget-ctl` is the command-line client for this API.
+
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use crate::{
+    config::{ApiSettings, SharedConfig},
+    history::{self, HistoryDb, HistoryFilter, HistoryPage},
+    lyrics::LyricsEngine,
+    playback::PlaybackHub,
+    player::{self, PlayRequest, PlayerError},
+    spotify::{SpotifyClient, SpotifyError},
+};
+
+const API_KEY_LENGTH: usize = 40;
+const DEFAULT_HISTORY_LIMIT: u64 = 20;
+
+#[derive(Clone)]
+struct ApiState {
+    config: SharedConfig,
+    spotify: Arc<SpotifyClient>,
+    hub: Arc<PlaybackHub>,
+    lyrics: Arc<LyricsEngine>,
+    history: Arc<HistoryDb>,
+}
+
+pub(crate) struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// A missing login is the server's problem rather than the client's, so it
+/// is reported as unavailable instead of as a bad request.
+impl From<SpotifyError> for ApiError {
+    fn from(error: SpotifyError) -> Self {
+        let status = match error {
+            SpotifyError::NotAuthenticated => StatusCode::SERVICE_UNAVAILABLE,
+            SpotifyError::Api { .. } | SpotifyError::Request(_) => StatusCode::BAD_GATEWAY,
+        };
+        ApiError(status, error.to_string())
+    }
+}
+
+impl From<PlayerError> for ApiError {
+    fn from(error: PlayerError) -> Self {
+        match error {
+            PlayerError::Invalid(message) => ApiError(StatusCode::BAD_REQUEST, message),
+            PlayerError::Spotify(error) => error.into(),
+        }
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+fn generate_key() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(API_KEY_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Creates the API and overlay keys on first start.
+pub fn ensure_api_keys(config: &SharedConfig) {
+    let Ok(mut config) = config.lock() else {
+        return;
+    };
+    if !config.api.api_key.is_empty() && !config.api.overlay_key.is_empty() {
+        return;
+    }
+    if config.api.api_key.is_empty() {
+        config.api.api_key = generate_key();
+    }
+    if config.api.overlay_key.is_empty() {
+        config.api.overlay_key = generate_key();
+    }
+    if let Err(e) = config.save() {
+        eprintln!("Failed to save API keys: {}", e);
+    }
+}
+
+/// The address the API, WebSocket and overlay are also served on, when the
+/// configured one is not loopback. Loopback is always served, so an address
+/// that does not parse only loses the extra listener.
+pub fn remote_bind_address(config: &SharedConfig, port: u16) -> Option<SocketAddr> {
+    let configured = config
+        .lock()
+        .map(|config| config.api.bind_address.clone())
+        .unwrap_or_default();
+    match configured.parse::<IpAddr>() {
+        Ok(ip) if !ip.is_loopback() => Some(SocketAddr::new(ip, port)),
+        Ok(_) => None,
+        Err(e) => {
+            eprintln!("Invalid API bind address {:?}: {}", configured, e);
+            None
+        }
+    }
+}
+
+/// Answers `404` to anyone but a loopback client. The OAuth routes are only
+/// routed on the loopback listener, except when a wildcard address takes the
+/// loopback port too; this keeps them local in that case as well.
+pub async fn loopback_only(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if peer.ip().to_canonical().is_loopback() {
+        next.run(request).await
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+/// Compares without bailing out at the first difference, so response
+/// times do not leak how much of a guessed key was right.
+fn keys_match(provided: &str, expected: &str) -> bool {
+    provided.len() == expected.len()
+        && provided
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn provided_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key.trim());
+    }
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// What the key sent with a request grants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    /// The API key: everything.
+    Control,
+    /// The overlay key: the overlay and the `/ws` stream only. It ends up in
+    /// OBS scene files and screenshots, so it cannot change playback.
+    ReadOnly,
+}
+
+/// Checks the key sent with a request and returns what it grants.
+/// `query_key` is for clients that cannot set headers, like WebSockets
+/// opened from a browser.
+pub(crate) fn check_key(
+    config: &SharedConfig,
+    headers: &HeaderMap,
+    query_key: Option<&str>,
+) -> Result<Access, ApiError> {
+    let settings = config
+        .lock()
+        .map(|config| config.api.clone())
+        .unwrap_or_default();
+    if !settings.enabled {
+        return Err(ApiError(
+            StatusCode::NOT_FOUND,
+            "The local API is disabled".to_string(),
+        ));
+    }
+
+    let provided = provided_key(headers).or(query_key).unwrap_or_default();
+    let grants = |expected: &str| !expected.is_empty() && keys_match(provided, expected);
+    if grants(&settings.api_key) {
+        Ok(Access::Control)
+    } else if grants(&settings.overlay_key) {
+        Ok(Access::ReadOnly)
+    } else {
+        Err(ApiError(
+            StatusCode::UNAUTHORIZED,
+            "Missing or invalid API key".to_string(),
+        ))
+    }
+}
+
+async fn require_key(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    match check_key(&state.config, request.headers(), None) {
+        Ok(Access::Control) => next.run(request).await,
+        Ok(Access::ReadOnly) => ApiError(
+            StatusCode::FORBIDDEN,
+            "The overlay key only opens the overlay and /ws".to_string(),
+        )
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// The current snapshot, or `204 No Content` when nothing is playing.
+async fn now_playing(State(state): State<ApiState>) -> Response {
+    match state.hub.current() {
+        Some(snapshot) => Json(snapshot).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// The body is optional; without one playback resumes.
+async fn play(State(state): State<ApiState>, body: Bytes) -> ApiResult<StatusCode> {
+    let request = if body.iter().all(u8::is_ascii_whitespace) {
+        PlayRequest::default()
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?
+    };
+    player::play(&state.spotify, request).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn pause(State(state): State<ApiState>) -> ApiResult<StatusCode> {
+    player::pause(&state.spotify).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn next(State(state): State<ApiState>) -> ApiResult<StatusCode> {
+    player::next(&state.spotify).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn previous(State(state): State<ApiState>) -> ApiResult<StatusCode> {
+    player::previous(&state.spotify).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct SeekRequest {
+    position_ms: u64,
+}
+
+async fn seek(
+    State(state): State<ApiState>,
+    Json(request): Json<SeekRequest>,
+) -> ApiResult<StatusCode> {
+    player::seek(&state.spotify, request.position_ms).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct VolumeRequest {
+    volume_percent: u8,
+}
+
+async fn volume(
+    State(state): State<ApiState>,
+    Json(request): Json<VolumeRequest>,
+) -> ApiResult<StatusCode> {
+    player::set_volume(&state.spotify, request.volume_percent).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn queue(State(state): State<ApiState>) -> ApiResult<Response> {
+    Ok(Json(state.spotify.queue().await?).into_response())
+}
+
+#[derive(Deserialize)]
+struct QueueRequest {
+    uri: String,
+}
+
+async fn add_to_queue(
+    State(state): State<ApiState>,
+    Json(request): Json<QueueRequest>,
+) -> ApiResult<StatusCode> {
+    player::add_to_queue(&state.spotify, &request.uri).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct LikeRequest {
+    uri: Option<String>,
+}
+
+/// Likes `uri`, or the current track when the body is empty.
+async fn like(State(state): State<ApiState>, body: Bytes) -> ApiResult<StatusCode> {
+    let request: LikeRequest = if body.iter().all(u8::is_ascii_whitespace) {
+        LikeRequest::default()
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?
+    };
+    let uri = match request.uri {
+        Some(uri) => uri,
+        None => state
+            .hub
+            .current()
+            .map(|snapshot| snapshot.uri)
+            .ok_or_else(|| ApiError(StatusCode::CONFLICT, "Nothing is playing".to_string()))?,
+    };
+    player::like(&state.spotify, &uri).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The current track's lyrics and line, or `204 No Content` when there is
+/// no track.
+async fn current_lyrics(State(state): State<ApiState>) -> Response {
+    match state.lyrics.current() {
+        Some(current) => Json(json!({
+            "lyrics": current,
+            "position": state.lyrics.position(),
+        }))
+        .into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct HistoryQuery {
+    /// Only plays since local midnight; overrides `from`.
+    today: bool,
+    from: Option<u64>,
+    to: Option<u64>,
+    search: Option<String>,
+    artist: Option<String>,
+    limit: Option<u64>,
+}
+
+/// The most recent plays, newest first.
+async fn recent_plays(
+    State(state): State<ApiState>,
+    Query(query): Query<HistoryQuery>,
+) -> ApiResult<Json<HistoryPage>> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .clamp(1, history::MAX_PAGE_SIZE);
+    let db = state.history.clone();
+    let page = tauri::async_runtime::spawn_blocking(move || {
+        let from = if query.today {
+            Some(db.start_of_today()?)
+        } else {
+            query.from
+        };
+        let filter = HistoryFilter {
+            search: query.search,
+            artist: query.artist,
+            from,
+            to: query.to,
+            ..HistoryFilter::default()
+        };
+        let (items, total) = db.query(&filter, 0, limit)?;
+        Ok::<_, String>(HistoryPage {
+            items,
+            total,
+            page: 0,
+            page_size: limit,
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|page| page)
+    .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(page))
+}
+
+/// Routes to nest under `/api/v1`.
+pub fn router(
+    config: SharedConfig,
+    spotify: Arc<SpotifyClient>,
+    hub: Arc<PlaybackHub>,
+    lyrics: Arc<LyricsEngine>,
+    history: Arc<HistoryDb>,
+) -> Router {
+    let state = ApiState {
+        config,
+        spotify,
+        hub,
+        lyrics,
+        history,
+    };
+    Router::new()
+        .route("/now-playing", get(now_playing))
+        .route("/play", post(play))
+        .route("/pause", post(pause))
+        .route("/next", post(next))
+        .route("/previous", post(previous))
+        .route("/seek", post(seek))
+        .route("/volume", post(volume))
+        .route("/queue", get(queue).post(add_to_queue))
+        .route("/like", post(like))
+        .route("/lyrics", get(current_lyrics))
+        .route("/history", get(recent_plays))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_key))
+        .with_state(state)
+}
+
+#[tauri::command]
+pub fn get_api_settings(config: tauri::State<'_, SharedConfig>) -> Result<ApiSettings, String> {
+    let config = config.lock().map_err(|e| e.to_string())?;
+    Ok(config.api.clone())
+}
+
+/// Takes effect immediately, except `bind_address`, which applies on the
+/// next start. An empty key, of either kind, keeps the current one.
+#[tauri::command]
+pub fn set_api_settings(
+    mut settings: ApiSettings,
+    config: tauri::State<'_, SharedConfig>,
+) -> Result<(), String> {
+    settings
+        .bind_address
+        .parse::<IpAddr>()
+        .map_err(|e| format!("Invalid bind address {:?}: {}", settings.bind_address, e))?;
+
+    let mut config = config.lock().map_err(|e| e.to_string())?;
+    if settings.api_key.is_empty() {
+        settings.api_key = config.api.api_key.clone();
+    }
+    if settings.overlay_key.is_empty() {
+        settings.overlay_key = config.api.overlay_key.clone();
+    }
+    config.api = settings;
+    config.save()
+}
+
+/// Replaces the API key, invalidating the old one. Returns the new key.
+#[tauri::command]
+pub fn regenerate_api_key(config: tauri::State<'_, SharedConfig>) -> Result<String, String> {
+    let mut config = config.lock().map_err(|e| e.to_string())?;
+    config.api.api_key = generate_key();
+    config.save()?;
+    println!("Regenerated local API key");
+    Ok(config.api.api_key.clone())
+}
+
+/// Replaces the overlay key, breaking overlay URLs that use the old one.
+/// Returns the new key.
+#[tauri::command]
+pub fn regenerate_overlay_key(config: tauri::State<'_, SharedConfig>) -> Result<String, String> {
+    let mut config = config.lock().map_err(|e| e.to_string())?;
+    config.api.overlay_key = generate_key();
+    config.save()?;
+    println!("Regenerated overlay key");
+    Ok(config.api.overlay_key.clone())
+}
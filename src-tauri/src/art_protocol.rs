@@ -0,0 +1,228 @@
+//! The `albumart://` URI scheme. Webviews refuse most `file://` URLs, so local
+//! cover images are served through this handler instead. It only hands out
+//! images that live under a library root or in the artwork caches.
+//!
+//! A `?size=N` query serves a thumbnail instead of the original image.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+use tauri::{
+    http::{header, Method, Request, Response, StatusCode},
+    UriSchemeResponder,
+};
+
+use crate::{
+    artwork,
+    config::SharedConfig,
+    library, tags,
+    thumbnails::{self, ThumbnailCache},
+};
+
+pub const SCHEME: &str = "albumart";
+
+/// Cached files are named after their contents and never change.
+const CACHE_IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Library files can be replaced in place, so browsers revalidate hourly.
+/// This also covers art served on behalf of a library file, such as the
+/// cover extracted from an audio file's tags.
+const CACHE_LIBRARY: &str = "public, max-age=3600";
+
+/// URL the webview can load for a local image or audio file. Windows and
+/// Android webviews only accept custom schemes as `http://<scheme>.localhost`.
+pub fn url_for(path: &Path) -> String {
+    let encoded = urlencoding::encode(&path.to_string_lossy()).into_owned();
+    if cfg!(any(target_os = "windows", target_os = "android")) {
+        format!("http://{}.localhost/{}", SCHEME, encoded)
+    } else {
+        format!("{}://localhost/{}", SCHEME, encoded)
+    }
+}
+
+fn mime_type(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        _ => return None,
+    })
+}
+
+fn status(code: StatusCode) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(code)
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(Vec::new())
+        .unwrap_or_default()
+}
+
+fn cache_dirs() -> [PathBuf; 2] {
+    [artwork::art_cache_dir(), thumbnails::thumbnail_dir()]
+}
+
+/// Canonical form of `path` if it lies under a library root or one of the
+/// caches. Symlinks and `..` are resolved before the prefix check so neither
+/// can escape.
+pub fn resolve_allowed(config: &SharedConfig, path: &Path) -> Option<PathBuf> {
+    let requested = path.canonicalize().ok()?;
+    let allowed = library::effective_roots(config)
+        .into_iter()
+        .chain(cache_dirs())
+        .filter_map(|dir| dir.canonicalize().ok())
+        .any(|dir| requested.starts_with(dir));
+
+    if allowed {
+        Some(requested)
+    } else {
+        println!(
+            "Refused album art outside library roots: {}",
+            requested.display()
+        );
+        None
+    }
+}
+
+pub fn handle(
+    config: SharedConfig,
+    thumbnails: Arc<ThumbnailCache>,
+    request: Request<Vec<u8>>,
+    responder: UriSchemeResponder,
+) {
+    tauri::async_runtime::spawn_blocking(move || {
+        responder.respond(respond(&config, &thumbnails, &request));
+    });
+}
+
+fn requested_size(request: &Request<Vec<u8>>) -> Option<u32> {
+    request
+        .uri()
+        .query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("size="))
+        .and_then(|size| size.parse().ok())
+}
+
+fn respond(
+    config: &SharedConfig,
+    thumbnails: &ThumbnailCache,
+    request: &Request<Vec<u8>>,
+) -> Response<Vec<u8>> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    let raw = request.uri().path().trim_start_matches('/');
+    let Ok(decoded) = urlencoding::decode(raw) else {
+        return status(StatusCode::BAD_REQUEST);
+    };
+    let requested = Path::new(decoded.as_ref());
+    if !requested.exists() {
+        return status(StatusCode::NOT_FOUND);
+    }
+    let Some(requested) = resolve_allowed(config, requested) else {
+        return status(StatusCode::FORBIDDEN);
+    };
+    // Caching follows the file named in the URL, not the one served: the
+    // cover extracted from an audio file changes whenever the file is
+    // retagged, even though the cache copy itself never does.
+    let Ok(source) = fs::metadata(&requested) else {
+        return status(StatusCode::NOT_FOUND);
+    };
+    let in_cache = cache_dirs()
+        .iter()
+        .filter_map(|dir| dir.canonicalize().ok())
+        .any(|dir| requested.starts_with(dir));
+
+    // Audio files stand for the cover embedded in their tags, which is
+    // extracted into the cache on first request.
+    let path = if tags::is_audio_file(&requested) {
+        match artwork::extract_embedded(&requested) {
+            Some(path) => path,
+            None => return status(StatusCode::NOT_FOUND),
+        }
+    } else {
+        requested
+    };
+
+    let Some(mut mime) = mime_type(&path) else {
+        return status(StatusCode::FORBIDDEN);
+    };
+
+    let mut thumbnail_size = None;
+    let path = match requested_size(request) {
+        Some(size) => match thumbnails.for_file(&path, size) {
+            Ok(thumbnail) => {
+                mime = "image/jpeg";
+                thumbnail_size = Some(thumbnails::standard_size(size));
+                thumbnail
+            }
+            Err(e) => {
+                eprintln!("Failed to make thumbnail of {}: {}", path.display(), e);
+                path
+            }
+        },
+        None => path,
+    };
+    let Ok(metadata) = fs::metadata(&path) else {
+        return status(StatusCode::NOT_FOUND);
+    };
+
+    let modified = source
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let etag = match thumbnail_size {
+        Some(size) => format!("\"{:x}-{:x}-{}\"", source.len(), modified, size),
+        None => format!("\"{:x}-{:x}\"", source.len(), modified),
+    };
+    let cache_control = if in_cache {
+        CACHE_IMMUTABLE
+    } else {
+        CACHE_LIBRARY
+    };
+
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, mime)
+        .header(header::CACHE_CONTROL, cache_control)
+        .header(header::ETAG, &etag)
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*");
+
+    let not_modified = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(',').any(|tag| tag.trim() == etag))
+        .unwrap_or(false);
+    if not_modified {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Vec::new())
+            .unwrap_or_default();
+    }
+
+    let body = if request.method() == Method::HEAD {
+        Vec::new()
+    } else {
+        match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Failed to read album art {}: {}", path.display(), e);
+                return status(StatusCode::NOT_FOUND);
+            }
+        }
+    };
+
+    builder
+        .header(header::CONTENT_LENGTH, metadata.len())
+        .status(StatusCode::OK)
+        .body(body)
+        .unwrap_or_default()
+}
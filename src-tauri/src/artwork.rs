@@ -0,0 +1,41 @@
+//! Disk cache for cover images pulled out of audio file tags. Files are
+//! named after a hash of their contents, so every track of an album that
+//! embeds the same cover shares one cached image.
+
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use crate::{config, tags};
+
+pub fn art_cache_dir() -> PathBuf {
+    config::cache_dir().join("art")
+}
+
+/// Writes the picture into the cache unless an identical one is already
+/// there, and returns its path.
+pub fn store_picture(picture: &tags::Picture) -> Result<PathBuf, String> {
+    let hash = format!("{:x}", Sha256::digest(&picture.data));
+    let path = art_cache_dir().join(format!("{}.{}", hash, picture.extension()));
+
+    if !path.is_file() {
+        config::write_atomic(&path, &picture.data)?;
+    }
+    Ok(path)
+}
+
+/// Re-reads a track's tags and caches its embedded cover, for when the cache
+/// was cleared after the track was indexed.
+pub fn extract_embedded(track: &Path) -> Option<PathBuf> {
+    let picture = tags::read_tags(track).ok()?.picture?;
+    match store_picture(&picture) {
+        Ok(path) => Some(path),
+        Err(e) => {
+            eprintln!(
+                "Failed to cache embedded art from {}: {}",
+                track.display(),
+                e
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! `spotify-widget-ctl`: controls the running widget through its local API,
+//! for window manager bindings, status bars and scripts. The API address
+//! and key are read from the widget's config file.
+//!
+//! Output is human-readable by default; `--json` prints the API's JSON
+//! instead. Errors go to stderr with a non-zero exit code.
+
+use serde_json::{json, Value};
+use std::{
+    fs,
+    path::PathBuf,
+    process::ExitCode,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Must match `APP_IDENTIFIER` in the app's config module.
+const APP_IDENTIFIER: &str = "com.spotify.widget";
+const PORT: u16 = 14700;
+
+const USAGE: &str = "\
+Usage: spotify-widget-ctl [--json] <command>
+
+Commands:
+  status                      Show the current track
+  play                        Resume playback
+  pause                       Pause playback
+  toggle                      Play or pause
+  next                        Skip to the next track
+  previous                    Go back to the previous track
+  seek <seconds>              Jump to a position in the current track
+  volume <0-100|+N|-N>        Set or change the volume
+  like                        Save the current track to Liked Songs
+  lyrics [--all]              Show the current lyric line, or all lyrics
+  history [--today] [--limit N]
+                              Show recent plays, newest first
+
+Options:
+  --json                      Print JSON instead of text";
+
+enum Command {
+    Status,
+    Play,
+    Pause,
+    Toggle,
+    Next,
+    Previous,
+    Seek(u64),
+    Volume(String),
+    Like,
+    Lyrics { all: bool },
+    History { today: bool, limit: Option<u64> },
+}
+
+fn parse_args(args: &[String]) -> Result<(Command, bool), String> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let args: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--json")
+        .collect();
+
+    let command = match args.as_slice() {
+        ["status"] => Command::Status,
+        ["play"] => Command::Play,
+        ["pause"] => Command::Pause,
+        ["toggle"] => Command::Toggle,
+        ["next"] => Command::Next,
+        ["previous"] | ["prev"] => Command::Previous,
+        ["seek", seconds] => {
+            let seconds: f64 = seconds
+                .parse()
+                .ok()
+                .filter(|s: &f64| *s >= 0.0)
+                .ok_or_else(|| format!("Invalid position: {}", seconds))?;
+            Command::Seek((seconds * 1000.0) as u64)
+        }
+        ["volume", volume] => Command::Volume(volume.to_string()),
+        ["like"] => Command::Like,
+        ["lyrics"] => Command::Lyrics { all: false },
+        ["lyrics", "--all"] => Command::Lyrics { all: true },
+        ["history", options @ ..] => {
+            let mut today = false;
+            let mut limit = None;
+            let mut options = options.iter();
+            while let Some(option) = options.next() {
+                match *option {
+                    "--today" => today = true,
+                    "--limit" => {
+                        let value = options.next().ok_or("--limit needs a number")?;
+                        limit = Some(
+                            value
+                                .parse()
+                                .map_err(|_| format!("Invalid limit: {}", value))?,
+                        );
+                    }
+                    other => return Err(format!("Unknown history option: {}", other)),
+                }
+            }
+            Command::History { today, limit }
+        }
+        [] => return Err("No command given".to_string()),
+        [command, ..] => return Err(format!("Unknown command or arguments: {}", command)),
+    };
+    Ok((command, json))
+}
+
+struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+}
+
+impl Client {
+    /// Reads the API settings the app writes to its config file.
+    fn from_config() -> Result<Client, String> {
+        let path = dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(APP_IDENTIFIER)
+            .join("config.json");
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "Could not read {}: {}. Has the widget been started?",
+                path.display(),
+                e
+            )
+        })?;
+        let config: Value = serde_json::from_str(&contents)
+            .map_err(|e| format!("Could not parse {}: {}", path.display(), e))?;
+        let api = &config["api"];
+
+        if api["enabled"] == Value::Bool(false) {
+            return Err("The local API is disabled in the widget's settings".to_string());
+        }
+        let api_key = api["api_key"].as_str().unwrap_or_default().to_string();
+        if api_key.is_empty() {
+            return Err("No API key in the config file. Has the widget been started?".to_string());
+        }
+
+        Ok(Client {
+            http: reqwest::Client::new(),
+            // The widget always listens on loopback, whatever the bind address.
+            base_url: format!("http://127.0.0.1:{}/api/v1", PORT),
+            api_key,
+        })
+    }
+
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<Value>,
+    ) -> Result<Option<Value>, String> {
+        let mut request = self
+            .http
+            .request(method, format!("{}{}", self.base_url, path))
+            .bearer_auth(&self.api_key)
+            .query(query);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await.map_err(|e| {
+            if e.is_connect() {
+                format!("The widget is not running ({})", self.base_url)
+            } else {
+                e.to_string()
+            }
+        })?;
+
+        let status = response.status();
+        let text = response.text().await.map_err(|e| e.to_string())?;
+        if !status.is_success() {
+            let message = serde_json::from_str::<Value>(&text)
+                .ok()
+                .and_then(|body| body["error"].as_str().map(str::to_string))
+                .unwrap_or(text);
+            return Err(format!("{} ({})", message, status));
+        }
+        if text.is_empty() {
+            return Ok(None);
+        }
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> Result<Option<Value>, String> {
+        self.request(reqwest::Method::GET, path, query, None).await
+    }
+
+    async fn post(&self, path: &str, body: Option<Value>) -> Result<(), String> {
+        self.request(reqwest::Method::POST, path, &[], body)
+            .await
+            .map(|_| ())
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn format_duration(ms: u64) -> String {
+    let seconds = ms / 1000;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn format_ago(ms: u64) -> String {
+    let minutes = now_ms().saturating_sub(ms) / 60_000;
+    match minutes {
+        0 => "just now".to_string(),
+        1..=59 => format!("{}m ago", minutes),
+        60..=1439 => format!("{}h ago", minutes / 60),
+        _ => format!("{}d ago", minutes / 1440),
+    }
+}
+
+fn artists(value: &Value) -> String {
+    value["artists"]
+        .as_array()
+        .map(|artists| {
+            artists
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .unwrap_or_default()
+}
+
+/// One line, for status bars. The snapshot is up to a poll old, so the
+/// position is extrapolated while playing.
+fn print_status(snapshot: &Value) {
+    let playing = snapshot["is_playing"].as_bool().unwrap_or(false);
+    let duration = snapshot["duration_ms"].as_u64().unwrap_or(0);
+    let mut progress = snapshot["progress_ms"].as_u64().unwrap_or(0);
+    if playing {
+        let observed_at = snapshot["observed_at"].as_u64().unwrap_or(0);
+        progress = (progress + now_ms().saturating_sub(observed_at)).min(duration);
+    }
+
+    println!(
+        "{} {} - {}  {} / {}",
+        if playing { "▶" } else { "⏸" },
+        snapshot["title"].as_str().unwrap_or_default(),
+        artists(snapshot),
+        format_duration(progress),
+        format_duration(duration)
+    );
+}
+
+fn print_lyrics(body: &Value, all: bool) {
+    let lyrics = &body["lyrics"]["lyrics"];
+    let Some(lines) = lyrics["lines"].as_array() else {
+        println!("No lyrics");
+        return;
+    };
+    let text = |line: &Value| line["text"].as_str().unwrap_or_default().to_string();
+
+    // Unsynced lyrics have no current line.
+    if all || lyrics["synced"] != Value::Bool(true) {
+        for line in lines {
+            println!("{}", text(line));
+        }
+        return;
+    }
+    let current = body["position"]["line_index"]
+        .as_u64()
+        .and_then(|index| lines.get(index as usize));
+    println!("{}", current.map(text).unwrap_or_default());
+}
+
+fn print_history(page: &Value) {
+    let items = page["items"].as_array().cloned().unwrap_or_default();
+    if items.is_empty() {
+        println!("No plays");
+        return;
+    }
+    for play in &items {
+        println!(
+            "{:>9}  {} - {}",
+            format_ago(play["started_at"].as_u64().unwrap_or(0)),
+            play["title"].as_str().unwrap_or_default(),
+            artists(play)
+        );
+    }
+    let total = page["total"].as_u64().unwrap_or(0);
+    if total > items.len() as u64 {
+        println!("({} of {} plays)", items.len(), total);
+    }
+}
+
+/// Applies `+N`/`-N` to the current volume; plain numbers are absolute.
+async fn target_volume(client: &Client, volume: &str) -> Result<u8, String> {
+    let invalid = || format!("Invalid volume: {}", volume);
+    let delta = match volume.as_bytes().first() {
+        Some(b'+') | Some(b'-') => volume.parse::<i64>().map_err(|_| invalid())?,
+        _ => {
+            return volume
+                .parse::<u8>()
+                .ok()
+                .filter(|v| *v <= 100)
+                .ok_or_else(invalid)
+        }
+    };
+    let current = client
+        .get("/now-playing", &[])
+        .await?
+        .and_then(|snapshot| snapshot["volume_percent"].as_i64())
+        .ok_or("The current device does not report its volume")?;
+    Ok((current + delta).clamp(0, 100) as u8)
+}
+
+async fn run(command: Command, json: bool) -> Result<(), String> {
+    let client = Client::from_config()?;
+    let action = match command {
+        Command::Status => {
+            let snapshot = client.get("/now-playing", &[]).await?;
+            match (snapshot, json) {
+                (snapshot, true) => println!("{}", snapshot.unwrap_or(Value::Null)),
+                (Some(snapshot), false) => print_status(&snapshot),
+                (None, false) => println!("Nothing playing"),
+            }
+            return Ok(());
+        }
+        Command::Lyrics { all } => {
+            let body = client.get("/lyrics", &[]).await?;
+            match (body, json) {
+                (body, true) => println!("{}", body.unwrap_or(Value::Null)),
+                (Some(body), false) => print_lyrics(&body, all),
+                (None, false) => println!("Nothing playing"),
+            }
+            return Ok(());
+        }
+        Command::History { today, limit } => {
+            let mut query = vec![("today", today.to_string())];
+            if let Some(limit) = limit {
+                query.push(("limit", limit.to_string()));
+            }
+            let page = client.get("/history", &query).await?.unwrap_or(Value::Null);
+            if json {
+                println!("{}", page);
+            } else {
+                print_history(&page);
+            }
+            return Ok(());
+        }
+        Command::Play => client.post("/play", None).await,
+        Command::Pause => client.post("/pause", None).await,
+        Command::Toggle => {
+            let playing = client
+                .get("/now-playing", &[])
+                .await?
+                .is_some_and(|snapshot| snapshot["is_playing"] == Value::Bool(true));
+            let path = if playing { "/pause" } else { "/play" };
+            client.post(path, None).await
+        }
+        Command::Next => client.post("/next", None).await,
+        Command::Previous => client.post("/previous", None).await,
+        Command::Seek(position_ms) => {
+            let body = json!({ "position_ms": position_ms });
+            client.post("/seek", Some(body)).await
+        }
+        Command::Volume(volume) => {
+            let volume_percent = target_volume(&client, &volume).await?;
+            let body = json!({ "volume_percent": volume_percent });
+            client.post("/volume", Some(body)).await
+        }
+        Command::Like => client.post("/like", None).await,
+    };
+
+    // Actions are silent on success, apart from an acknowledgement for
+    // scripts reading JSON.
+    action?;
+    if json {
+        println!("{}", json!({ "ok": true }));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let (command, json) = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("spotify-widget-ctl: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run(command, json).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("spotify-widget-ctl: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
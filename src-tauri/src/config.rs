@@ -0,0 +1,284 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Matches the `identifier` in tauri.conf.json so our files live next to the
+/// ones Tauri itself creates.
+pub const APP_IDENTIFIER: &str = "com.spotify.widget";
+
+const CONFIG_FILE: &str = "config.json";
+
+/// Distinguishes the temporary files of concurrent `write_atomic` calls.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_IDENTIFIER)
+}
+
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_IDENTIFIER)
+}
+
+/// For files that can be rebuilt at any time, like extracted artwork.
+pub fn cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_IDENTIFIER)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Where lyrics are looked for, in order. See `lyrics::providers` for the
+/// known provider names.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LyricsSettings {
+    pub providers: Vec<String>,
+    pub lrclib_url: String,
+}
+
+impl Default for LyricsSettings {
+    fn default() -> Self {
+        LyricsSettings {
+            providers: vec!["local".to_string(), "lrclib".to_string()],
+            lrclib_url: "https://lrclib.net".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LastfmSettings {
+    pub enabled: bool,
+    pub api_key: String,
+    pub api_secret: String,
+    /// Obtained through the desktop auth flow; empty until then.
+    pub session_key: String,
+    pub username: Option<String>,
+    pub base_url: String,
+    pub auth_url: String,
+}
+
+impl Default for LastfmSettings {
+    fn default() -> Self {
+        LastfmSettings {
+            enabled: false,
+            api_key: String::new(),
+            api_secret: String::new(),
+            session_key: String::new(),
+            username: None,
+            base_url: "https://ws.audioscrobbler.com/2.0/".to_string(),
+            auth_url: "https://www.last.fm/api/auth/".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ListenbrainzSettings {
+    pub enabled: bool,
+    pub token: String,
+    pub base_url: String,
+}
+
+impl Default for ListenbrainzSettings {
+    fn default() -> Self {
+        ListenbrainzSettings {
+            enabled: false,
+            token: String::new(),
+            base_url: "https://api.listenbrainz.org".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScrobbleSettings {
+    pub lastfm: LastfmSettings,
+    pub listenbrainz: ListenbrainzSettings,
+}
+
+/// The local control API served under `/api/v1`. The key is generated on
+/// first start. The server always listens on loopback; a `bind_address` other
+/// than loopback also exposes the API, WebSocket and overlay to the network,
+/// but never the OAuth routes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiSettings {
+    pub enabled: bool,
+    pub api_key: String,
+    /// Read-only key for the overlay and the event stream, so the API key
+    /// never has to go into a browser source URL.
+    pub overlay_key: String,
+    pub bind_address: String,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        ApiSettings {
+            enabled: true,
+            api_key: String::new(),
+            overlay_key: String::new(),
+            bind_address: "127.0.0.1".to_string(),
+        }
+    }
+}
+
+/// A text file rewritten from `template` on every track change; see
+/// `now_playing` for the placeholders.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NowPlayingFile {
+    pub path: PathBuf,
+    pub template: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NowPlayingFileSettings {
+    pub enabled: bool,
+    pub files: Vec<NowPlayingFile>,
+    /// Where to copy the cover image, as JPEG.
+    pub cover_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Webhook {
+    /// Generated when the webhook is saved.
+    pub id: String,
+    pub enabled: bool,
+    pub url: String,
+    /// Event names to send; empty sends all of them.
+    pub events: Vec<String>,
+    /// Signs each request with HMAC-SHA256 when set.
+    pub secret: String,
+    /// JSON payload template; the default payload when absent.
+    pub template: Option<String>,
+}
+
+impl Default for Webhook {
+    fn default() -> Self {
+        Webhook {
+            id: String::new(),
+            enabled: true,
+            url: String::new(),
+            events: Vec::new(),
+            secret: String::new(),
+            template: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookSettings {
+    pub enabled: bool,
+    pub hooks: Vec<Webhook>,
+}
+
+/// Backend settings persisted as JSON in the config directory.
+///
+/// Every field has a default so config files written by older versions keep
+/// loading after new settings are added.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub window_geometry: HashMap<String, WindowGeometry>,
+    pub library_roots: Vec<PathBuf>,
+    pub lyrics: LyricsSettings,
+    pub scrobbling: ScrobbleSettings,
+    pub api: ApiSettings,
+    pub now_playing_files: NowPlayingFileSettings,
+    pub webhooks: WebhookSettings,
+}
+
+pub type SharedConfig = Arc<Mutex<Config>>;
+
+impl Config {
+    pub fn path() -> PathBuf {
+        config_dir().join(CONFIG_FILE)
+    }
+
+    pub fn load() -> Config {
+        let path = Self::path();
+        match fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Failed to parse {}: {}", path.display(), e);
+                    Config::default()
+                }
+            },
+            Err(_) => Config::default(),
+        }
+    }
+
+    /// The file holds the API keys and scrobbler secrets, so only the
+    /// user can read it.
+    pub fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        write_private(&Self::path(), contents.as_bytes())
+    }
+}
+
+/// Writes through a temporary sibling file and renames it into place so a
+/// crash mid-write never leaves a truncated file behind. Each call uses its
+/// own temporary file, so concurrent writers cannot rename each other's.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), String> {
+    write_through_tmp(path, contents, false)
+}
+
+/// [`write_atomic`] for files holding secrets: readable by the owner only
+/// on Unix.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    write_through_tmp(path, contents, true)
+}
+
+fn write_through_tmp(path: &Path, contents: &[u8], private: bool) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    if private {
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    }
+
+    let written = options
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(contents))
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,393 @@
+//! Listening history, recorded by the backend from playback state and kept
+//! in an SQLite database in the data directory.
+
+mod db;
+mod export;
+mod import;
+mod recorder;
+mod report;
+mod stats;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use tauri::{AppHandle, Emitter};
+use tauri_plugin_dialog::DialogExt;
+
+use crate::{
+    config::{self, SharedConfig},
+    thumbnails::{self, ThumbnailCache},
+};
+
+pub use db::HistoryDb;
+pub use export::{ExportFormat, ExportSummary};
+pub use import::ImportProgress;
+pub use recorder::spawn_recorder;
+pub use report::ReportSummary;
+pub use stats::{
+    ArtistSkipRate, DailyListening, Discovery, HeatmapCell, Streaks, TopAlbum, TopArtist, TopTrack,
+};
+
+const DB_FILE: &str = "history.sqlite3";
+
+/// `source` of plays recorded while the app was running.
+pub const SOURCE_LIVE: &str = "live";
+
+/// Plays shorter than this are left out; they come from flicking through
+/// tracks rather than listening.
+pub(crate) const MIN_PLAY_MS: u64 = 2_000;
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+pub(crate) const MAX_PAGE_SIZE: u64 = 500;
+
+const DEFAULT_TOP_LIMIT: u64 = 10;
+const MAX_TOP_LIMIT: u64 = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Play {
+    pub id: i64,
+    pub track_id: Option<String>,
+    pub uri: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub image_url: Option<String>,
+    pub duration_ms: u64,
+    pub is_local: bool,
+    pub item_type: String,
+    pub context_uri: Option<String>,
+    pub context_type: Option<String>,
+    pub device_id: Option<String>,
+    pub device_name: Option<String>,
+    /// Unix milliseconds.
+    pub started_at: u64,
+    pub ms_played: u64,
+    /// Moved on to another track before the end.
+    pub skipped: bool,
+    /// Played to (nearly) the end.
+    pub completed: bool,
+    pub source: String,
+}
+
+/// Every field narrows the result; an empty filter matches all plays.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct HistoryFilter {
+    /// Substring of the title, album or artists, case-insensitive.
+    pub search: Option<String>,
+    pub artist: Option<String>,
+    pub track_uri: Option<String>,
+    pub context_uri: Option<String>,
+    pub device_id: Option<String>,
+    /// Unix milliseconds, inclusive.
+    pub from: Option<u64>,
+    /// Unix milliseconds, exclusive.
+    pub to: Option<u64>,
+    pub skipped: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct PageRequest {
+    /// Zero-based.
+    pub page: u64,
+    /// Defaults to 50, at most 500.
+    pub page_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryPage {
+    pub items: Vec<Play>,
+    pub total: u64,
+    pub page: u64,
+    pub page_size: u64,
+}
+
+/// Opens the history database, falling back to an in-memory one so the app
+/// still works when the data directory is unwritable.
+pub fn open() -> Arc<HistoryDb> {
+    let path = config::data_dir().join(DB_FILE);
+    let db = HistoryDb::open(&path).unwrap_or_else(|e| {
+        eprintln!("Failed to open history database {}: {}", path.display(), e);
+        HistoryDb::open_in_memory().expect("Failed to open in-memory history database")
+    });
+    Arc::new(db)
+}
+
+/// Runs a database call off the async runtime.
+async fn with_db<T: Send + 'static>(
+    history: tauri::State<'_, Arc<HistoryDb>>,
+    f: impl FnOnce(&HistoryDb) -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    let db = history.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || f(&db))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Asks the user where to save a file; `None` means they cancelled.
+async fn choose_save_path(
+    app_handle: &AppHandle,
+    title: &str,
+    extension: &str,
+    file_name: String,
+) -> Result<Option<PathBuf>, String> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    app_handle
+        .dialog()
+        .file()
+        .set_title(title)
+        .add_filter(extension, &[extension])
+        .set_file_name(file_name)
+        .save_file(move |path| {
+            let _ = tx.send(path);
+        });
+
+    match rx.await.map_err(|e| e.to_string())? {
+        Some(path) => path.into_path().map(Some).map_err(|e| e.to_string()),
+        None => Ok(None),
+    }
+}
+
+fn top_limit(limit: Option<u64>) -> u64 {
+    limit.unwrap_or(DEFAULT_TOP_LIMIT).clamp(1, MAX_TOP_LIMIT)
+}
+
+#[tauri::command]
+pub async fn get_history(
+    filter: Option<HistoryFilter>,
+    page: Option<PageRequest>,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<HistoryPage, String> {
+    let filter = filter.unwrap_or_default();
+    let page = page.unwrap_or_default();
+    let page_size = page
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let (items, total) = with_db(history, move |db| {
+        db.query(&filter, page.page * page_size, page_size)
+    })
+    .await?;
+
+    Ok(HistoryPage {
+        items,
+        total,
+        page: page.page,
+        page_size,
+    })
+}
+
+#[tauri::command]
+pub async fn clear_history(history: tauri::State<'_, Arc<HistoryDb>>) -> Result<usize, String> {
+    let removed = with_db(history, |db| db.clear()).await?;
+    println!("Cleared {} plays from history", removed);
+    Ok(removed)
+}
+
+/// Merges a Spotify privacy export into the history. `paths` may be the
+/// JSON files themselves or the folders they were unzipped into.
+#[tauri::command]
+pub async fn import_spotify_history(
+    paths: Vec<String>,
+    app_handle: AppHandle,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<ImportProgress, String> {
+    let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+    let import_handle = app_handle.clone();
+    let summary = with_db(history, move |db| {
+        import::import(&import_handle, db, &paths)
+    })
+    .await?;
+
+    if let Err(e) = app_handle.emit("history-import-complete", &summary) {
+        eprintln!("Failed to emit history-import-complete: {}", e);
+    }
+    Ok(summary)
+}
+
+/// Takes over the history the webview used to keep in `localStorage`; see
+/// [`import::import_legacy`]. Returns the number of plays added.
+#[tauri::command]
+pub async fn import_local_history(
+    entries: Vec<serde_json::Value>,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<usize, String> {
+    with_db(history, move |db| import::import_legacy(db, entries)).await
+}
+
+/// Writes matching plays to `path` as CSV, JSON Lines or a ListenBrainz
+/// import document. Without a path the user picks one; `None` means they
+/// cancelled. `fields` selects CSV/JSON Lines columns, defaulting to all.
+#[tauri::command]
+pub async fn export_history(
+    format: ExportFormat,
+    path: Option<String>,
+    filter: Option<HistoryFilter>,
+    fields: Option<Vec<String>>,
+    app_handle: AppHandle,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<Option<ExportSummary>, String> {
+    let fields = export::resolve_fields(fields)?;
+    let filter = filter.unwrap_or_default();
+
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let file_name = format!("listening-history.{}", format.extension());
+            match choose_save_path(
+                &app_handle,
+                "Export listening history",
+                format.extension(),
+                file_name,
+            )
+            .await?
+            {
+                Some(path) => path,
+                None => return Ok(None),
+            }
+        }
+    };
+
+    with_db(history, move |db| {
+        export::export(db, &filter, format, &fields, &path)
+    })
+    .await
+    .map(Some)
+}
+
+#[tauri::command]
+pub async fn get_top_tracks(
+    filter: Option<HistoryFilter>,
+    limit: Option<u64>,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<Vec<TopTrack>, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(history, move |db| db.top_tracks(&filter, top_limit(limit))).await
+}
+
+#[tauri::command]
+pub async fn get_top_artists(
+    filter: Option<HistoryFilter>,
+    limit: Option<u64>,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<Vec<TopArtist>, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(history, move |db| db.top_artists(&filter, top_limit(limit))).await
+}
+
+#[tauri::command]
+pub async fn get_top_albums(
+    filter: Option<HistoryFilter>,
+    limit: Option<u64>,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<Vec<TopAlbum>, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(history, move |db| db.top_albums(&filter, top_limit(limit))).await
+}
+
+/// Plays and listening time per local calendar day.
+#[tauri::command]
+pub async fn get_listening_by_day(
+    filter: Option<HistoryFilter>,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<Vec<DailyListening>, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(history, move |db| db.listening_by_day(&filter)).await
+}
+
+/// Plays and listening time per weekday and hour; empty cells are omitted.
+#[tauri::command]
+pub async fn get_listening_heatmap(
+    filter: Option<HistoryFilter>,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<Vec<HeatmapCell>, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(history, move |db| db.heatmap(&filter)).await
+}
+
+#[tauri::command]
+pub async fn get_listening_streaks(
+    filter: Option<HistoryFilter>,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<Streaks, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(history, move |db| db.streaks(&filter)).await
+}
+
+/// Artists by share of plays skipped, ignoring artists with fewer than
+/// `min_plays` plays (default 5).
+#[tauri::command]
+pub async fn get_skip_rates(
+    filter: Option<HistoryFilter>,
+    min_plays: Option<u64>,
+    limit: Option<u64>,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<Vec<ArtistSkipRate>, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(history, move |db| {
+        db.skip_rates(&filter, min_plays.unwrap_or(5), top_limit(limit))
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn get_discovery_stats(
+    filter: Option<HistoryFilter>,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<Discovery, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(history, move |db| db.discovery(&filter)).await
+}
+
+/// Writes a self-contained HTML listening report for the plays matching
+/// `filter`, usually just a `from`/`to` period. Without a path the user
+/// picks one; `None` means they cancelled.
+#[tauri::command]
+pub async fn generate_listening_report(
+    filter: Option<HistoryFilter>,
+    title: Option<String>,
+    path: Option<String>,
+    app_handle: AppHandle,
+    config: tauri::State<'_, SharedConfig>,
+    thumbnails: tauri::State<'_, Arc<ThumbnailCache>>,
+    history: tauri::State<'_, Arc<HistoryDb>>,
+) -> Result<Option<ReportSummary>, String> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let file_name = "listening-report.html".to_string();
+            match choose_save_path(&app_handle, "Save listening report", "html", file_name).await? {
+                Some(path) => path,
+                None => return Ok(None),
+            }
+        }
+    };
+
+    let filter = filter.unwrap_or_default();
+    let data = with_db(history, move |db| report::gather(db, &filter)).await?;
+
+    // Covers come from the thumbnail cache; missing ones get a placeholder.
+    let mut covers = HashMap::new();
+    for url in data.image_urls() {
+        let thumbnail =
+            thumbnails::thumbnail_for_source(thumbnails.inner().clone(), config.inner(), &url, 64)
+                .await;
+        if let Ok(bytes) = thumbnail.and_then(|p| std::fs::read(p).map_err(|e| e.to_string())) {
+            let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+            covers.insert(url, format!("data:image/jpeg;base64,{}", encoded));
+        }
+    }
+
+    let title = title.unwrap_or_else(|| "Listening report".to_string());
+    let html = report::render(&title, &data, &covers);
+    config::write_atomic(&path, html.as_bytes())?;
+    println!("Wrote listening report to {}", path.display());
+
+    Ok(Some(ReportSummary {
+        path: path.to_string_lossy().to_string(),
+        plays: data.discovery.plays,
+        ms_played: data.ms_played(),
+    }))
+}
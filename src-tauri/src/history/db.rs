@@ -0,0 +1,393 @@
+//! SQLite storage for plays. Schema changes are applied in order as
+//! migrations, tracked with `PRAGMA user_version`.
+
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension, Row};
+use std::{
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use super::{HistoryFilter, Play};
+
+pub(super) const MIGRATIONS: &[&str] = &[
+    // 1: plays, with artists stored as a JSON array.
+    "CREATE TABLE plays (
+        id INTEGER PRIMARY KEY,
+        track_id TEXT,
+        uri TEXT NOT NULL,
+        title TEXT NOT NULL,
+        artists TEXT NOT NULL DEFAULT '[]',
+        album TEXT,
+        image_url TEXT,
+        duration_ms INTEGER NOT NULL DEFAULT 0,
+        is_local INTEGER NOT NULL DEFAULT 0,
+        item_type TEXT NOT NULL DEFAULT 'track',
+        context_uri TEXT,
+        context_type TEXT,
+        device_id TEXT,
+        device_name TEXT,
+        started_at INTEGER NOT NULL,
+        ms_played INTEGER NOT NULL DEFAULT 0,
+        skipped INTEGER NOT NULL DEFAULT 0,
+        completed INTEGER NOT NULL DEFAULT 0,
+        source TEXT NOT NULL DEFAULT 'live'
+    );
+    CREATE INDEX plays_started_at ON plays (started_at);
+    CREATE INDEX plays_uri ON plays (uri);",
+    // 2: lets "first play of this track" lookups use the index alone.
+    "DROP INDEX plays_uri;
+    CREATE INDEX plays_uri_started_at ON plays (uri, started_at);",
+    // 3: one row per artist of a play, so artist stats can use an index
+    // instead of unpacking the JSON of every play.
+    "CREATE TABLE play_artists (
+        play_id INTEGER NOT NULL REFERENCES plays (id) ON DELETE CASCADE,
+        artist TEXT NOT NULL COLLATE NOCASE,
+        PRIMARY KEY (play_id, artist)
+    );
+    CREATE INDEX play_artists_artist ON play_artists (artist, play_id);
+    INSERT OR IGNORE INTO play_artists (play_id, artist)
+        SELECT plays.id, json_each.value FROM plays, json_each(plays.artists)
+        WHERE json_each.type = 'text';",
+];
+
+pub(super) const COLUMNS: &str =
+    "id, track_id, uri, title, artists, album, image_url, duration_ms, \
+    is_local, item_type, context_uri, context_type, device_id, device_name, started_at, \
+    ms_played, skipped, completed, source";
+
+pub struct HistoryDb {
+    conn: Mutex<Connection>,
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+pub(super) fn play_from_row(row: &Row) -> rusqlite::Result<Play> {
+    let artists: String = row.get("artists")?;
+    Ok(Play {
+        id: row.get("id")?,
+        track_id: row.get("track_id")?,
+        uri: row.get("uri")?,
+        title: row.get("title")?,
+        artists: serde_json::from_str(&artists).unwrap_or_default(),
+        album: row.get("album")?,
+        image_url: row.get("image_url")?,
+        duration_ms: row.get("duration_ms")?,
+        is_local: row.get("is_local")?,
+        item_type: row.get("item_type")?,
+        context_uri: row.get("context_uri")?,
+        context_type: row.get("context_type")?,
+        device_id: row.get("device_id")?,
+        device_name: row.get("device_name")?,
+        started_at: row.get("started_at")?,
+        ms_played: row.get("ms_played")?,
+        skipped: row.get("skipped")?,
+        completed: row.get("completed")?,
+        source: row.get("source")?,
+    })
+}
+
+/// `WHERE` clause and parameters for a filter. Columns are unqualified so
+/// the clause works on `plays` in any query.
+pub(crate) fn filter_clause(filter: &HistoryFilter) -> (String, Vec<Value>) {
+    let mut conditions: Vec<&str> = Vec::new();
+    let mut values: Vec<Value> = Vec::new();
+
+    if let Some(search) = filter
+        .search
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+    {
+        conditions.push(
+            "(title LIKE ? ESCAPE '\\' OR album LIKE ? ESCAPE '\\' OR artists LIKE ? ESCAPE '\\')",
+        );
+        let escaped = search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let pattern = Value::Text(format!("%{}%", escaped));
+        values.extend([pattern.clone(), pattern.clone(), pattern]);
+    }
+    if let Some(artist) = &filter.artist {
+        conditions.push(
+            "EXISTS (SELECT 1 FROM play_artists AS pa WHERE pa.play_id = plays.id AND pa.artist = ?)",
+        );
+        values.push(Value::Text(artist.clone()));
+    }
+    if let Some(uri) = &filter.track_uri {
+        conditions.push("uri = ?");
+        values.push(Value::Text(uri.clone()));
+    }
+    if let Some(uri) = &filter.context_uri {
+        conditions.push("context_uri = ?");
+        values.push(Value::Text(uri.clone()));
+    }
+    if let Some(device) = &filter.device_id {
+        conditions.push("device_id = ?");
+        values.push(Value::Text(device.clone()));
+    }
+    if let Some(from) = filter.from {
+        conditions.push("started_at >= ?");
+        values.push(Value::Integer(from as i64));
+    }
+    if let Some(to) = filter.to {
+        conditions.push("started_at < ?");
+        values.push(Value::Integer(to as i64));
+    }
+    if let Some(skipped) = filter.skipped {
+        conditions.push("skipped = ?");
+        values.push(Value::Integer(skipped as i64));
+    }
+
+    if conditions.is_empty() {
+        return ("1 = 1".to_string(), values);
+    }
+    (conditions.join(" AND "), values)
+}
+
+pub(crate) fn insert_play(conn: &Connection, play: &Play) -> Result<i64, String> {
+    conn.execute(
+        "INSERT INTO plays (track_id, uri, title, artists, album, image_url, duration_ms,
+            is_local, item_type, context_uri, context_type, device_id, device_name,
+            started_at, ms_played, skipped, completed, source)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+        params![
+            play.track_id,
+            play.uri,
+            play.title,
+            serde_json::to_string(&play.artists).map_err(|e| e.to_string())?,
+            play.album,
+            play.image_url,
+            play.duration_ms,
+            play.is_local,
+            play.item_type,
+            play.context_uri,
+            play.context_type,
+            play.device_id,
+            play.device_name,
+            play.started_at,
+            play.ms_played,
+            play.skipped,
+            play.completed,
+            play.source,
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    let id = conn.last_insert_rowid();
+
+    let mut statement = conn
+        .prepare_cached("INSERT OR IGNORE INTO play_artists (play_id, artist) VALUES (?1, ?2)")
+        .map_err(|e| e.to_string())?;
+    for artist in &play.artists {
+        statement
+            .execute(params![id, artist])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(id)
+}
+
+/// Plays read per lock of the connection by [`HistoryDb::for_each`].
+const EXPORT_PAGE_SIZE: usize = 500;
+
+impl HistoryDb {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let conn = Connection::open(path).map_err(|e| e.to_string())?;
+        Self::init(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self, String> {
+        Self::init(Connection::open_in_memory().map_err(|e| e.to_string())?)
+    }
+
+    pub(super) fn init(mut conn: Connection) -> Result<Self, String> {
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(|e| e.to_string())?;
+        // Deleting a play takes its `play_artists` rows with it.
+        conn.pragma_update(None, "foreign_keys", true)
+            .map_err(|e| e.to_string())?;
+        migrate(&mut conn).map_err(|e| e.to_string())?;
+        Ok(HistoryDb {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub(crate) fn conn(&self) -> MutexGuard<'_, Connection> {
+        match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Inserts the play and returns its id; `play.id` is ignored.
+    pub fn insert(&self, play: &Play) -> Result<i64, String> {
+        insert_play(&self.conn(), play)
+    }
+
+    pub fn update_progress(
+        &self,
+        id: i64,
+        ms_played: u64,
+        skipped: bool,
+        completed: bool,
+    ) -> Result<(), String> {
+        self.conn()
+            .execute(
+                "UPDATE plays SET ms_played = ?2, skipped = ?3, completed = ?4 WHERE id = ?1",
+                params![id, ms_played, skipped, completed],
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn delete(&self, id: i64) -> Result<(), String> {
+        self.conn()
+            .execute("DELETE FROM plays WHERE id = ?1", params![id])
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Deletes plays from `source` shorter than `min_ms`, returning how many.
+    pub fn delete_shorter_than(&self, source: &str, min_ms: u64) -> Result<usize, String> {
+        self.conn()
+            .execute(
+                "DELETE FROM plays WHERE source = ?1 AND ms_played < ?2",
+                params![source, min_ms],
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    /// The most recently started play from `source`.
+    pub fn latest(&self, source: &str) -> Result<Option<Play>, String> {
+        self.conn()
+            .query_row(
+                &format!(
+                    "SELECT {} FROM plays WHERE source = ?1
+                     ORDER BY started_at DESC, id DESC LIMIT 1",
+                    COLUMNS
+                ),
+                params![source],
+                play_from_row,
+            )
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn clear(&self) -> Result<usize, String> {
+        self.conn()
+            .execute("DELETE FROM plays", [])
+            .map_err(|e| e.to_string())
+    }
+
+    /// Local midnight as Unix milliseconds, in the same time zone the daily
+    /// stats use.
+    pub fn start_of_today(&self) -> Result<u64, String> {
+        self.conn()
+            .query_row(
+                "SELECT CAST(strftime('%s', 'now', 'localtime', 'start of day', 'utc') AS INTEGER)",
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .map(|seconds| seconds as u64 * 1000)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Matching plays, newest first, and the total number of matches.
+    pub fn query(
+        &self,
+        filter: &HistoryFilter,
+        offset: u64,
+        limit: u64,
+    ) -> Result<(Vec<Play>, u64), String> {
+        let (clause, values) = filter_clause(filter);
+        let conn = self.conn();
+
+        let total: u64 = conn
+            .query_row(
+                &format!("SELECT COUNT(*) FROM plays WHERE {}", clause),
+                params_from_iter(values.iter()),
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+
+        let mut values = values;
+        values.push(Value::Integer(limit as i64));
+        values.push(Value::Integer(offset as i64));
+
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT {} FROM plays WHERE {} ORDER BY started_at DESC, id DESC LIMIT ? OFFSET ?",
+                COLUMNS, clause
+            ))
+            .map_err(|e| e.to_string())?;
+        let plays = statement
+            .query_map(params_from_iter(values.iter()), play_from_row)
+            .map_err(|e| e.to_string())?
+            .collect::<rusqlite::Result<Vec<Play>>>()
+            .map_err(|e| e.to_string())?;
+
+        Ok((plays, total))
+    }
+
+    /// Calls `f` for every matching play, oldest first, without loading
+    /// them all into memory. Plays are read a page at a time and the
+    /// connection is released between pages, so a long export does not
+    /// stall the recorder; `f` itself runs without the lock.
+    pub fn for_each(
+        &self,
+        filter: &HistoryFilter,
+        mut f: impl FnMut(Play) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let (clause, values) = filter_clause(filter);
+        let sql = format!(
+            "SELECT {} FROM plays
+             WHERE {} AND (started_at > ? OR (started_at = ? AND id > ?))
+             ORDER BY started_at, id LIMIT ?",
+            COLUMNS, clause
+        );
+
+        // Keyset pagination: each page starts after the last play seen, so
+        // plays recorded meanwhile cannot shift or repeat rows.
+        let mut after: (i64, i64) = (i64::MIN, i64::MIN);
+        loop {
+            let page: Vec<Play> = {
+                let conn = self.conn();
+                let mut statement = conn.prepare_cached(&sql).map_err(|e| e.to_string())?;
+                let mut params = values.clone();
+                params.extend([
+                    Value::Integer(after.0),
+                    Value::Integer(after.0),
+                    Value::Integer(after.1),
+                    Value::Integer(EXPORT_PAGE_SIZE as i64),
+                ]);
+                let rows = statement
+                    .query_map(params_from_iter(params.iter()), play_from_row)
+                    .map_err(|e| e.to_string())?;
+                rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+            };
+
+            let Some(last) = page.last() else {
+                return Ok(());
+            };
+            after = (last.started_at as i64, last.id);
+            let full = page.len() == EXPORT_PAGE_SIZE;
+            for play in page {
+                f(play)?;
+            }
+            if !full {
+                return Ok(());
+            }
+        }
+    }
+}
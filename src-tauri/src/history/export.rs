@@ -0,0 +1,234 @@
+//! Writes the history out for use in other tools. Rows are streamed from
+//! the database straight to the file, so exports of any size use little
+//! memory.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use super::{HistoryDb, HistoryFilter, Play};
+
+/// Columns that can be selected for CSV and JSON Lines, in default order.
+pub const FIELDS: &[&str] = &[
+    "id",
+    "track_id",
+    "uri",
+    "title",
+    "artists",
+    "album",
+    "image_url",
+    "duration_ms",
+    "is_local",
+    "item_type",
+    "context_uri",
+    "context_type",
+    "device_id",
+    "device_name",
+    "started_at",
+    "ms_played",
+    "skipped",
+    "completed",
+    "source",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    /// The `{"listen_type": "import", "payload": [...]}` document accepted
+    /// by ListenBrainz. Ignores the field selection.
+    Listenbrainz,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Listenbrainz => "json",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportSummary {
+    pub path: String,
+    pub rows: u64,
+}
+
+/// Validates the selection, defaulting to every field.
+pub fn resolve_fields(fields: Option<Vec<String>>) -> Result<Vec<String>, String> {
+    let Some(fields) = fields.filter(|f| !f.is_empty()) else {
+        return Ok(FIELDS.iter().map(|f| f.to_string()).collect());
+    };
+    if let Some(unknown) = fields.iter().find(|f| !FIELDS.contains(&f.as_str())) {
+        return Err(format!("Unknown history field: {}", unknown));
+    }
+    for (i, field) in fields.iter().enumerate() {
+        if fields[..i].contains(field) {
+            return Err(format!("Duplicate history field: {}", field));
+        }
+    }
+    Ok(fields)
+}
+
+fn selected(play: &Play, fields: &[String]) -> Result<Map<String, Value>, String> {
+    let Value::Object(mut all) = serde_json::to_value(play).map_err(|e| e.to_string())? else {
+        return Err("Play did not serialize to an object".to_string());
+    };
+    Ok(fields
+        .iter()
+        .map(|field| (field.clone(), all.remove(field).unwrap_or(Value::Null)))
+        .collect())
+}
+
+fn csv_field(value: &Value) -> String {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| item.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join("; "),
+        other => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+fn listen(play: &Play) -> Value {
+    let mut info = Map::new();
+    info.insert("duration_ms".into(), json!(play.duration_ms));
+    info.insert("ms_played".into(), json!(play.ms_played));
+    info.insert("music_service".into(), json!("spotify.com"));
+    info.insert("submission_client".into(), json!("spotify-widget"));
+    if play.uri.starts_with("spotify:track:") {
+        if let Some(id) = &play.track_id {
+            let url = format!("https://open.spotify.com/track/{}", id);
+            info.insert("spotify_id".into(), json!(url));
+            info.insert("origin_url".into(), json!(url));
+        }
+    }
+    if play.artists.len() > 1 {
+        info.insert("artist_names".into(), json!(play.artists));
+    }
+
+    let mut metadata = Map::new();
+    metadata.insert("artist_name".into(), json!(play.artists.join(", ")));
+    metadata.insert("track_name".into(), json!(play.title));
+    if let Some(album) = &play.album {
+        metadata.insert("release_name".into(), json!(album));
+    }
+    metadata.insert("additional_info".into(), Value::Object(info));
+
+    json!({
+        "listened_at": play.started_at / 1000,
+        "track_metadata": metadata,
+    })
+}
+
+fn write_rows(
+    db: &HistoryDb,
+    filter: &HistoryFilter,
+    format: ExportFormat,
+    fields: &[String],
+    out: &mut impl Write,
+) -> Result<u64, String> {
+    let mut rows = 0u64;
+    let io = |e: std::io::Error| e.to_string();
+
+    match format {
+        ExportFormat::Csv => writeln!(out, "{}", fields.join(",")).map_err(io)?,
+        ExportFormat::Listenbrainz => {
+            write!(out, "{{\"listen_type\":\"import\",\"payload\":[").map_err(io)?
+        }
+        ExportFormat::Jsonl => {}
+    }
+
+    db.for_each(filter, |play| {
+        match format {
+            ExportFormat::Csv => {
+                let row = selected(&play, fields)?;
+                // The map is sorted by key, so go by the requested order.
+                let line: Vec<String> = fields.iter().map(|f| csv_field(&row[f])).collect();
+                writeln!(out, "{}", line.join(",")).map_err(io)?;
+            }
+            ExportFormat::Jsonl => {
+                let row = selected(&play, fields)?;
+                serde_json::to_writer(&mut *out, &row).map_err(|e| e.to_string())?;
+                writeln!(out).map_err(io)?;
+            }
+            ExportFormat::Listenbrainz => {
+                // ListenBrainz only takes music.
+                if play.item_type != "track" || play.artists.is_empty() {
+                    return Ok(());
+                }
+                if rows > 0 {
+                    write!(out, ",").map_err(io)?;
+                }
+                serde_json::to_writer(&mut *out, &listen(&play)).map_err(|e| e.to_string())?;
+            }
+        }
+        rows += 1;
+        Ok(())
+    })?;
+
+    if format == ExportFormat::Listenbrainz {
+        writeln!(out, "]}}").map_err(io)?;
+    }
+    Ok(rows)
+}
+
+/// Streams matching plays to `path`. The file is written under a temporary
+/// name and renamed at the end, so a failed export never leaves half a file.
+pub fn export(
+    db: &HistoryDb,
+    filter: &HistoryFilter,
+    format: ExportFormat,
+    fields: &[String],
+    path: &Path,
+) -> Result<ExportSummary, String> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let result = File::create(&tmp_path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            let rows = write_rows(db, filter, format, fields, &mut out)?;
+            out.flush().map_err(|e| e.to_string())?;
+            Ok(rows)
+        });
+
+    let rows = match result {
+        Ok(rows) => rows,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+    };
+    fs::rename(&tmp_path, path).map_err(|e| e.to_string())?;
+
+    println!("Exported {} plays to {}", rows, path.display());
+    Ok(ExportSummary {
+        path: path.display().to_string(),
+        rows,
+    })
+}
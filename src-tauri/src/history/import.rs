@@ -0,0 +1,419 @@
+//! Imports the listening history from a Spotify privacy export.
+//!
+//! Two formats exist. The "Extended streaming history" export has one
+//! `Streaming_History_Audio_*.json` per year or so, with track URIs and
+//! second-precision timestamps. The older "Account data" export has
+//! `StreamingHistory*.json` with only names and minute-precision times.
+//! Both record when a play *ended*, so the start is derived from that and
+//! the time played.
+//!
+//! Also takes over the history the webview used to keep in `localStorage`.
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tauri::{AppHandle, Emitter};
+
+use super::{db::insert_play, HistoryDb, Play, MIN_PLAY_MS};
+
+pub const SOURCE_EXTENDED: &str = "spotify_extended";
+pub const SOURCE_ACCOUNT: &str = "spotify_account";
+pub const SOURCE_LOCALSTORAGE: &str = "localstorage";
+
+/// A play matching one from another source within this window is a
+/// duplicate. Generous because the account export rounds to the minute and
+/// live plays are timed from the first poll that saw them. Within one
+/// source only an exact match counts, so quick replays survive.
+const DUPLICATE_WINDOW_MS: u64 = 120_000;
+
+/// Plays merged per transaction. Small enough that the recorder and the
+/// UI's queries are only held off the connection briefly.
+const CHUNK_SIZE: usize = 250;
+
+#[derive(Debug, Deserialize)]
+struct ExtendedEntry {
+    ts: String,
+    ms_played: u64,
+    platform: Option<String>,
+    master_metadata_track_name: Option<String>,
+    master_metadata_album_artist_name: Option<String>,
+    master_metadata_album_album_name: Option<String>,
+    spotify_track_uri: Option<String>,
+    episode_name: Option<String>,
+    episode_show_name: Option<String>,
+    spotify_episode_uri: Option<String>,
+    reason_end: Option<String>,
+    skipped: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountEntry {
+    end_time: String,
+    artist_name: String,
+    track_name: String,
+    ms_played: u64,
+}
+
+/// An item of the webview's old `spotify-track-history` list. Only when the
+/// track was first seen is known, not how long it played.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyEntry {
+    track: LegacyTrack,
+    played_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyTrack {
+    id: Option<String>,
+    name: String,
+    #[serde(default)]
+    artists: Vec<LegacyName>,
+    album: Option<LegacyAlbum>,
+    #[serde(default)]
+    duration_ms: u64,
+    uri: String,
+    #[serde(default)]
+    is_local: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyName {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyAlbum {
+    name: String,
+    #[serde(default)]
+    images: Vec<LegacyImage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyImage {
+    url: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportProgress {
+    pub file: String,
+    pub files_done: usize,
+    pub files_total: usize,
+    /// Entries of the current file processed so far, and its total.
+    pub entries_done: usize,
+    pub entries_total: usize,
+    pub imported: usize,
+    pub duplicates: usize,
+    /// Entries without usable metadata, or too short to count.
+    pub ignored: usize,
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Parses `2023-04-01T18:22:05Z` or `2023-04-01 18:22` (both UTC) into Unix
+/// milliseconds.
+fn parse_utc(ts: &str) -> Option<u64> {
+    let ts = ts.trim().trim_end_matches('Z');
+    let (date, time) = ts.split_once(['T', ' '])?;
+
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let mut time_parts = time.splitn(3, ':');
+    let hour: i64 = time_parts.next()?.parse().ok()?;
+    let minute: i64 = time_parts.next()?.parse().ok()?;
+    let second: i64 = match time_parts.next() {
+        Some(second) => second.split('.').next()?.parse().ok()?,
+        None => 0,
+    };
+
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second;
+    u64::try_from(secs).ok().map(|secs| secs * 1000)
+}
+
+fn base_play(source: &str, ended_at: u64, ms_played: u64) -> Play {
+    Play {
+        id: 0,
+        track_id: None,
+        uri: String::new(),
+        title: String::new(),
+        artists: Vec::new(),
+        album: None,
+        image_url: None,
+        duration_ms: 0,
+        is_local: false,
+        item_type: "track".to_string(),
+        context_uri: None,
+        context_type: None,
+        device_id: None,
+        device_name: None,
+        started_at: ended_at.saturating_sub(ms_played),
+        ms_played,
+        skipped: false,
+        completed: false,
+        source: source.to_string(),
+    }
+}
+
+fn from_extended(entry: ExtendedEntry) -> Option<Play> {
+    let ended_at = parse_utc(&entry.ts)?;
+    let mut play = base_play(SOURCE_EXTENDED, ended_at, entry.ms_played);
+    play.device_name = entry.platform;
+    play.completed = entry.reason_end.as_deref() == Some("trackdone");
+    play.skipped = entry.skipped.unwrap_or(false) || entry.reason_end.as_deref() == Some("fwdbtn");
+
+    if let (Some(title), Some(uri)) = (entry.master_metadata_track_name, entry.spotify_track_uri) {
+        play.track_id = uri.rsplit(':').next().map(str::to_string);
+        play.uri = uri;
+        play.title = title;
+        play.artists = entry
+            .master_metadata_album_artist_name
+            .into_iter()
+            .collect();
+        play.album = entry.master_metadata_album_album_name;
+        return Some(play);
+    }
+    if let (Some(title), Some(uri)) = (entry.episode_name, entry.spotify_episode_uri) {
+        play.track_id = uri.rsplit(':').next().map(str::to_string);
+        play.uri = uri;
+        play.title = title;
+        play.artists = entry.episode_show_name.into_iter().collect();
+        play.item_type = "episode".to_string();
+        return Some(play);
+    }
+    None
+}
+
+fn from_account(entry: AccountEntry) -> Option<Play> {
+    let ended_at = parse_utc(&entry.end_time)?;
+    if entry.track_name.trim().is_empty() {
+        return None;
+    }
+    let mut play = base_play(SOURCE_ACCOUNT, ended_at, entry.ms_played);
+    // There is no URI in this format; a stable stand-in keeps plays of the
+    // same track grouped together.
+    play.uri = format!(
+        "spotify:unresolved:{}:{}",
+        urlencoding::encode(&entry.artist_name),
+        urlencoding::encode(&entry.track_name)
+    );
+    play.title = entry.track_name;
+    play.artists = vec![entry.artist_name];
+    Some(play)
+}
+
+fn from_legacy(entry: LegacyEntry) -> Option<Play> {
+    let started_at = parse_utc(&entry.played_at)?;
+    let track = entry.track;
+    if track.uri.is_empty() || track.name.is_empty() {
+        return None;
+    }
+
+    let mut play = base_play(SOURCE_LOCALSTORAGE, started_at, 0);
+    play.track_id = track.id.filter(|id| !id.is_empty());
+    play.uri = track.uri;
+    play.title = track.name;
+    play.artists = track.artists.into_iter().map(|a| a.name).collect();
+    if let Some(album) = track.album {
+        play.image_url = album.images.into_iter().next().map(|i| i.url);
+        play.album = Some(album.name).filter(|name| !name.is_empty());
+    }
+    play.duration_ms = track.duration_ms;
+    play.is_local = track.is_local;
+    Some(play)
+}
+
+fn is_duplicate(conn: &Connection, play: &Play) -> Result<bool, String> {
+    let artist = play.artists.first().map(String::as_str).unwrap_or("");
+    conn.query_row(
+        "SELECT 1 FROM plays
+         WHERE started_at BETWEEN ?1 AND ?2
+           AND (uri = ?3
+                OR (title = ?4 COLLATE NOCASE
+                    AND json_extract(artists, '$[0]') = ?5 COLLATE NOCASE))
+           AND (source != ?6 OR started_at = ?7)
+         LIMIT 1",
+        params![
+            play.started_at.saturating_sub(DUPLICATE_WINDOW_MS),
+            play.started_at + DUPLICATE_WINDOW_MS,
+            play.uri,
+            play.title,
+            artist,
+            play.source,
+            play.started_at,
+        ],
+        |_| Ok(()),
+    )
+    .optional()
+    .map(|found| found.is_some())
+    .map_err(|e| e.to_string())
+}
+
+fn is_export_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    name.ends_with(".json")
+        && (name.starts_with("Streaming_History_Audio_") || name.starts_with("StreamingHistory"))
+}
+
+/// Export files among `paths`, looking inside directories (the unzipped
+/// export) as well.
+fn export_files(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = paths
+        .iter()
+        .flat_map(|path| {
+            if path.is_dir() {
+                walkdir::WalkDir::new(path)
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.into_path())
+                    .filter(|p| is_export_file(p))
+                    .collect()
+            } else {
+                vec![path.clone()]
+            }
+        })
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
+
+fn emit_progress(app_handle: &AppHandle, progress: &ImportProgress) {
+    if let Err(e) = app_handle.emit("history-import-progress", progress) {
+        eprintln!("Failed to emit history-import-progress: {}", e);
+    }
+}
+
+fn parse_file(path: &Path) -> Result<Vec<Option<Play>>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let values: Vec<serde_json::Value> =
+        serde_json::from_str(contents.trim_start_matches('\u{feff}'))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    Ok(values
+        .into_iter()
+        .map(|value| {
+            if value.get("ts").is_some() {
+                serde_json::from_value(value).ok().and_then(from_extended)
+            } else {
+                serde_json::from_value(value).ok().and_then(from_account)
+            }
+        })
+        .collect())
+}
+
+/// Imports every export file found under `paths`, emitting
+/// `history-import-progress` after each chunk of [`CHUNK_SIZE`] entries.
+/// Chunks are committed separately; an interrupted import can simply be run
+/// again, since plays already imported are skipped as duplicates.
+pub fn import(
+    app_handle: &AppHandle,
+    db: &HistoryDb,
+    paths: &[PathBuf],
+) -> Result<ImportProgress, String> {
+    let files = export_files(paths);
+    if files.is_empty() {
+        return Err("No Spotify streaming history files found".to_string());
+    }
+
+    let mut progress = ImportProgress {
+        files_total: files.len(),
+        ..ImportProgress::default()
+    };
+
+    for file in &files {
+        progress.file = file.display().to_string();
+        let plays = match parse_file(file) {
+            Ok(plays) => plays,
+            Err(e) => {
+                eprintln!("Skipping history file {}: {}", file.display(), e);
+                progress.files_done += 1;
+                continue;
+            }
+        };
+
+        progress.entries_done = 0;
+        progress.entries_total = plays.len();
+
+        for chunk in plays.chunks(CHUNK_SIZE) {
+            let mut conn = db.conn();
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            for play in chunk {
+                let Some(play) = play.as_ref().filter(|p| p.ms_played >= MIN_PLAY_MS) else {
+                    progress.ignored += 1;
+                    continue;
+                };
+                if is_duplicate(&tx, play)? {
+                    progress.duplicates += 1;
+                    continue;
+                }
+                insert_play(&tx, play)?;
+                progress.imported += 1;
+            }
+            tx.commit().map_err(|e| e.to_string())?;
+            drop(conn);
+
+            progress.entries_done += chunk.len();
+            if progress.entries_done == progress.entries_total {
+                progress.files_done += 1;
+            }
+            emit_progress(app_handle, &progress);
+        }
+        if plays.is_empty() {
+            progress.files_done += 1;
+            emit_progress(app_handle, &progress);
+        }
+    }
+
+    println!(
+        "Imported {} plays from {} files ({} duplicates, {} ignored)",
+        progress.imported, progress.files_done, progress.duplicates, progress.ignored
+    );
+    Ok(progress)
+}
+
+/// Merges the webview's old `localStorage` history, skipping entries that
+/// are malformed or already recorded, and returns how many were added.
+/// Importing the same list twice adds nothing the second time. The old
+/// history did not record listened time, so these plays are kept for the
+/// list but, being under [`MIN_PLAY_MS`], left out of the stats.
+pub fn import_legacy(db: &HistoryDb, entries: Vec<serde_json::Value>) -> Result<usize, String> {
+    let mut conn = db.conn();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let mut imported = 0;
+    for entry in entries {
+        let Some(play) = serde_json::from_value(entry).ok().and_then(from_legacy) else {
+            continue;
+        };
+        if is_duplicate(&tx, &play)? {
+            continue;
+        }
+        insert_play(&tx, &play)?;
+        imported += 1;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+
+    println!("Imported {} plays from the old webview history", imported);
+    Ok(imported)
+}
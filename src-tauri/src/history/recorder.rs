@@ -0,0 +1,353 @@
+//! Turns the playback poller's snapshots into plays.
+//!
+//! A play is inserted once it has lasted [`MIN_PLAY_MS`] and its listened
+//! time is written out as it goes, so it survives the app being closed
+//! mid-track. When the app comes back to the same track, the open play is
+//! picked up again instead of recording a second one. Only time that
+//! actually elapsed while playing counts: seeking forward does not add
+//! listened time and seeking back does not subtract it.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use super::{HistoryDb, Play, MIN_PLAY_MS, SOURCE_LIVE};
+use crate::playback::{PlaybackEvent, PlaybackHub, PlaybackSnapshot};
+
+/// A play counts as completed when it got this close to the end.
+const COMPLETION_MARGIN_MS: u64 = 10_000;
+
+/// Poll jitter allowed on top of the wall-clock time between two polls.
+const PROGRESS_SLACK_MS: u64 = 1_500;
+
+/// How often listened time of the running play is written out.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How far a resumed play's start may be estimated before the recorded
+/// one; the estimate comes from the poll time and is not exact.
+const RESUME_SLACK_MS: u64 = 5_000;
+
+struct Session {
+    play: Play,
+    /// `None` until the play is long enough to be stored.
+    play_id: Option<i64>,
+    ms_played: u64,
+    last_progress: u64,
+    last_seen: Instant,
+    playing: bool,
+    last_flush: Instant,
+}
+
+impl Session {
+    fn completed(&self) -> bool {
+        let duration_ms = self.play.duration_ms;
+        duration_ms > 0 && self.last_progress + COMPLETION_MARGIN_MS >= duration_ms
+    }
+
+    /// Repeat-one, or replaying the track from its end, shows up as the
+    /// position jumping from the end back to the start.
+    fn restarted(&self, progress_ms: u64) -> bool {
+        self.completed() && progress_ms + COMPLETION_MARGIN_MS < self.last_progress
+    }
+}
+
+fn play_for(snapshot: &PlaybackSnapshot) -> Play {
+    Play {
+        id: 0,
+        track_id: snapshot.track_id.clone(),
+        uri: snapshot.uri.clone(),
+        title: snapshot.title.clone(),
+        artists: snapshot.artists.clone(),
+        album: snapshot.album.clone(),
+        image_url: snapshot.image_url.clone(),
+        duration_ms: snapshot.duration_ms,
+        is_local: snapshot.is_local,
+        item_type: snapshot.item_type.clone(),
+        context_uri: snapshot.context_uri.clone(),
+        context_type: snapshot.context_type.clone(),
+        device_id: snapshot.device_id.clone(),
+        device_name: snapshot.device_name.clone(),
+        // The poll that first sees a track can come well into it.
+        started_at: snapshot.observed_at.saturating_sub(snapshot.progress_ms),
+        ms_played: 0,
+        skipped: false,
+        completed: false,
+        source: SOURCE_LIVE.to_string(),
+    }
+}
+
+/// Whether `latest`, the last recorded play, is the listen `play` is still
+/// part of: the app restarted, or playback stopped and resumed, mid-track.
+fn resumes(latest: &Play, play: &Play) -> bool {
+    latest.uri == play.uri
+        && !latest.completed
+        && !latest.skipped
+        && latest.started_at <= play.started_at + RESUME_SLACK_MS
+        && play.started_at <= latest.started_at + play.duration_ms.max(latest.duration_ms)
+}
+
+struct Recorder {
+    db: Arc<HistoryDb>,
+    session: Option<Session>,
+}
+
+impl Recorder {
+    fn start(&mut self, snapshot: &PlaybackSnapshot) {
+        let play = play_for(snapshot);
+        let resumed = match self.db.latest(SOURCE_LIVE) {
+            Ok(latest) => latest.filter(|latest| resumes(latest, &play)),
+            Err(e) => {
+                eprintln!("Failed to look up the last play: {}", e);
+                None
+            }
+        };
+
+        let now = Instant::now();
+        self.session = Some(Session {
+            play_id: resumed.as_ref().map(|latest| latest.id),
+            ms_played: resumed.map(|latest| latest.ms_played).unwrap_or(0),
+            last_progress: snapshot.progress_ms,
+            last_seen: now,
+            playing: snapshot.is_playing,
+            last_flush: now,
+            play,
+        });
+    }
+
+    /// Writes out the play, inserting it the first time.
+    fn save(&self, session: &mut Session, skipped: bool, completed: bool) -> Result<(), String> {
+        match session.play_id {
+            Some(id) => self
+                .db
+                .update_progress(id, session.ms_played, skipped, completed),
+            None => {
+                let play = Play {
+                    ms_played: session.ms_played,
+                    skipped,
+                    completed,
+                    ..session.play.clone()
+                };
+                session.play_id = Some(self.db.insert(&play)?);
+                Ok(())
+            }
+        }
+    }
+
+    /// Closes the running play. `moved_on` is false when playback simply
+    /// stopped, which is not a skip.
+    fn finish(&mut self, moved_on: bool) {
+        let Some(mut session) = self.session.take() else {
+            return;
+        };
+
+        let result = match session.play_id {
+            _ if session.ms_played >= MIN_PLAY_MS => {
+                let completed = session.completed();
+                self.save(&mut session, moved_on && !completed, completed)
+            }
+            Some(id) => self.db.delete(id),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to finish play of {}: {}", session.play.uri, e);
+        }
+    }
+
+    fn observe(&mut self, snapshot: &PlaybackSnapshot) {
+        let restarted = match &self.session {
+            None => true,
+            Some(session) => {
+                session.play.uri != snapshot.uri || session.restarted(snapshot.progress_ms)
+            }
+        };
+        if restarted {
+            self.finish(true);
+            self.start(snapshot);
+            return;
+        }
+
+        let Some(mut session) = self.session.take() else {
+            return;
+        };
+        if session.playing {
+            let elapsed = session.last_seen.elapsed().as_millis() as u64;
+            let advanced = snapshot.progress_ms.saturating_sub(session.last_progress);
+            session.ms_played += advanced.min(elapsed + PROGRESS_SLACK_MS);
+        }
+        session.last_progress = snapshot.progress_ms;
+        session.last_seen = Instant::now();
+        session.playing = snapshot.is_playing;
+
+        if session.ms_played >= MIN_PLAY_MS && session.last_flush.elapsed() >= FLUSH_INTERVAL {
+            session.last_flush = Instant::now();
+            let completed = session.completed();
+            if let Err(e) = self.save(&mut session, false, completed) {
+                eprintln!("Failed to update play of {}: {}", session.play.uri, e);
+            }
+        }
+        self.session = Some(session);
+    }
+}
+
+/// Records every play reported by the playback poller. SQLite calls block,
+/// so the recorder runs on its own thread rather than the async runtime.
+pub fn spawn_recorder(hub: Arc<PlaybackHub>, db: Arc<HistoryDb>) {
+    let events = hub.subscribe();
+    let spawned = std::thread::Builder::new()
+        .name("history-recorder".to_string())
+        .spawn(move || run(events, db));
+
+    if let Err(e) = spawned {
+        eprintln!("Failed to start history recorder: {}", e);
+    }
+}
+
+fn run(mut events: Receiver<PlaybackEvent>, db: Arc<HistoryDb>) {
+    // Plays cut short by a crash in older versions, which stored every
+    // play as soon as it started.
+    match db.delete_shorter_than(SOURCE_LIVE, MIN_PLAY_MS) {
+        Ok(0) => {}
+        Ok(removed) => println!("Removed {} unfinished plays from history", removed),
+        Err(e) => eprintln!("Failed to clean up unfinished plays: {}", e),
+    }
+
+    let mut recorder = Recorder { db, session: None };
+    loop {
+        match events.blocking_recv() {
+            // Progress follows in the same poll and starts the play.
+            Ok(PlaybackEvent::TrackChanged(_)) => recorder.finish(true),
+            Ok(PlaybackEvent::Progress(snapshot)) => recorder.observe(&snapshot),
+            Ok(PlaybackEvent::Stopped) => recorder.finish(false),
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => {
+                recorder.finish(false);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::HistoryFilter;
+
+    fn snapshot(uri: &str, observed_at: u64, progress_ms: u64) -> PlaybackSnapshot {
+        PlaybackSnapshot {
+            track_id: None,
+            uri: uri.to_string(),
+            title: "Title".to_string(),
+            artists: vec!["Artist".to_string()],
+            album: None,
+            image_url: None,
+            duration_ms: 200_000,
+            progress_ms,
+            is_playing: true,
+            is_local: false,
+            item_type: "track".to_string(),
+            device_id: None,
+            device_name: None,
+            volume_percent: None,
+            context_uri: None,
+            context_type: None,
+            shuffle: false,
+            repeat: "off".to_string(),
+            observed_at,
+        }
+    }
+
+    fn recorder(db: &Arc<HistoryDb>) -> Recorder {
+        Recorder {
+            db: db.clone(),
+            session: None,
+        }
+    }
+
+    /// Pretends `ms` of playback went by since the last poll.
+    fn play_for_ms(recorder: &mut Recorder, uri: &str, observed_at: u64, progress_ms: u64) {
+        let session = recorder.session.as_mut().unwrap();
+        let ms = progress_ms - session.last_progress;
+        session.last_seen = Instant::now() - Duration::from_millis(ms);
+        session.last_flush = Instant::now() - FLUSH_INTERVAL;
+        recorder.observe(&snapshot(uri, observed_at, progress_ms));
+    }
+
+    fn plays(db: &HistoryDb) -> Vec<Play> {
+        db.query(&HistoryFilter::default(), 0, 100).unwrap().0
+    }
+
+    #[test]
+    fn short_plays_are_never_stored() {
+        let db = Arc::new(HistoryDb::open_in_memory().unwrap());
+        let mut recorder = recorder(&db);
+        recorder.observe(&snapshot("spotify:track:a", 1_000_000, 0));
+        play_for_ms(&mut recorder, "spotify:track:a", 1_001_000, 1_000);
+        recorder.observe(&snapshot("spotify:track:b", 1_002_000, 0));
+        assert!(plays(&db).is_empty());
+
+        play_for_ms(&mut recorder, "spotify:track:b", 1_032_000, 30_000);
+        recorder.finish(false);
+        let plays = plays(&db);
+        assert_eq!(plays.len(), 1);
+        assert_eq!(plays[0].uri, "spotify:track:b");
+        assert_eq!(plays[0].ms_played, 30_000);
+    }
+
+    #[test]
+    fn restart_mid_track_resumes_the_open_play() {
+        let db = Arc::new(HistoryDb::open_in_memory().unwrap());
+        let mut before = recorder(&db);
+        before.observe(&snapshot("spotify:track:a", 1_000_000, 0));
+        play_for_ms(&mut before, "spotify:track:a", 1_060_000, 60_000);
+        // Crash: the session is never finished.
+        drop(before);
+        assert_eq!(plays(&db)[0].ms_played, 60_000);
+
+        let mut after = recorder(&db);
+        after.observe(&snapshot("spotify:track:a", 1_090_000, 61_000));
+        play_for_ms(&mut after, "spotify:track:a", 1_120_000, 91_000);
+        after.finish(true);
+
+        let plays = plays(&db);
+        assert_eq!(plays.len(), 1);
+        assert_eq!(plays[0].ms_played, 90_000);
+        assert!(plays[0].skipped);
+    }
+
+    #[test]
+    fn replaying_a_finished_track_is_a_new_play() {
+        let db = Arc::new(HistoryDb::open_in_memory().unwrap());
+        let mut recorder = recorder(&db);
+        recorder.observe(&snapshot("spotify:track:a", 1_000_000, 0));
+        play_for_ms(&mut recorder, "spotify:track:a", 1_195_000, 195_000);
+        recorder.finish(false);
+        assert!(plays(&db)[0].completed);
+
+        recorder.observe(&snapshot("spotify:track:a", 1_300_000, 0));
+        play_for_ms(&mut recorder, "spotify:track:a", 1_330_000, 30_000);
+        recorder.finish(false);
+        assert_eq!(plays(&db).len(), 2);
+    }
+
+    #[test]
+    fn startup_removes_plays_cut_short() {
+        let db = HistoryDb::open_in_memory().unwrap();
+        let orphan = play_for(&snapshot("spotify:track:a", 1_000_000, 0));
+        db.insert(&orphan).unwrap();
+        db.insert(&Play {
+            ms_played: 30_000,
+            ..orphan.clone()
+        })
+        .unwrap();
+        db.insert(&Play {
+            source: "localstorage".to_string(),
+            ..orphan
+        })
+        .unwrap();
+
+        assert_eq!(db.delete_shorter_than(SOURCE_LIVE, MIN_PLAY_MS), Ok(1));
+        assert_eq!(plays(&db).len(), 2);
+    }
+}
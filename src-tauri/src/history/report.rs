@@ -0,0 +1,463 @@
+//! A "year in review" style report for any period, rendered as a single
+//! HTML file. Styles, charts (inline SVG) and cover art (data URIs) are all
+//! embedded, so the file opens offline and can be shared as is.
+
+use serde::Serialize;
+use std::{collections::HashMap, fmt::Write};
+
+use super::{
+    DailyListening, Discovery, HeatmapCell, HistoryDb, HistoryFilter, Play, Streaks, TopAlbum,
+    TopArtist, TopTrack,
+};
+
+const TOP_LIMIT: u64 = 10;
+const FIRST_LISTENS_LIMIT: u64 = 20;
+
+/// Periods longer than this are charted by month instead of by day.
+const MAX_DAILY_BARS: usize = 92;
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportSummary {
+    pub path: String,
+    pub plays: u64,
+    pub ms_played: u64,
+}
+
+/// Everything the report shows, read in one go from the database.
+pub struct ReportData {
+    pub period_start: Option<String>,
+    pub period_end: Option<String>,
+    pub discovery: Discovery,
+    pub streaks: Streaks,
+    pub top_tracks: Vec<TopTrack>,
+    pub top_artists: Vec<TopArtist>,
+    pub top_albums: Vec<TopAlbum>,
+    pub days: Vec<DailyListening>,
+    pub heatmap: Vec<HeatmapCell>,
+    /// First listens with the local day each happened on.
+    pub first_listens: Vec<(Play, String)>,
+}
+
+impl ReportData {
+    pub fn ms_played(&self) -> u64 {
+        self.days.iter().map(|day| day.ms_played).sum()
+    }
+
+    /// Image URLs worth embedding, without duplicates.
+    pub fn image_urls(&self) -> Vec<String> {
+        let mut urls: Vec<String> = Vec::new();
+        let images = self
+            .top_tracks
+            .iter()
+            .map(|t| &t.image_url)
+            .chain(self.top_albums.iter().map(|a| &a.image_url))
+            .chain(self.first_listens.iter().map(|(p, _)| &p.image_url));
+        for url in images.flatten() {
+            if !urls.contains(url) {
+                urls.push(url.clone());
+            }
+        }
+        urls
+    }
+}
+
+/// Local dates (`YYYY-MM-DD`) of Unix millisecond timestamps, as SQLite
+/// sees them, so they match the daily stats.
+fn local_dates(db: &HistoryDb, timestamps: &[u64]) -> Result<Vec<String>, String> {
+    let conn = db.conn();
+    let mut statement = conn
+        .prepare("SELECT date(?1 / 1000, 'unixepoch', 'localtime')")
+        .map_err(|e| e.to_string())?;
+    timestamps
+        .iter()
+        .map(|ms| {
+            statement
+                .query_row([*ms as i64], |row| row.get(0))
+                .map_err(|e| e.to_string())
+        })
+        .collect()
+}
+
+pub fn gather(db: &HistoryDb, filter: &HistoryFilter) -> Result<ReportData, String> {
+    let days = db.listening_by_day(filter)?;
+    let first_listens = db.first_listens(filter, FIRST_LISTENS_LIMIT)?;
+    let first_days = local_dates(
+        db,
+        &first_listens
+            .iter()
+            .map(|p| p.started_at)
+            .collect::<Vec<_>>(),
+    )?;
+
+    // `to` is exclusive, so the last day of the period is the one before it.
+    let period_start = match filter.from {
+        Some(from) => local_dates(db, &[from])?.pop(),
+        None => days.first().map(|d| d.day.clone()),
+    };
+    let period_end = match filter.to {
+        Some(to) => local_dates(db, &[to.saturating_sub(1)])?.pop(),
+        None => days.last().map(|d| d.day.clone()),
+    };
+
+    Ok(ReportData {
+        period_start,
+        period_end,
+        discovery: db.discovery(filter)?,
+        streaks: db.streaks(filter)?,
+        top_tracks: db.top_tracks(filter, TOP_LIMIT)?,
+        top_artists: db.top_artists(filter, TOP_LIMIT)?,
+        top_albums: db.top_albums(filter, TOP_LIMIT)?,
+        days,
+        heatmap: db.heatmap(filter)?,
+        first_listens: first_listens.into_iter().zip(first_days).collect(),
+    })
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn format_duration(ms: u64) -> String {
+    let minutes = ms / 60_000;
+    if minutes < 60 {
+        format!("{} min", minutes)
+    } else if minutes < 60 * 48 {
+        format!("{} h {} min", minutes / 60, minutes % 60)
+    } else {
+        format!("{} days {} h", minutes / (60 * 24), minutes / 60 % 24)
+    }
+}
+
+fn minutes(ms: u64) -> f64 {
+    ms as f64 / 60_000.0
+}
+
+/// A vertical bar chart. Only every few labels are drawn when there are
+/// many bars; each bar carries its own tooltip.
+fn bar_chart(bars: &[(String, f64)], unit: &str) -> String {
+    const WIDTH: f64 = 720.0;
+    const HEIGHT: f64 = 180.0;
+    const LABEL_HEIGHT: f64 = 20.0;
+
+    if bars.is_empty() {
+        return "<p class=\"muted\">Nothing played.</p>".to_string();
+    }
+    let max = bars.iter().map(|(_, v)| *v).fold(0.0, f64::max).max(1.0);
+    let slot = WIDTH / bars.len() as f64;
+    let gap = (slot * 0.2).min(4.0);
+    let label_every = (bars.len() as f64 / 12.0).ceil().max(1.0) as usize;
+
+    let mut svg = format!(
+        "<svg class=\"chart\" viewBox=\"0 0 {} {}\" role=\"img\">",
+        WIDTH,
+        HEIGHT + LABEL_HEIGHT
+    );
+    for (i, (label, value)) in bars.iter().enumerate() {
+        let height = value / max * HEIGHT;
+        let x = i as f64 * slot;
+        let _ = write!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" rx=\"2\">\
+             <title>{}: {:.0} {}</title></rect>",
+            x + gap / 2.0,
+            HEIGHT - height,
+            slot - gap,
+            height,
+            escape(label),
+            value,
+            unit
+        );
+        if i % label_every == 0 {
+            let _ = write!(
+                svg,
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+                x + slot / 2.0,
+                HEIGHT + LABEL_HEIGHT - 4.0,
+                escape(label)
+            );
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Weekday by hour grid, shaded by listening time.
+fn heatmap_chart(cells: &[HeatmapCell]) -> String {
+    const CELL: f64 = 26.0;
+    const LEFT: f64 = 40.0;
+    const TOP: f64 = 18.0;
+
+    let max = cells.iter().map(|c| c.ms_played).max().unwrap_or(0).max(1) as f64;
+    let mut svg = format!(
+        "<svg class=\"chart heatmap\" viewBox=\"0 0 {} {}\" role=\"img\">",
+        LEFT + CELL * 24.0,
+        TOP + CELL * 7.0
+    );
+    for hour in (0..24).step_by(3) {
+        let _ = write!(
+            svg,
+            "<text x=\"{:.1}\" y=\"12\" text-anchor=\"middle\">{:02}</text>",
+            LEFT + hour as f64 * CELL + CELL / 2.0,
+            hour
+        );
+    }
+    for (weekday, name) in WEEKDAYS.iter().enumerate() {
+        let y = TOP + weekday as f64 * CELL;
+        let _ = write!(
+            svg,
+            "<text x=\"0\" y=\"{:.1}\">{}</text>",
+            y + CELL * 0.65,
+            name
+        );
+        for hour in 0..24 {
+            let ms = cells
+                .iter()
+                .find(|c| c.weekday as usize == weekday && c.hour == hour)
+                .map(|c| c.ms_played)
+                .unwrap_or(0);
+            let _ = write!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" rx=\"3\" \
+                 fill-opacity=\"{:.2}\"><title>{} {:02}:00: {}</title></rect>",
+                LEFT + hour as f64 * CELL + 1.0,
+                y + 1.0,
+                CELL - 2.0,
+                CELL - 2.0,
+                0.06 + 0.94 * ms as f64 / max,
+                name,
+                hour,
+                format_duration(ms)
+            );
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+fn daily_bars(days: &[DailyListening]) -> Vec<(String, f64)> {
+    if days.len() <= MAX_DAILY_BARS {
+        return days
+            .iter()
+            .map(|d| (d.day.clone(), minutes(d.ms_played)))
+            .collect();
+    }
+    let mut months: Vec<(String, f64)> = Vec::new();
+    for day in days {
+        let month = day.day.get(..7).unwrap_or(&day.day);
+        match months.last_mut() {
+            Some((last, total)) if last == month => *total += minutes(day.ms_played),
+            _ => months.push((month.to_string(), minutes(day.ms_played))),
+        }
+    }
+    months
+}
+
+fn cover(covers: &HashMap<String, String>, image_url: &Option<String>) -> String {
+    match image_url.as_ref().and_then(|url| covers.get(url)) {
+        Some(data) => format!("<img class=\"cover\" src=\"{}\" alt=\"\">", data),
+        None => "<span class=\"cover\"></span>".to_string(),
+    }
+}
+
+fn stat(html: &mut String, value: &str, label: &str) {
+    let _ = write!(
+        html,
+        "<div class=\"stat\"><strong>{}</strong><span>{}</span></div>",
+        escape(value),
+        escape(label)
+    );
+}
+
+const STYLE: &str = r#"
+:root { color-scheme: dark; --accent: #1db954; }
+body { margin: 0; background: #121212; color: #eee; font: 15px/1.45 system-ui, sans-serif; }
+main { max-width: 780px; margin: 0 auto; padding: 32px 24px 64px; }
+h1 { margin: 0; font-size: 2.2em; }
+h2 { margin-top: 40px; font-size: 1.3em; border-bottom: 1px solid #333; padding-bottom: 6px; }
+.muted { color: #999; }
+.stats { display: grid; grid-template-columns: repeat(auto-fill, minmax(160px, 1fr)); gap: 12px; margin-top: 24px; }
+.stat { background: #1e1e1e; border-radius: 8px; padding: 12px 14px; }
+.stat strong { display: block; font-size: 1.5em; color: var(--accent); }
+.stat span { color: #aaa; font-size: 0.9em; }
+ol { list-style: none; padding: 0; counter-reset: rank; }
+li { display: flex; align-items: center; gap: 12px; padding: 6px 0; counter-increment: rank; }
+ol li::before { content: counter(rank); width: 1.6em; text-align: right; color: #777; }
+.cover { width: 48px; height: 48px; border-radius: 4px; background: #2a2a2a; object-fit: cover; flex: none; }
+.name { flex: 1; min-width: 0; }
+.name div { overflow: hidden; text-overflow: ellipsis; white-space: nowrap; }
+.name .muted { font-size: 0.9em; }
+.count { color: #aaa; font-size: 0.9em; text-align: right; white-space: nowrap; }
+.chart { width: 100%; height: auto; }
+.chart rect { fill: var(--accent); }
+.chart text { fill: #999; font-size: 11px; }
+footer { margin-top: 48px; color: #666; font-size: 0.85em; }
+"#;
+
+/// Renders the report. `covers` maps image URLs to data URIs.
+pub fn render(title: &str, data: &ReportData, covers: &HashMap<String, String>) -> String {
+    let mut html = String::new();
+    let period = match (&data.period_start, &data.period_end) {
+        (Some(start), Some(end)) if start == end => start.clone(),
+        (Some(start), Some(end)) => format!("{} to {}", start, end),
+        _ => "No listening in this period".to_string(),
+    };
+
+    let _ = write!(
+        html,
+        "<!DOCTYPE html><html lang=\"en\"><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{0}</title><style>{1}</style></head><body><main>\
+         <h1>{0}</h1><p class=\"muted\">{2}</p>",
+        escape(title),
+        STYLE,
+        escape(&period)
+    );
+
+    // Totals and listening time breakdown.
+    let ms_played = data.ms_played();
+    let active_days = data.days.len() as u64;
+    let discovery = &data.discovery;
+    html.push_str("<div class=\"stats\">");
+    stat(&mut html, &format_duration(ms_played), "listened");
+    stat(&mut html, &discovery.plays.to_string(), "plays");
+    stat(
+        &mut html,
+        &discovery.unique_tracks.to_string(),
+        "different tracks",
+    );
+    stat(
+        &mut html,
+        &discovery.unique_artists.to_string(),
+        "different artists",
+    );
+    stat(&mut html, &discovery.new_tracks.to_string(), "new tracks");
+    stat(&mut html, &discovery.new_artists.to_string(), "new artists");
+    stat(&mut html, &active_days.to_string(), "days with music");
+    if let Some(per_day) = ms_played.checked_div(active_days) {
+        stat(&mut html, &format_duration(per_day), "per listening day");
+    }
+    stat(
+        &mut html,
+        &format!("{} days", data.streaks.longest_days),
+        "longest streak",
+    );
+    if let Some(busiest) = data.days.iter().max_by_key(|d| d.ms_played) {
+        stat(&mut html, &busiest.day, "busiest day");
+    }
+    html.push_str("</div>");
+
+    html.push_str("<h2>Top tracks</h2><ol>");
+    for track in &data.top_tracks {
+        let _ = write!(
+            html,
+            "<li>{}<div class=\"name\"><div>{}</div><div class=\"muted\">{}</div></div>\
+             <div class=\"count\">{} plays<br>{}</div></li>",
+            cover(covers, &track.image_url),
+            escape(&track.title),
+            escape(&track.artists.join(", ")),
+            track.plays,
+            format_duration(track.ms_played)
+        );
+    }
+    html.push_str("</ol>");
+
+    html.push_str("<h2>Top artists</h2><ol>");
+    for artist in &data.top_artists {
+        let share = if ms_played > 0 {
+            artist.ms_played as f64 / ms_played as f64 * 100.0
+        } else {
+            0.0
+        };
+        let _ = write!(
+            html,
+            "<li><div class=\"name\"><div>{}</div><div class=\"muted\">{} tracks, \
+             {:.1}% of listening time</div></div>\
+             <div class=\"count\">{} plays<br>{}</div></li>",
+            escape(&artist.artist),
+            artist.tracks,
+            share,
+            artist.plays,
+            format_duration(artist.ms_played)
+        );
+    }
+    html.push_str("</ol>");
+
+    html.push_str("<h2>Top albums</h2><ol>");
+    for album in &data.top_albums {
+        let _ = write!(
+            html,
+            "<li>{}<div class=\"name\"><div>{}</div><div class=\"muted\">{}</div></div>\
+             <div class=\"count\">{} plays<br>{}</div></li>",
+            cover(covers, &album.image_url),
+            escape(&album.album),
+            escape(album.artist.as_deref().unwrap_or_default()),
+            album.plays,
+            format_duration(album.ms_played)
+        );
+    }
+    html.push_str("</ol>");
+
+    let by_month = data.days.len() > MAX_DAILY_BARS;
+    let _ = write!(
+        html,
+        "<h2>Minutes by {}</h2>{}",
+        if by_month { "month" } else { "day" },
+        bar_chart(&daily_bars(&data.days), "min")
+    );
+
+    let mut hours = [0u64; 24];
+    let mut weekdays = [0u64; 7];
+    for cell in &data.heatmap {
+        hours[cell.hour as usize % 24] += cell.ms_played;
+        weekdays[cell.weekday as usize % 7] += cell.ms_played;
+    }
+    let hour_bars: Vec<(String, f64)> = hours
+        .iter()
+        .enumerate()
+        .map(|(hour, ms)| (format!("{:02}", hour), minutes(*ms)))
+        .collect();
+    let weekday_bars: Vec<(String, f64)> = WEEKDAYS
+        .iter()
+        .zip(weekdays)
+        .map(|(name, ms)| (name.to_string(), minutes(ms)))
+        .collect();
+    let _ = write!(
+        html,
+        "<h2>Time of day</h2>{}<h2>Day of the week</h2>{}<h2>When you listen</h2>{}",
+        bar_chart(&hour_bars, "min"),
+        bar_chart(&weekday_bars, "min"),
+        heatmap_chart(&data.heatmap)
+    );
+
+    if !data.first_listens.is_empty() {
+        html.push_str("<h2>First listens</h2><ol>");
+        for (play, day) in &data.first_listens {
+            let _ = write!(
+                html,
+                "<li>{}<div class=\"name\"><div>{}</div><div class=\"muted\">{}</div></div>\
+                 <div class=\"count\">{}</div></li>",
+                cover(covers, &play.image_url),
+                escape(&play.title),
+                escape(&play.artists.join(", ")),
+                escape(day)
+            );
+        }
+        html.push_str("</ol>");
+    }
+
+    html.push_str("<footer>Generated by Spotify Widget.</footer></main></body></html>");
+    html
+}
@@ -0,0 +1,460 @@
+//! Aggregates over the plays table. Everything is plain SQL so SQLite can
+//! use the indexes; days and hours are in the machine's local time.
+
+use rusqlite::{params_from_iter, types::Value, Row};
+use serde::Serialize;
+
+use super::{
+    db::{filter_clause, play_from_row, COLUMNS},
+    HistoryDb, HistoryFilter, Play, MIN_PLAY_MS,
+};
+
+/// Unix milliseconds to a local date-time, for `date()` and `strftime()`.
+const LOCAL_TIME: &str = "started_at / 1000, 'unixepoch', 'localtime'";
+
+/// The filter, limited to plays long enough to count. Entries from the old
+/// webview history carry no listened time, so they only show in the list.
+fn counted_clause(filter: &HistoryFilter) -> (String, Vec<Value>) {
+    let (clause, mut values) = filter_clause(filter);
+    values.push(Value::Integer(MIN_PLAY_MS as i64));
+    (format!("{} AND ms_played >= ?", clause), values)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopTrack {
+    pub uri: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub image_url: Option<String>,
+    pub plays: u64,
+    pub ms_played: u64,
+    pub last_played: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopArtist {
+    pub artist: String,
+    pub plays: u64,
+    pub ms_played: u64,
+    pub tracks: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopAlbum {
+    pub album: String,
+    pub artist: Option<String>,
+    pub image_url: Option<String>,
+    pub plays: u64,
+    pub ms_played: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DailyListening {
+    /// `YYYY-MM-DD`.
+    pub day: String,
+    pub plays: u64,
+    pub ms_played: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HeatmapCell {
+    /// 0 is Sunday.
+    pub weekday: u8,
+    pub hour: u8,
+    pub plays: u64,
+    pub ms_played: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Streaks {
+    /// Consecutive days with plays ending today, or yesterday if nothing
+    /// has played yet today.
+    pub current_days: u64,
+    pub longest_days: u64,
+    pub longest_start: Option<String>,
+    pub longest_end: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtistSkipRate {
+    pub artist: String,
+    pub plays: u64,
+    pub skips: u64,
+    pub skip_rate: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Discovery {
+    pub plays: u64,
+    pub unique_tracks: u64,
+    pub unique_artists: u64,
+    /// Tracks whose first play ever falls inside the filter.
+    pub new_tracks: u64,
+    pub new_artists: u64,
+    /// Share of unique tracks that were new, 0.0 to 1.0.
+    pub track_discovery_rate: f64,
+    pub artist_discovery_rate: f64,
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+impl HistoryDb {
+    fn collect<T>(
+        &self,
+        sql: &str,
+        values: Vec<Value>,
+        map: impl FnMut(&Row) -> rusqlite::Result<T>,
+    ) -> Result<Vec<T>, String> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(sql).map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map(params_from_iter(values.iter()), map)
+            .map_err(|e| e.to_string())?
+            .collect::<rusqlite::Result<Vec<T>>>()
+            .map_err(|e| e.to_string());
+        rows
+    }
+
+    pub fn top_tracks(&self, filter: &HistoryFilter, limit: u64) -> Result<Vec<TopTrack>, String> {
+        let (clause, mut values) = counted_clause(filter);
+        values.push(Value::Integer(limit as i64));
+        // With MAX(), SQLite takes the bare columns from the latest play, so
+        // renamed tracks show their current metadata.
+        let sql = format!(
+            "SELECT uri, title, artists, album, image_url, COUNT(*) AS plays,
+                SUM(ms_played) AS ms_played, MAX(started_at) AS last_played
+             FROM plays WHERE {}
+             GROUP BY uri ORDER BY plays DESC, ms_played DESC LIMIT ?",
+            clause
+        );
+        self.collect(&sql, values, |row| {
+            let artists: String = row.get("artists")?;
+            Ok(TopTrack {
+                uri: row.get("uri")?,
+                title: row.get("title")?,
+                artists: serde_json::from_str(&artists).unwrap_or_default(),
+                album: row.get("album")?,
+                image_url: row.get("image_url")?,
+                plays: row.get("plays")?,
+                ms_played: row.get("ms_played")?,
+                last_played: row.get("last_played")?,
+            })
+        })
+    }
+
+    pub fn top_artists(
+        &self,
+        filter: &HistoryFilter,
+        limit: u64,
+    ) -> Result<Vec<TopArtist>, String> {
+        let (clause, mut values) = counted_clause(filter);
+        values.push(Value::Integer(limit as i64));
+        let sql = format!(
+            "SELECT play_artists.artist, COUNT(*) AS plays, SUM(ms_played) AS ms_played,
+                COUNT(DISTINCT uri) AS tracks
+             FROM plays JOIN play_artists ON play_artists.play_id = plays.id WHERE {}
+             GROUP BY play_artists.artist
+             ORDER BY plays DESC, ms_played DESC LIMIT ?",
+            clause
+        );
+        self.collect(&sql, values, |row| {
+            Ok(TopArtist {
+                artist: row.get("artist")?,
+                plays: row.get("plays")?,
+                ms_played: row.get("ms_played")?,
+                tracks: row.get("tracks")?,
+            })
+        })
+    }
+
+    pub fn top_albums(&self, filter: &HistoryFilter, limit: u64) -> Result<Vec<TopAlbum>, String> {
+        let (clause, mut values) = counted_clause(filter);
+        values.push(Value::Integer(limit as i64));
+        let sql = format!(
+            "SELECT album, json_extract(artists, '$[0]') AS artist, image_url,
+                COUNT(*) AS plays, SUM(ms_played) AS ms_played, MAX(started_at)
+             FROM plays WHERE {} AND album IS NOT NULL AND album != ''
+             GROUP BY album COLLATE NOCASE, json_extract(artists, '$[0]') COLLATE NOCASE
+             ORDER BY plays DESC, ms_played DESC LIMIT ?",
+            clause
+        );
+        self.collect(&sql, values, |row| {
+            Ok(TopAlbum {
+                album: row.get("album")?,
+                artist: row.get("artist")?,
+                image_url: row.get("image_url")?,
+                plays: row.get("plays")?,
+                ms_played: row.get("ms_played")?,
+            })
+        })
+    }
+
+    pub fn listening_by_day(&self, filter: &HistoryFilter) -> Result<Vec<DailyListening>, String> {
+        let (clause, values) = counted_clause(filter);
+        let sql = format!(
+            "SELECT date({}) AS day, COUNT(*) AS plays, SUM(ms_played) AS ms_played
+             FROM plays WHERE {} GROUP BY day ORDER BY day",
+            LOCAL_TIME, clause
+        );
+        self.collect(&sql, values, |row| {
+            Ok(DailyListening {
+                day: row.get("day")?,
+                plays: row.get("plays")?,
+                ms_played: row.get("ms_played")?,
+            })
+        })
+    }
+
+    pub fn heatmap(&self, filter: &HistoryFilter) -> Result<Vec<HeatmapCell>, String> {
+        let (clause, values) = counted_clause(filter);
+        let sql = format!(
+            "SELECT CAST(strftime('%w', {0}) AS INTEGER) AS weekday,
+                CAST(strftime('%H', {0}) AS INTEGER) AS hour,
+                COUNT(*) AS plays, SUM(ms_played) AS ms_played
+             FROM plays WHERE {1} GROUP BY weekday, hour ORDER BY weekday, hour",
+            LOCAL_TIME, clause
+        );
+        self.collect(&sql, values, |row| {
+            Ok(HeatmapCell {
+                weekday: row.get("weekday")?,
+                hour: row.get("hour")?,
+                plays: row.get("plays")?,
+                ms_played: row.get("ms_played")?,
+            })
+        })
+    }
+
+    pub fn streaks(&self, filter: &HistoryFilter) -> Result<Streaks, String> {
+        let (clause, values) = counted_clause(filter);
+        let sql = format!(
+            "SELECT DISTINCT CAST(julianday(date({0})) AS INTEGER) AS day_number,
+                date({0}) AS day
+             FROM plays WHERE {1} ORDER BY day_number",
+            LOCAL_TIME, clause
+        );
+        let days: Vec<(i64, String)> =
+            self.collect(&sql, values, |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let today: i64 = self
+            .conn()
+            .query_row(
+                "SELECT CAST(julianday(date('now', 'localtime')) AS INTEGER)",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+
+        let mut streaks = Streaks::default();
+        let mut run_start = 0;
+        for i in 0..days.len() {
+            if i > 0 && days[i].0 != days[i - 1].0 + 1 {
+                run_start = i;
+            }
+            let length = (i - run_start + 1) as u64;
+            if length > streaks.longest_days {
+                streaks.longest_days = length;
+                streaks.longest_start = Some(days[run_start].1.clone());
+                streaks.longest_end = Some(days[i].1.clone());
+            }
+            if i == days.len() - 1 && today - days[i].0 <= 1 {
+                streaks.current_days = length;
+            }
+        }
+        Ok(streaks)
+    }
+
+    pub fn skip_rates(
+        &self,
+        filter: &HistoryFilter,
+        min_plays: u64,
+        limit: u64,
+    ) -> Result<Vec<ArtistSkipRate>, String> {
+        let (clause, mut values) = counted_clause(filter);
+        values.push(Value::Integer(min_plays as i64));
+        values.push(Value::Integer(limit as i64));
+        let sql = format!(
+            "SELECT play_artists.artist, COUNT(*) AS plays, SUM(skipped) AS skips,
+                CAST(SUM(skipped) AS REAL) / COUNT(*) AS skip_rate
+             FROM plays JOIN play_artists ON play_artists.play_id = plays.id WHERE {}
+             GROUP BY play_artists.artist HAVING plays >= ?
+             ORDER BY skip_rate DESC, plays DESC LIMIT ?",
+            clause
+        );
+        self.collect(&sql, values, |row| {
+            Ok(ArtistSkipRate {
+                artist: row.get("artist")?,
+                plays: row.get("plays")?,
+                skips: row.get("skips")?,
+                skip_rate: row.get("skip_rate")?,
+            })
+        })
+    }
+
+    pub fn discovery(&self, filter: &HistoryFilter) -> Result<Discovery, String> {
+        let (clause, values) = counted_clause(filter);
+        let conn = self.conn();
+        let count = |sql: String| -> Result<u64, String> {
+            conn.query_row(&sql, params_from_iter(values.iter()), |row| row.get(0))
+                .map_err(|e| e.to_string())
+        };
+
+        let plays = count(format!("SELECT COUNT(*) FROM plays WHERE {}", clause))?;
+        let unique_tracks = count(format!(
+            "SELECT COUNT(DISTINCT uri) FROM plays WHERE {}",
+            clause
+        ))?;
+        let unique_artists = count(format!(
+            "SELECT COUNT(DISTINCT play_artists.artist)
+             FROM plays JOIN play_artists ON play_artists.play_id = plays.id WHERE {}",
+            clause
+        ))?;
+        // A play is a discovery when no play of the same track (or artist)
+        // happened before it, filter or not.
+        let new_tracks = count(format!(
+            "SELECT COUNT(DISTINCT uri) FROM plays WHERE {} AND NOT EXISTS (
+                SELECT 1 FROM plays AS earlier
+                WHERE earlier.uri = plays.uri AND earlier.started_at < plays.started_at)",
+            clause
+        ))?;
+        let new_artists = count(format!(
+            "WITH first_plays AS (
+                SELECT play_artists.artist, MIN(plays.started_at) AS first_played
+                FROM plays JOIN play_artists ON play_artists.play_id = plays.id
+                GROUP BY play_artists.artist)
+             SELECT COUNT(DISTINCT play_artists.artist)
+             FROM plays JOIN play_artists ON play_artists.play_id = plays.id
+             JOIN first_plays ON first_plays.artist = play_artists.artist
+             WHERE {} AND plays.started_at = first_plays.first_played",
+            clause
+        ))?;
+
+        Ok(Discovery {
+            plays,
+            unique_tracks,
+            unique_artists,
+            new_tracks,
+            new_artists,
+            track_discovery_rate: ratio(new_tracks, unique_tracks),
+            artist_discovery_rate: ratio(new_artists, unique_artists),
+        })
+    }
+
+    /// The first play ever of each track first heard inside the filter,
+    /// oldest first.
+    pub fn first_listens(&self, filter: &HistoryFilter, limit: u64) -> Result<Vec<Play>, String> {
+        let (clause, mut values) = counted_clause(filter);
+        values.push(Value::Integer(limit as i64));
+        let sql = format!(
+            "SELECT {} FROM plays WHERE {} AND NOT EXISTS (
+                SELECT 1 FROM plays AS earlier
+                WHERE earlier.uri = plays.uri AND earlier.started_at < plays.started_at)
+             GROUP BY uri ORDER BY started_at LIMIT ?",
+            COLUMNS, clause
+        );
+        self.collect(&sql, values, play_from_row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::SOURCE_LIVE;
+
+    fn play(uri: &str, artists: &[&str], started_at: u64, skipped: bool) -> Play {
+        Play {
+            id: 0,
+            track_id: None,
+            uri: uri.to_string(),
+            title: uri.to_string(),
+            artists: artists.iter().map(|a| a.to_string()).collect(),
+            album: None,
+            image_url: None,
+            duration_ms: 200_000,
+            is_local: false,
+            item_type: "track".to_string(),
+            context_uri: None,
+            context_type: None,
+            device_id: None,
+            device_name: None,
+            started_at,
+            ms_played: 60_000,
+            skipped,
+            completed: !skipped,
+            source: SOURCE_LIVE.to_string(),
+        }
+    }
+
+    fn artist_plays(db: &HistoryDb, filter: &HistoryFilter) -> Vec<(String, u64, u64)> {
+        let mut top: Vec<_> = db
+            .top_artists(filter, 10)
+            .unwrap()
+            .into_iter()
+            .map(|a| (a.artist.to_lowercase(), a.plays, a.tracks))
+            .collect();
+        top.sort();
+        top
+    }
+
+    #[test]
+    fn artist_stats_count_every_credited_artist() {
+        let db = HistoryDb::open_in_memory().unwrap();
+        db.insert(&play("a", &["Daft Punk", "Pharrell"], 1_000, false))
+            .unwrap();
+        db.insert(&play("b", &["daft punk"], 2_000, true)).unwrap();
+        let removed = db.insert(&play("c", &["Pharrell"], 3_000, true)).unwrap();
+        db.delete(removed).unwrap();
+
+        let all = HistoryFilter::default();
+        assert_eq!(
+            artist_plays(&db, &all),
+            [
+                ("daft punk".to_string(), 2, 2),
+                ("pharrell".to_string(), 1, 1)
+            ]
+        );
+
+        let skips = db.skip_rates(&all, 2, 10).unwrap();
+        assert_eq!(skips.len(), 1);
+        assert_eq!(skips[0].skips, 1);
+
+        let discovery = db.discovery(&all).unwrap();
+        assert_eq!(discovery.unique_artists, 2);
+        assert_eq!(discovery.new_artists, 2);
+
+        let pharrell = HistoryFilter {
+            artist: Some("PHARRELL".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(db.query(&pharrell, 0, 10).unwrap().1, 1);
+    }
+
+    #[test]
+    fn migration_fills_play_artists_from_existing_plays() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        for migration in &crate::history::db::MIGRATIONS[..2] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", 2).unwrap();
+        conn.execute(
+            "INSERT INTO plays (uri, title, artists, started_at, ms_played)
+             VALUES ('a', 'A', '[\"Björk\", \"Thom Yorke\"]', 1000, 60000)",
+            [],
+        )
+        .unwrap();
+
+        let db = HistoryDb::init(conn).unwrap();
+        assert_eq!(
+            artist_plays(&db, &HistoryFilter::default()),
+            [
+                ("björk".to_string(), 1, 1),
+                ("thom yorke".to_string(), 1, 1)
+            ]
+        );
+    }
+}
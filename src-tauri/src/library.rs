@@ -0,0 +1,408 @@
+mod index;
+mod matcher;
+mod scanner;
+mod watcher;
+
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    time::Instant,
+};
+use tauri::{AppHandle, Emitter};
+use tauri_plugin_dialog::DialogExt;
+
+use crate::config::SharedConfig;
+pub use index::{AlbumMatch, LibraryIndex};
+pub use watcher::spawn_watcher;
+
+/// The local music index plus the bookkeeping for background scans.
+pub struct Library {
+    index: RwLock<LibraryIndex>,
+    scanning: AtomicBool,
+    rescan_requested: AtomicBool,
+    /// Watcher changes that arrived while a scan was running, replayed once
+    /// it commits. The lock also orders watcher updates against a scan
+    /// starting and committing, so neither can overwrite the other.
+    deferred: Mutex<BTreeSet<PathBuf>>,
+    /// Counts committed scans, so the watcher can tell one happened while
+    /// it was working out changes.
+    scan_generation: AtomicU64,
+    /// Set when the watcher changed the index since it was last saved.
+    index_dirty: AtomicBool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LibraryStatus {
+    pub tracks: usize,
+    pub albums: usize,
+    pub last_scan: Option<u64>,
+    pub scanning: bool,
+}
+
+impl Library {
+    pub fn load() -> Self {
+        Library {
+            index: RwLock::new(LibraryIndex::load()),
+            scanning: AtomicBool::new(false),
+            rescan_requested: AtomicBool::new(false),
+            deferred: Mutex::new(BTreeSet::new()),
+            scan_generation: AtomicU64::new(0),
+            index_dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn index(&self) -> RwLockReadGuard<'_, LibraryIndex> {
+        match self.index.read() {
+            Ok(index) => index,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn index_mut(&self) -> RwLockWriteGuard<'_, LibraryIndex> {
+        match self.index.write() {
+            Ok(index) => index,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn deferred(&self) -> MutexGuard<'_, BTreeSet<PathBuf>> {
+        match self.deferred.lock() {
+            Ok(deferred) => deferred,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Writes the index if the watcher changed it since the last save.
+    fn save_if_dirty(&self) {
+        if self.index_dirty.swap(false, Ordering::SeqCst) {
+            if let Err(e) = self.index().save() {
+                eprintln!("Failed to save library index: {}", e);
+            }
+        }
+    }
+
+    pub fn status(&self) -> LibraryStatus {
+        let index = self.index();
+        LibraryStatus {
+            tracks: index.tracks.len(),
+            albums: index.album_count(),
+            last_scan: index.last_scan,
+            scanning: self.scanning.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LibraryRoot {
+    pub path: String,
+    /// False when the folder is missing, e.g. an unplugged external drive.
+    pub available: bool,
+}
+
+/// Expands a leading `~` to the user's home directory. `PathBuf` never does
+/// this on its own, so `~/Music` would otherwise be looked up relative to the
+/// working directory.
+pub fn expand_tilde(input: &str) -> PathBuf {
+    let trimmed = input.trim();
+
+    if let Some(home) = dirs::home_dir() {
+        if trimmed == "~" {
+            return home;
+        }
+        if let Some(rest) = trimmed
+            .strip_prefix("~/")
+            .or_else(|| trimmed.strip_prefix("~\\"))
+        {
+            return home.join(rest);
+        }
+    }
+
+    PathBuf::from(trimmed)
+}
+
+/// Folders searched when the user has not configured any roots.
+pub fn default_roots() -> Vec<PathBuf> {
+    if cfg!(target_os = "windows") {
+        vec![
+            dirs::audio_dir().unwrap_or_else(|| PathBuf::from("C:\\Users\\Public\\Music")),
+            dirs::document_dir()
+                .map(|d| d.join("Music"))
+                .unwrap_or_else(|| PathBuf::from("C:\\Users\\Public\\Documents\\Music")),
+        ]
+    } else {
+        vec![
+            dirs::audio_dir().unwrap_or_else(|| expand_tilde("~/Music")),
+            dirs::document_dir()
+                .map(|d| d.join("Music"))
+                .unwrap_or_else(|| expand_tilde("~/Documents/Music")),
+        ]
+    }
+}
+
+/// The roots every library feature should look in: the configured ones, or
+/// the platform defaults if none are configured yet.
+pub fn effective_roots(config: &SharedConfig) -> Vec<PathBuf> {
+    let configured = config
+        .lock()
+        .map(|c| c.library_roots.clone())
+        .unwrap_or_default();
+
+    if configured.is_empty() {
+        default_roots()
+    } else {
+        configured
+    }
+}
+
+/// Compares component by component, so trailing separators do not matter.
+fn same_path(a: &Path, b: &Path) -> bool {
+    if cfg!(target_os = "windows") {
+        a.components().count() == b.components().count()
+            && a.components().zip(b.components()).all(|(a, b)| {
+                a.as_os_str()
+                    .to_string_lossy()
+                    .eq_ignore_ascii_case(&b.as_os_str().to_string_lossy())
+            })
+    } else {
+        a == b
+    }
+}
+
+fn list_roots(config: &SharedConfig) -> Vec<LibraryRoot> {
+    config
+        .lock()
+        .map(|c| {
+            c.library_roots
+                .iter()
+                .map(|path| LibraryRoot {
+                    path: path.display().to_string(),
+                    available: path.is_dir(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn add_root(config: &SharedConfig, path: &str) -> Result<PathBuf, String> {
+    let expanded = expand_tilde(path);
+    if !expanded.is_dir() {
+        return Err(format!("Not a directory: {}", expanded.display()));
+    }
+    let root = expanded.canonicalize().unwrap_or(expanded);
+
+    let mut config = config.lock().map_err(|e| e.to_string())?;
+    if !config.library_roots.iter().any(|r| same_path(r, &root)) {
+        config.library_roots.push(root.clone());
+        config.save()?;
+        println!("Added library root: {}", root.display());
+    }
+
+    Ok(root)
+}
+
+#[derive(Clone, Serialize)]
+struct ScanProgress {
+    scanned: usize,
+}
+
+/// Rescans all roots on a blocking thread. A request that arrives while a
+/// scan is running is remembered and served by one follow-up scan.
+pub fn spawn_scan(app_handle: AppHandle, library: Arc<Library>, config: SharedConfig) {
+    {
+        let _deferred = library.deferred();
+        if library.scanning.swap(true, Ordering::SeqCst) {
+            library.rescan_requested.store(true, Ordering::SeqCst);
+            return;
+        }
+    }
+
+    tauri::async_runtime::spawn_blocking(move || loop {
+        library.rescan_requested.store(false, Ordering::SeqCst);
+
+        let roots = effective_roots(&config);
+        let started = Instant::now();
+        println!("Scanning music library ({} roots)", roots.len());
+
+        // Work from a snapshot so the watcher and status queries are not
+        // blocked on the index lock for the length of the walk.
+        let previous = library.index().clone();
+        let index = scanner::scan(&roots, &previous, |scanned| {
+            let _ = app_handle.emit("library-scan-progress", ScanProgress { scanned });
+        });
+
+        if let Err(e) = index.save() {
+            eprintln!("Failed to save library index: {}", e);
+        }
+        println!(
+            "Library scan finished: {} tracks in {:.1}s",
+            index.tracks.len(),
+            started.elapsed().as_secs_f32()
+        );
+
+        let (again, replay) = {
+            let mut deferred = library.deferred();
+            *library.index_mut() = index;
+            library.scan_generation.fetch_add(1, Ordering::SeqCst);
+            let again = library.rescan_requested.swap(false, Ordering::SeqCst);
+            if !again {
+                library.scanning.store(false, Ordering::SeqCst);
+            }
+            // Changes seen mid-scan wait for the last scan in a row.
+            let replay = if again {
+                BTreeSet::new()
+            } else {
+                std::mem::take(&mut *deferred)
+            };
+            (again, replay)
+        };
+
+        if let Err(e) = app_handle.emit("library-scan-complete", library.status()) {
+            eprintln!("Failed to emit library-scan-complete: {}", e);
+        }
+        if !replay.is_empty() {
+            watcher::apply(&app_handle, &library, &config, replay);
+        }
+        if !again {
+            break;
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn select_music_directory(
+    app_handle: AppHandle,
+    config: tauri::State<'_, SharedConfig>,
+    library: tauri::State<'_, Arc<Library>>,
+) -> Result<Option<String>, String> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+
+    app_handle
+        .dialog()
+        .file()
+        .set_title("Choose a music folder")
+        .pick_folder(move |folder| {
+            let _ = tx.send(folder);
+        });
+
+    let folder = match rx.await.map_err(|e| e.to_string())? {
+        Some(folder) => folder.into_path().map_err(|e| e.to_string())?,
+        None => return Ok(None),
+    };
+
+    let root = add_root(config.inner(), &folder.to_string_lossy())?;
+    spawn_scan(app_handle, library.inner().clone(), config.inner().clone());
+    Ok(Some(root.display().to_string()))
+}
+
+#[tauri::command]
+pub async fn add_library_root(
+    path: String,
+    app_handle: AppHandle,
+    config: tauri::State<'_, SharedConfig>,
+    library: tauri::State<'_, Arc<Library>>,
+) -> Result<Vec<LibraryRoot>, String> {
+    add_root(config.inner(), &path)?;
+    spawn_scan(app_handle, library.inner().clone(), config.inner().clone());
+    Ok(list_roots(config.inner()))
+}
+
+#[tauri::command]
+pub async fn remove_library_root(
+    path: String,
+    app_handle: AppHandle,
+    config: tauri::State<'_, SharedConfig>,
+    library: tauri::State<'_, Arc<Library>>,
+) -> Result<Vec<LibraryRoot>, String> {
+    // Roots are stored canonicalized; one that is not mounted right now can
+    // only be matched as typed.
+    let target = expand_tilde(&path);
+    let canonical = target.canonicalize().ok();
+    {
+        let mut config = config.lock().map_err(|e| e.to_string())?;
+        let before = config.library_roots.len();
+        config.library_roots.retain(|r| {
+            !same_path(r, &target) && !canonical.as_ref().is_some_and(|c| same_path(r, c))
+        });
+
+        if config.library_roots.len() == before {
+            return Err(format!("Not a library root: {}", target.display()));
+        }
+        config.save()?;
+    }
+
+    println!("Removed library root: {}", target.display());
+    spawn_scan(app_handle, library.inner().clone(), config.inner().clone());
+    Ok(list_roots(config.inner()))
+}
+
+#[tauri::command]
+pub async fn list_library_roots(
+    config: tauri::State<'_, SharedConfig>,
+) -> Result<Vec<LibraryRoot>, String> {
+    Ok(list_roots(config.inner()))
+}
+
+#[tauri::command]
+pub async fn scan_library(
+    app_handle: AppHandle,
+    config: tauri::State<'_, SharedConfig>,
+    library: tauri::State<'_, Arc<Library>>,
+) -> Result<LibraryStatus, String> {
+    spawn_scan(app_handle, library.inner().clone(), config.inner().clone());
+    Ok(library.status())
+}
+
+#[tauri::command]
+pub async fn get_library_status(
+    library: tauri::State<'_, Arc<Library>>,
+) -> Result<LibraryStatus, String> {
+    Ok(library.status())
+}
+
+#[tauri::command]
+pub async fn find_local_album_art(
+    artist: String,
+    album: String,
+    track: String,
+    library: tauri::State<'_, Arc<Library>>,
+) -> Result<Option<String>, String> {
+    println!(
+        "Searching for album art: {} - {} - {}",
+        artist, album, track
+    );
+
+    match library.index().find_album_art(&artist, &album, &track) {
+        Some(found) => {
+            println!(
+                "Found album art: {:?} (confidence {:.2})",
+                found.album.art, found.confidence
+            );
+            Ok(found.album.art_url)
+        }
+        None => {
+            println!("No album art found for: {} - {}", artist, album);
+            Ok(None)
+        }
+    }
+}
+
+/// The local album that best matches the given metadata, whatever its
+/// confidence, so the UI can decide how much to trust it.
+#[tauri::command]
+pub async fn match_local_album(
+    artist: String,
+    album: String,
+    track: String,
+    library: tauri::State<'_, Arc<Library>>,
+) -> Result<Option<AlbumMatch>, String> {
+    Ok(library
+        .index()
+        .match_albums(&artist, &album, &track)
+        .into_iter()
+        .next())
+}
@@ -0,0 +1,316 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::matcher;
+use crate::{art_protocol, config};
+
+const INDEX_FILE: &str = "library-index.json";
+
+/// Bumped whenever the on-disk layout changes; older files are discarded and
+/// rebuilt by the next scan.
+const INDEX_VERSION: u32 = 2;
+
+/// Albums scoring below this are not worth reporting as candidates at all.
+const MIN_SCORE: f32 = 0.4;
+const MAX_CANDIDATES: usize = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedTrack {
+    pub path: PathBuf,
+    pub root: PathBuf,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub duration_ms: Option<u64>,
+    /// Cached copy of the cover embedded in the file's tags, if any.
+    pub embedded_art: Option<PathBuf>,
+    /// Modification time (Unix seconds) and size when the tags were read;
+    /// rescans only re-read files where either changed.
+    pub modified: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderArt {
+    pub dir: PathBuf,
+    pub image: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlbumSummary {
+    pub artist: String,
+    pub album: String,
+    pub dir: PathBuf,
+    pub art: Option<PathBuf>,
+    /// `albumart://` URL for `art` that the webview can load.
+    pub art_url: Option<String>,
+    pub track_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlbumMatch {
+    #[serde(flatten)]
+    pub album: AlbumSummary,
+    /// 0.0 to 1.0; see [`matcher::MIN_CONFIDENCE`].
+    pub confidence: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexFile {
+    version: u32,
+    last_scan: Option<u64>,
+    tracks: Vec<IndexedTrack>,
+    folder_art: Vec<FolderArt>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct LibraryIndex {
+    pub tracks: HashMap<PathBuf, IndexedTrack>,
+    /// Best cover image per directory, keyed by the directory.
+    pub folder_art: HashMap<PathBuf, PathBuf>,
+    pub last_scan: Option<u64>,
+    /// Tracks grouped by album with their art already resolved, so matching
+    /// and status queries never touch the filesystem. Derived from `tracks`
+    /// and `folder_art`; call [`LibraryIndex::rebuild_albums`] after
+    /// changing either.
+    albums: HashMap<(String, String), Album>,
+}
+
+#[derive(Debug, Clone)]
+struct Album {
+    tracks: Vec<PathBuf>,
+    art: Option<PathBuf>,
+    keys: matcher::AlbumKeys,
+}
+
+impl IndexedTrack {
+    /// The artist the album is filed under.
+    pub fn album_artist_or_artist(&self) -> &str {
+        self.album_artist.as_deref().unwrap_or(&self.artist)
+    }
+}
+
+impl LibraryIndex {
+    fn path() -> PathBuf {
+        config::data_dir().join(INDEX_FILE)
+    }
+
+    pub fn load() -> LibraryIndex {
+        let file: Option<IndexFile> = fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok());
+
+        match file {
+            Some(file) if file.version == INDEX_VERSION => LibraryIndex::new(
+                file.tracks
+                    .into_iter()
+                    .map(|t| (t.path.clone(), t))
+                    .collect(),
+                file.folder_art
+                    .into_iter()
+                    .map(|a| (a.dir, a.image))
+                    .collect(),
+                file.last_scan,
+            ),
+            _ => LibraryIndex::default(),
+        }
+    }
+
+    pub fn new(
+        tracks: HashMap<PathBuf, IndexedTrack>,
+        folder_art: HashMap<PathBuf, PathBuf>,
+        last_scan: Option<u64>,
+    ) -> LibraryIndex {
+        let mut index = LibraryIndex {
+            tracks,
+            folder_art,
+            last_scan,
+            albums: HashMap::new(),
+        };
+        index.rebuild_albums();
+        index
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let file = IndexFile {
+            version: INDEX_VERSION,
+            last_scan: self.last_scan,
+            tracks: self.tracks.values().cloned().collect(),
+            folder_art: self
+                .folder_art
+                .iter()
+                .map(|(dir, image)| FolderArt {
+                    dir: dir.clone(),
+                    image: image.clone(),
+                })
+                .collect(),
+        };
+
+        let contents = serde_json::to_vec(&file).map_err(|e| e.to_string())?;
+        config::write_atomic(&Self::path(), &contents)
+    }
+
+    /// Cover image for the folder a track lives in. Multi-disc albums often
+    /// keep the art one level up from `CD1`/`CD2` folders.
+    pub fn art_for_dir(&self, dir: &Path) -> Option<&PathBuf> {
+        self.folder_art
+            .get(dir)
+            .or_else(|| dir.parent().and_then(|parent| self.folder_art.get(parent)))
+    }
+
+    /// Folder art wins over embedded art since it is usually the higher
+    /// resolution scan. Tracks without either have no art. The embedded
+    /// art cache is checked by the scanner, not here.
+    pub fn art_for_track(&self, track: &IndexedTrack) -> Option<PathBuf> {
+        track
+            .path
+            .parent()
+            .and_then(|dir| self.art_for_dir(dir))
+            .or(track.embedded_art.as_ref())
+            .cloned()
+    }
+
+    /// Regroups tracks by album, keyed by lower-cased album artist and
+    /// title, and resolves each album's art and match keys.
+    pub fn rebuild_albums(&mut self) {
+        let mut groups: HashMap<(String, String), Vec<&IndexedTrack>> = HashMap::new();
+        for track in self.tracks.values() {
+            let key = (
+                track.album_artist_or_artist().to_lowercase(),
+                track.album.to_lowercase(),
+            );
+            groups.entry(key).or_default().push(track);
+        }
+
+        let albums = groups
+            .into_iter()
+            .map(|(key, tracks)| {
+                // Compilations credit each track separately, so every artist
+                // on the album is a candidate.
+                let artists: Vec<&str> = tracks
+                    .iter()
+                    .flat_map(|t| [t.artist.as_str(), t.album_artist_or_artist()])
+                    .collect();
+                let album = Album {
+                    tracks: tracks.iter().map(|t| t.path.clone()).collect(),
+                    art: tracks.iter().find_map(|t| self.art_for_track(t)),
+                    keys: matcher::AlbumKeys::new(&artists, &tracks[0].album),
+                };
+                (key, album)
+            })
+            .collect();
+        self.albums = albums;
+    }
+
+    fn album_tracks(&self, album: &Album) -> Vec<&IndexedTrack> {
+        album
+            .tracks
+            .iter()
+            .filter_map(|path| self.tracks.get(path))
+            .collect()
+    }
+
+    fn summarize(&self, album: &Album, tracks: &[&IndexedTrack]) -> AlbumSummary {
+        let first = tracks[0];
+        let art = album.art.clone();
+        AlbumSummary {
+            artist: first.album_artist_or_artist().to_string(),
+            album: first.album.clone(),
+            dir: first.path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            art_url: art.as_deref().map(art_protocol::url_for),
+            art,
+            track_count: tracks.len(),
+        }
+    }
+
+    /// Local albums ranked by how well they match the given Spotify metadata,
+    /// best first.
+    pub fn match_albums(&self, artist: &str, album: &str, title: &str) -> Vec<AlbumMatch> {
+        let query = matcher::Query::new(artist, album, title);
+
+        let mut scored: Vec<(f32, &Album, Vec<&IndexedTrack>)> = self
+            .albums
+            .values()
+            .map(|album| (album, self.album_tracks(album)))
+            .filter(|(_, tracks)| !tracks.is_empty())
+            .map(|(album, tracks)| (query.score_keys(&album.keys), album, tracks))
+            .filter(|(score, _, _)| *score >= MIN_SCORE)
+            .collect();
+
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(MAX_CANDIDATES);
+
+        let mut matches: Vec<AlbumMatch> = scored
+            .into_iter()
+            .map(|(score, album, tracks)| {
+                let confidence = if tracks.iter().any(|t| query.title_matches(&t.title)) {
+                    matcher::Query::with_title_bonus(score)
+                } else {
+                    score
+                };
+                AlbumMatch {
+                    album: self.summarize(album, &tracks),
+                    confidence,
+                }
+            })
+            .collect();
+
+        matches.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        matches
+    }
+
+    /// The indexed track that confidently matches the given metadata. The
+    /// title has to match; the album may be empty if unknown.
+    pub fn find_track(&self, artist: &str, album: &str, title: &str) -> Option<IndexedTrack> {
+        let query = matcher::Query::new(artist, album, title);
+
+        self.tracks
+            .values()
+            .filter(|t| query.title_matches(&t.title))
+            .map(|t| {
+                let score = query.score(&[&t.artist, t.album_artist_or_artist()], &t.album);
+                (matcher::Query::with_title_bonus(score), t)
+            })
+            .filter(|(confidence, _)| *confidence >= matcher::MIN_CONFIDENCE)
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, track)| track.clone())
+    }
+
+    /// Art of the best confident match that has any.
+    pub fn find_album_art(&self, artist: &str, album: &str, title: &str) -> Option<AlbumMatch> {
+        self.match_albums(artist, album, title)
+            .into_iter()
+            .find(|m| m.confidence >= matcher::MIN_CONFIDENCE && m.album.art.is_some())
+    }
+
+    /// Drops every track at or below `path`, plus folder art for
+    /// directories below it. Returns the number of tracks removed.
+    pub fn remove_under(&mut self, path: &Path) -> usize {
+        let before = self.tracks.len();
+        self.tracks.retain(|track, _| !track.starts_with(path));
+        self.folder_art.retain(|dir, _| !dir.starts_with(path));
+        before - self.tracks.len()
+    }
+
+    pub fn set_folder_art(&mut self, dir: PathBuf, image: Option<PathBuf>) {
+        match image {
+            Some(image) => {
+                self.folder_art.insert(dir, image);
+            }
+            None => {
+                self.folder_art.remove(&dir);
+            }
+        }
+    }
+
+    pub fn album_count(&self) -> usize {
+        self.albums.len()
+    }
+}
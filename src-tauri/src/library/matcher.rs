@@ -0,0 +1,384 @@
+//! Lines up Spotify's metadata with what is on disk. Names are compared after
+//! normalisation rather than byte for byte, since the two sides rarely agree
+//! exactly: "AC/DC" is stored as `AC_DC`, Spotify appends "(Remastered 2011)"
+//! to album titles, and macOS hands out NFD file names.
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Characters at least one supported filesystem refuses in file names. Rippers
+/// replace them with `_`, `-` or nothing, so they are treated as separators.
+const FS_ILLEGAL: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Words that mark a bracketed or dashed suffix as an edition qualifier,
+/// e.g. "(2011 Remaster)" or " - Deluxe Edition".
+const QUALIFIER_WORDS: &[&str] = &[
+    "remaster",
+    "remastered",
+    "deluxe",
+    "edition",
+    "expanded",
+    "anniversary",
+    "bonus",
+    "version",
+    "reissue",
+    "mono",
+    "stereo",
+    "explicit",
+    "clean",
+];
+
+/// Matches scoring at or above this are trusted without asking the user.
+pub const MIN_CONFIDENCE: f32 = 0.75;
+
+/// Replaces filesystem-illegal and control characters with spaces.
+pub fn sanitize(input: &str) -> String {
+    input
+        .chars()
+        .map(|c| {
+            if FS_ILLEGAL.contains(&c) || c.is_control() {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// The comparison form of a name: sanitised, NFC, case-folded, `&` spelled
+/// out and punctuation collapsed to single spaces. A leading "the" is dropped
+/// so "The Beatles" and "Beatles" compare equal.
+pub fn normalize(input: &str) -> String {
+    let composed: String = sanitize(input).nfc().collect();
+    let folded: String = caseless::default_case_fold_str(&composed).nfc().collect();
+    let folded = folded.replace(['&', '+'], " and ");
+
+    let mut words: Vec<&str> = folded
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    if words.len() > 1 && words[0] == "the" {
+        words.remove(0);
+    }
+    words.join(" ")
+}
+
+fn is_qualifier(text: &str) -> bool {
+    normalize(text)
+        .split(' ')
+        .any(|word| QUALIFIER_WORDS.contains(&word))
+}
+
+/// Removes edition and remaster qualifiers: bracketed groups such as
+/// "(Remastered 2011)" or "[Deluxe Edition]", and dashed suffixes such as
+/// " - 2009 Remaster". Anything else in brackets is kept, so "Live (Tokyo)"
+/// stays intact.
+pub fn strip_qualifiers(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(open) = rest.find(['(', '[', '{']) {
+        let close_char = match rest.as_bytes()[open] {
+            b'(' => ')',
+            b'[' => ']',
+            _ => '}',
+        };
+        let Some(close) = rest[open..].find(close_char).map(|i| open + i) else {
+            break;
+        };
+
+        out.push_str(&rest[..open]);
+        let group = &rest[open..=close];
+        if !is_qualifier(&group[1..group.len() - 1]) {
+            out.push_str(group);
+        }
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+
+    for separator in [" - ", " – ", " — "] {
+        if let Some((head, tail)) = out.rsplit_once(separator) {
+            if !head.trim().is_empty() && is_qualifier(tail) {
+                out = head.to_string();
+                break;
+            }
+        }
+    }
+
+    out.trim().to_string()
+}
+
+/// Sørensen–Dice coefficient over character bigrams, 0.0 to 1.0.
+fn dice(a: &str, b: &str) -> f32 {
+    let bigrams = |s: &str| {
+        let chars: Vec<char> = s.chars().collect();
+        chars.windows(2).map(|w| (w[0], w[1])).collect::<Vec<_>>()
+    };
+    let a = bigrams(a);
+    let mut b = bigrams(b);
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    let total = (a.len() + b.len()) as f32;
+    let mut shared = 0usize;
+    for pair in &a {
+        if let Some(i) = b.iter().position(|p| p == pair) {
+            b.swap_remove(i);
+            shared += 1;
+        }
+    }
+    2.0 * shared as f32 / total
+}
+
+/// Similarity of two already-normalised names. Spacing differences such as
+/// "ac dc" against "acdc" are close to, but not quite, an exact match.
+fn similarity(a: &str, b: &str) -> f32 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    if a == b {
+        return 1.0;
+    }
+
+    let compact_a: String = a.chars().filter(|c| *c != ' ').collect();
+    let compact_b: String = b.chars().filter(|c| *c != ' ').collect();
+    if compact_a == compact_b {
+        return 0.97;
+    }
+    dice(&compact_a, &compact_b) * 0.9
+}
+
+/// An album name in both its plain and qualifier-free normalised forms.
+#[derive(Debug, Clone)]
+struct AlbumName {
+    full: String,
+    stripped: String,
+}
+
+impl AlbumName {
+    fn new(raw: &str) -> Self {
+        AlbumName {
+            full: normalize(raw),
+            stripped: normalize(&strip_qualifiers(raw)),
+        }
+    }
+
+    fn similarity(&self, other: &AlbumName) -> f32 {
+        let full = similarity(&self.full, &other.full);
+        // Equal once qualifiers are gone means a different edition of the
+        // same album, which is nearly as good for artwork.
+        let stripped = similarity(&self.stripped, &other.stripped) * 0.95;
+        full.max(stripped)
+    }
+}
+
+/// The normalised names a local album is matched on. Built once when the
+/// index is, so matching does not re-normalise the whole library per query.
+#[derive(Debug, Clone)]
+pub struct AlbumKeys {
+    artists: Vec<String>,
+    album: AlbumName,
+}
+
+impl AlbumKeys {
+    pub fn new(artists: &[&str], album: &str) -> Self {
+        let mut artists: Vec<String> = artists.iter().map(|a| normalize(a)).collect();
+        artists.sort_unstable();
+        artists.dedup();
+        AlbumKeys {
+            artists,
+            album: AlbumName::new(album),
+        }
+    }
+}
+
+/// What the player reports for the current track, normalised once and
+/// scored against every album in the index.
+pub struct Query {
+    artists: Vec<String>,
+    album: AlbumName,
+    title: String,
+}
+
+impl Query {
+    /// `artist` may list several names ("A, B", "A feat. B"); any of them
+    /// matching the folder's artist counts.
+    pub fn new(artist: &str, album: &str, title: &str) -> Self {
+        let mut artists = vec![normalize(artist)];
+        let lowered = artist.to_lowercase();
+        for separator in [
+            ",",
+            ";",
+            " & ",
+            " feat. ",
+            " feat ",
+            " ft. ",
+            " featuring ",
+            " x ",
+        ] {
+            if lowered.contains(separator) {
+                artists.extend(
+                    lowered
+                        .split(separator)
+                        .map(normalize)
+                        .filter(|a| !a.is_empty()),
+                );
+            }
+        }
+        artists.sort();
+        artists.dedup();
+
+        Query {
+            artists,
+            album: AlbumName::new(album),
+            title: normalize(&strip_qualifiers(title)),
+        }
+    }
+
+    fn artist_similarity(&self, candidate: &str) -> f32 {
+        self.artists
+            .iter()
+            .map(|artist| similarity(artist, candidate))
+            .fold(0.0, f32::max)
+    }
+
+    /// Confidence (0.0 to 1.0) that a local album, credited to any of
+    /// `artists`, is the one being played. The album title weighs more than
+    /// the artist, which is often missing or "Various Artists" in local tags.
+    ///
+    /// Without an album in the query only the artist is compared.
+    pub fn score(&self, artists: &[&str], album: &str) -> f32 {
+        self.score_keys(&AlbumKeys::new(artists, album))
+    }
+
+    /// [`Query::score`] against names normalised ahead of time.
+    pub fn score_keys(&self, keys: &AlbumKeys) -> f32 {
+        let artist_score = keys
+            .artists
+            .iter()
+            .map(|artist| self.artist_similarity(artist))
+            .fold(0.0, f32::max);
+        if self.album.full.is_empty() {
+            return artist_score;
+        }
+
+        let album_score = self.album.similarity(&keys.album);
+        0.6 * album_score + 0.4 * artist_score
+    }
+
+    /// True when `title` is the track being played.
+    pub fn title_matches(&self, title: &str) -> bool {
+        !self.title.is_empty()
+            && similarity(&self.title, &normalize(&strip_qualifiers(title))) >= 0.9
+    }
+
+    /// Finding the playing track inside the candidate album closes half the
+    /// remaining gap to full confidence.
+    pub fn with_title_bonus(score: f32) -> f32 {
+        score + (1.0 - score) * 0.5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filesystem_illegal_characters_become_separators() {
+        assert_eq!(sanitize("AC/DC"), "AC DC");
+        assert_eq!(normalize("AC/DC"), normalize("AC_DC"));
+        assert_eq!(normalize("AC/DC"), "ac dc");
+        assert_eq!(normalize("Live: 1999"), "live 1999");
+        assert_eq!(normalize("Live: 1999"), normalize("Live - 1999"));
+    }
+
+    #[test]
+    fn normalize_folds_case_ampersands_and_leading_the() {
+        assert_eq!(normalize("The Beatles"), normalize("BEATLES"));
+        assert_eq!(normalize("Simon & Garfunkel"), "simon and garfunkel");
+        assert_eq!(normalize("Straße"), normalize("STRASSE"));
+        // A lone "The" is a name, not an article.
+        assert_eq!(normalize("The"), "the");
+    }
+
+    #[test]
+    fn nfc_and_nfd_names_compare_equal() {
+        let nfc = "Beyonc\u{e9}";
+        let nfd = "Beyonce\u{301}";
+        assert_ne!(nfc, nfd);
+        assert_eq!(normalize(nfc), normalize(nfd));
+        assert_eq!(normalize(nfd), "beyonc\u{e9}");
+    }
+
+    #[test]
+    fn strips_edition_qualifiers_only() {
+        assert_eq!(
+            strip_qualifiers("Abbey Road (Remastered 2011)"),
+            "Abbey Road"
+        );
+        assert_eq!(strip_qualifiers("Rumours [Deluxe Edition]"), "Rumours");
+        assert_eq!(strip_qualifiers("Help! - 2009 Remaster"), "Help!");
+        assert_eq!(strip_qualifiers("Live (Tokyo)"), "Live (Tokyo)");
+        assert_eq!(strip_qualifiers("Live: 1999"), "Live: 1999");
+        assert_eq!(strip_qualifiers("Unclosed (Remaster"), "Unclosed (Remaster");
+    }
+
+    #[test]
+    fn remastered_release_matches_local_folder() {
+        let query = Query::new("AC/DC", "Back In Black (Remastered)", "Hells Bells");
+        let score = query.score(&["AC_DC"], "Back in Black");
+        assert!(score >= MIN_CONFIDENCE, "score {}", score);
+        assert!(score < 1.0, "score {}", score);
+
+        let exact = query.score(&["AC/DC"], "Back In Black (Remastered)");
+        assert!((exact - 1.0).abs() < f32::EPSILON, "score {}", exact);
+    }
+
+    #[test]
+    fn different_album_by_the_same_artist_is_not_trusted() {
+        let query = Query::new("AC/DC", "Back In Black", "");
+        let score = query.score(&["AC/DC"], "Highway to Hell");
+        assert!(score < MIN_CONFIDENCE, "score {}", score);
+    }
+
+    #[test]
+    fn any_credited_artist_can_match() {
+        let query = Query::new(
+            "Daft Punk feat. Pharrell Williams",
+            "Random Access Memories",
+            "",
+        );
+        let score = query.score(&["Pharrell Williams"], "Random Access Memories");
+        assert!((score - 1.0).abs() < f32::EPSILON, "score {}", score);
+
+        let various = query.score(&["Various Artists"], "Random Access Memories");
+        assert!(various < score);
+    }
+
+    #[test]
+    fn without_an_album_only_the_artist_counts() {
+        let query = Query::new("The Beatles", "", "");
+        assert!((query.score(&["Beatles"], "Anything") - 1.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn precomputed_keys_score_like_raw_names() {
+        let query = Query::new("Prince", "1999 (Deluxe)", "");
+        let keys = AlbumKeys::new(&["Prince", "prince"], "1999");
+        assert_eq!(query.score_keys(&keys), query.score(&["Prince"], "1999"));
+    }
+
+    #[test]
+    fn titles_match_without_qualifiers() {
+        let query = Query::new("Queen", "", "Bohemian Rhapsody - Remastered 2011");
+        assert!(query.title_matches("BOHEMIAN RHAPSODY"));
+        assert!(!query.title_matches("Somebody to Love"));
+        assert!(!Query::new("Queen", "", "").title_matches(""));
+    }
+
+    #[test]
+    fn title_bonus_closes_half_the_gap() {
+        assert!((Query::with_title_bonus(0.5) - 0.75).abs() < f32::EPSILON);
+        assert!((Query::with_title_bonus(1.0) - 1.0).abs() < f32::EPSILON);
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use walkdir::WalkDir;
+
+use super::index::{IndexedTrack, LibraryIndex};
+use crate::{artwork, tags};
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif", "bmp"];
+
+/// Conventional cover file names, best first. Any other image in an album
+/// folder is still used, but only when none of these exist.
+const ART_NAMES: &[&str] = &["cover", "folder", "front", "album", "albumart"];
+
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Lower is better; `None` for files that are not images.
+pub fn art_rank(path: &Path) -> Option<usize> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    if !IMAGE_EXTENSIONS.contains(&ext.as_str()) {
+        return None;
+    }
+
+    let stem = path.file_stem()?.to_str()?.to_lowercase();
+    if let Some(rank) = ART_NAMES.iter().position(|name| *name == stem) {
+        return Some(rank);
+    }
+    // Windows Media Player's AlbumArt_{GUID}_Large.jpg
+    if stem.starts_with("albumart") {
+        return Some(ART_NAMES.len());
+    }
+    Some(ART_NAMES.len() + 1)
+}
+
+fn is_hidden(entry: &walkdir::DirEntry) -> bool {
+    entry.depth() > 0
+        && entry
+            .file_name()
+            .to_str()
+            .map(|name| name.starts_with('.'))
+            .unwrap_or(false)
+}
+
+/// True if any component of `path` below `root` is hidden, matching what
+/// [`walk`] skips.
+pub fn is_hidden_path(root: &Path, path: &Path) -> bool {
+    path.strip_prefix(root)
+        .map(|rel| {
+            rel.components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+        })
+        .unwrap_or(false)
+}
+
+/// Every visible file under `dir`, following symlinks.
+pub fn walk(dir: &Path) -> impl Iterator<Item = walkdir::DirEntry> {
+    WalkDir::new(dir)
+        .follow_links(true)
+        .into_iter()
+        .filter_entry(|e| !is_hidden(e))
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+}
+
+/// The best cover image directly inside `dir`, if any.
+pub fn best_art_in(dir: &Path) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter_map(|path| art_rank(&path).map(|rank| (rank, path)))
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, path)| path)
+}
+
+fn dir_name(path: Option<&Path>) -> Option<String> {
+    path?.file_name()?.to_str().map(str::to_string)
+}
+
+/// Reads one audio file into an index entry. Missing tags fall back to the
+/// usual `Artist/Album/Track.ext` folder layout.
+pub fn read_track(root: &Path, path: &Path) -> Option<IndexedTrack> {
+    path.to_str()?; // the index is JSON, so paths must be valid UTF-8
+
+    let metadata = fs::metadata(path).ok()?;
+    let mut tags = tags::read_tags(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read tags from {}: {}", path.display(), e);
+        tags::TrackTags::default()
+    });
+
+    let embedded_art = tags.picture.take().and_then(|picture| {
+        artwork::store_picture(&picture)
+            .map_err(|e| {
+                eprintln!(
+                    "Failed to cache embedded art from {}: {}",
+                    path.display(),
+                    e
+                )
+            })
+            .ok()
+    });
+
+    let album_dir = path.parent();
+    let artist_dir = album_dir.and_then(Path::parent).filter(|dir| *dir != root);
+
+    Some(IndexedTrack {
+        path: path.to_path_buf(),
+        root: root.to_path_buf(),
+        title: tags
+            .title
+            .or_else(|| path.file_stem()?.to_str().map(str::to_string))
+            .unwrap_or_else(|| "Unknown Track".to_string()),
+        artist: tags
+            .artist
+            .clone()
+            .or_else(|| tags.album_artist.clone())
+            .or_else(|| dir_name(artist_dir))
+            .unwrap_or_else(|| "Unknown Artist".to_string()),
+        album: tags
+            .album
+            .or_else(|| dir_name(album_dir.filter(|dir| *dir != root)))
+            .unwrap_or_else(|| "Unknown Album".to_string()),
+        album_artist: tags.album_artist,
+        track_number: tags.track_number,
+        disc_number: tags.disc_number,
+        duration_ms: tags.duration_ms,
+        embedded_art,
+        modified: metadata.modified().map(unix_secs).unwrap_or(0),
+        size: metadata.len(),
+    })
+}
+
+/// Walks every root and builds a fresh index, reusing entries from
+/// `previous` for files whose size and modification time are unchanged.
+/// Roots that are currently unavailable keep their previous entries.
+pub fn scan(
+    roots: &[PathBuf],
+    previous: &LibraryIndex,
+    mut progress: impl FnMut(usize),
+) -> LibraryIndex {
+    let mut tracks = HashMap::new();
+    let mut best_art: HashMap<PathBuf, (usize, PathBuf)> = HashMap::new();
+    let mut scanned = 0usize;
+
+    for root in roots {
+        if !root.is_dir() {
+            println!(
+                "Library root unavailable, keeping indexed entries: {}",
+                root.display()
+            );
+            for track in previous.tracks.values().filter(|t| &t.root == root) {
+                tracks.insert(track.path.clone(), track.clone());
+            }
+            for (dir, image) in previous
+                .folder_art
+                .iter()
+                .filter(|(dir, _)| dir.starts_with(root))
+            {
+                best_art.insert(dir.clone(), (0, image.clone()));
+            }
+            continue;
+        }
+
+        for entry in walk(root) {
+            let path = entry.path();
+
+            if let Some(rank) = art_rank(path) {
+                if let Some(dir) = path.parent() {
+                    let best = best_art
+                        .entry(dir.to_path_buf())
+                        .or_insert_with(|| (rank, path.to_path_buf()));
+                    if rank < best.0 {
+                        *best = (rank, path.to_path_buf());
+                    }
+                }
+                continue;
+            }
+
+            if !tags::is_audio_file(path) {
+                continue;
+            }
+
+            let unchanged = entry.metadata().ok().and_then(|metadata| {
+                let modified = metadata.modified().map(unix_secs).unwrap_or(0);
+                previous
+                    .tracks
+                    .get(path)
+                    .filter(|t| {
+                        t.modified == modified && t.size == metadata.len() && &t.root == root
+                    })
+                    // Re-read files whose cached cover was cleared, so the
+                    // index never points at missing art.
+                    .filter(|t| t.embedded_art.as_ref().is_none_or(|art| art.is_file()))
+                    .cloned()
+            });
+
+            if let Some(track) = unchanged.or_else(|| read_track(root, path)) {
+                tracks.insert(track.path.clone(), track);
+            }
+
+            scanned += 1;
+            if scanned.is_multiple_of(250) {
+                progress(scanned);
+            }
+        }
+    }
+
+    progress(scanned);
+
+    LibraryIndex::new(
+        tracks,
+        best_art
+            .into_iter()
+            .map(|(dir, (_, image))| (dir, image))
+            .collect(),
+        Some(unix_secs(SystemTime::now())),
+    )
+}
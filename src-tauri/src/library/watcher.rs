@@ -0,0 +1,292 @@
+//! Keeps the index in step with the library roots between full scans.
+//!
+//! Change notifications are debounced and applied as incremental updates. A
+//! rename arrives as its old and new paths, so every path is simply
+//! re-checked: whatever exists is (re)indexed and whatever is gone is
+//! dropped. Roots are re-checked periodically so unplugged drives are
+//! unwatched without losing their entries, and rescanned when they return.
+
+use notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, mpsc, Arc},
+    time::{Duration, Instant},
+};
+use tauri::{AppHandle, Emitter};
+
+use super::{effective_roots, index::IndexedTrack, scanner, spawn_scan, Library, LibraryStatus};
+use crate::{config::SharedConfig, tags};
+
+/// Editors and rippers write files in several steps; waiting for a quiet
+/// period turns a burst into one update.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How often roots are checked for being mounted, unmounted or reconfigured.
+const ROOT_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Incremental updates are written to disk at most this often; the index
+/// is rewritten whole, and the startup scan catches anything lost on exit.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize)]
+struct LibraryUpdate {
+    added: usize,
+    updated: usize,
+    removed: usize,
+    status: LibraryStatus,
+}
+
+enum Change {
+    Upsert(IndexedTrack),
+    Remove(PathBuf),
+    FolderArt(PathBuf, Option<PathBuf>),
+}
+
+pub fn spawn_watcher(app_handle: AppHandle, library: Arc<Library>, config: SharedConfig) {
+    let spawned = std::thread::Builder::new()
+        .name("library-watcher".to_string())
+        .spawn(move || run(app_handle, library, config));
+
+    if let Err(e) = spawned {
+        eprintln!("Failed to start library watcher: {}", e);
+    }
+}
+
+fn run(app_handle: AppHandle, library: Arc<Library>, config: SharedConfig) {
+    let (tx, rx) = mpsc::channel::<DebounceEventResult>();
+    let mut debouncer = match new_debouncer(DEBOUNCE, tx) {
+        Ok(debouncer) => debouncer,
+        Err(e) => {
+            eprintln!("Failed to create library watcher: {}", e);
+            return;
+        }
+    };
+
+    let mut watched: Vec<PathBuf> = Vec::new();
+    let mut first_sync = true;
+    let mut last_save = Instant::now();
+
+    loop {
+        let available: Vec<PathBuf> = effective_roots(&config)
+            .into_iter()
+            .filter(|root| root.is_dir())
+            .collect();
+
+        for root in watched.iter().filter(|r| !available.contains(r)) {
+            println!("No longer watching library root: {}", root.display());
+            // Fails if the root vanished, which already dropped the watch.
+            let _ = debouncer.watcher().unwatch(root);
+        }
+        watched.retain(|r| available.contains(r));
+
+        let mut appeared = false;
+        for root in available {
+            if watched.contains(&root) {
+                continue;
+            }
+            match debouncer.watcher().watch(&root, RecursiveMode::Recursive) {
+                Ok(()) => {
+                    println!("Watching library root: {}", root.display());
+                    watched.push(root);
+                    appeared = true;
+                }
+                Err(e) => eprintln!("Failed to watch {}: {}", root.display(), e),
+            }
+        }
+
+        // The startup scan already covers roots present at launch; anything
+        // that shows up later (a remounted drive) may have changed meanwhile.
+        if appeared && !first_sync {
+            spawn_scan(app_handle.clone(), library.clone(), config.clone());
+        }
+        first_sync = false;
+
+        match rx.recv_timeout(ROOT_CHECK_INTERVAL) {
+            Ok(Ok(events)) => {
+                let paths: BTreeSet<PathBuf> = events.into_iter().map(|e| e.path).collect();
+                apply(&app_handle, &library, &config, paths);
+            }
+            Ok(Err(e)) => eprintln!("Library watcher error: {}", e),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        if last_save.elapsed() >= SAVE_INTERVAL {
+            library.save_if_dirty();
+            last_save = Instant::now();
+        }
+    }
+    library.save_if_dirty();
+}
+
+fn is_unchanged(existing: Option<&IndexedTrack>, path: &Path) -> bool {
+    let (Some(track), Ok(metadata)) = (existing, fs::metadata(path)) else {
+        return false;
+    };
+    let modified = metadata.modified().map(scanner::unix_secs).unwrap_or(0);
+    track.modified == modified && track.size == metadata.len()
+}
+
+/// Works out the index changes for one changed path. Only reads the index.
+fn changes_for(library: &Library, root: &Path, path: &Path, changes: &mut Vec<Change>) {
+    if path.is_dir() {
+        // A folder moved or copied in; nothing inside gets its own event.
+        for entry in scanner::walk(path) {
+            changes_for(library, root, entry.path(), changes);
+        }
+        changes.push(Change::FolderArt(
+            path.to_path_buf(),
+            scanner::best_art_in(path),
+        ));
+        return;
+    }
+
+    if scanner::art_rank(path).is_some() {
+        if let Some(dir) = path.parent() {
+            changes.push(Change::FolderArt(
+                dir.to_path_buf(),
+                scanner::best_art_in(dir),
+            ));
+        }
+        return;
+    }
+
+    if !path.exists() {
+        changes.push(Change::Remove(path.to_path_buf()));
+        return;
+    }
+
+    if !tags::is_audio_file(path) || is_unchanged(library.index().tracks.get(path), path) {
+        return;
+    }
+    if let Some(track) = scanner::read_track(root, path) {
+        changes.push(Change::Upsert(track));
+    }
+}
+
+/// `path` with symlinks resolved. A deleted file cannot be canonicalized, so
+/// its parent is resolved instead.
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .ok()
+        .or_else(|| Some(path.parent()?.canonicalize().ok()?.join(path.file_name()?)))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// The root an event path belongs to, and the path rewritten under the root
+/// as configured. The scanner names index entries that way, while the OS
+/// may report events through the resolved target of a symlinked root.
+fn locate(roots: &[(PathBuf, PathBuf)], path: &Path) -> Option<(PathBuf, PathBuf)> {
+    if let Some((root, _)) = roots.iter().find(|(root, _)| path.starts_with(root)) {
+        return Some((root.clone(), path.to_path_buf()));
+    }
+    let resolved = canonical(path);
+    roots.iter().find_map(|(root, canonical_root)| {
+        let relative = resolved.strip_prefix(canonical_root).ok()?;
+        Some((root.clone(), root.join(relative)))
+    })
+}
+
+pub(super) fn apply(
+    app_handle: &AppHandle,
+    library: &Arc<Library>,
+    config: &SharedConfig,
+    paths: BTreeSet<PathBuf>,
+) {
+    // Changes arriving during a scan are replayed once it commits.
+    let generation = {
+        let mut deferred = library.deferred();
+        if library.scanning.load(Ordering::SeqCst) {
+            deferred.extend(paths);
+            return;
+        }
+        library.scan_generation.load(Ordering::SeqCst)
+    };
+
+    // Worked out without the lock: walking a new folder and reading its tags
+    // can take a while, and scans are started from async commands.
+    let roots: Vec<(PathBuf, PathBuf)> = effective_roots(config)
+        .into_iter()
+        .map(|root| {
+            let resolved = canonical(&root);
+            (root, resolved)
+        })
+        .collect();
+    let located: Vec<(PathBuf, PathBuf)> = paths
+        .iter()
+        .filter_map(|path| locate(&roots, path))
+        .collect();
+    let located_paths: BTreeSet<&PathBuf> = located.iter().map(|(_, path)| path).collect();
+    let mut changes = Vec::new();
+
+    for (root, path) in &located {
+        // An unmounted root reports its whole tree as deleted; keep the
+        // entries until the drive comes back.
+        if !root.is_dir() || scanner::is_hidden_path(root, path) {
+            continue;
+        }
+        // Files inside a new folder are covered by walking the folder.
+        if located_paths
+            .iter()
+            .any(|other| *other != path && path.starts_with(other) && other.is_dir())
+        {
+            continue;
+        }
+        changes_for(library, root, path, &mut changes);
+    }
+
+    if changes.is_empty() {
+        return;
+    }
+
+    let mut deferred = library.deferred();
+    if library.scanning.load(Ordering::SeqCst) {
+        deferred.extend(paths);
+        return;
+    }
+    if library.scan_generation.load(Ordering::SeqCst) != generation {
+        // A scan committed meanwhile and may hold newer state than these
+        // changes; work them out again against its index.
+        drop(deferred);
+        return apply(app_handle, library, config, paths);
+    }
+
+    let (mut added, mut updated, mut removed) = (0, 0, 0);
+    {
+        let mut index = library.index_mut();
+        for change in changes {
+            match change {
+                Change::Upsert(track) => {
+                    if index.tracks.insert(track.path.clone(), track).is_some() {
+                        updated += 1;
+                    } else {
+                        added += 1;
+                    }
+                }
+                Change::Remove(path) => removed += index.remove_under(&path),
+                Change::FolderArt(dir, image) => index.set_folder_art(dir, image),
+            }
+        }
+        index.rebuild_albums();
+    }
+    library.index_dirty.store(true, Ordering::SeqCst);
+    drop(deferred);
+
+    println!(
+        "Library updated: {} added, {} updated, {} removed",
+        added, updated, removed
+    );
+    let update = LibraryUpdate {
+        added,
+        updated,
+        removed,
+        status: library.status(),
+    };
+    if let Err(e) = app_handle.emit("library-updated", update) {
+        eprintln!("Failed to emit library-updated: {}", e);
+    }
+}
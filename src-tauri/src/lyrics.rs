@@ -0,0 +1,240 @@
+//! Lyrics for the current track. Local sources are an `.lrc` file next to
+//! the audio file and lyrics embedded in its tags; remote providers cover
+//! everything else.
+
+mod lrc;
+pub mod providers;
+mod sync;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{config::SharedConfig, tags};
+
+pub use providers::{LyricsProviders, LyricsQuery};
+pub use sync::{spawn_sync_engine, CurrentLyrics, LyricsEngine, LyricsPosition};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LyricWord {
+    pub time_ms: u64,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LyricLine {
+    /// Zero for every line of unsynced lyrics.
+    pub time_ms: u64,
+    pub text: String,
+    /// Word-level timing from enhanced LRC; empty when the source has none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub words: Vec<LyricWord>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LyricsSource {
+    /// An `.lrc` file beside the track.
+    Sidecar,
+    /// ID3 USLT/SYLT, Vorbis LYRICS or MP4 `©lyr`.
+    Embedded,
+    /// Fetched from LRCLIB.
+    Lrclib,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lyrics {
+    pub source: LyricsSource,
+    pub synced: bool,
+    /// The `[offset:]` from the LRC, already applied to the line times.
+    pub offset_ms: i64,
+    pub lines: Vec<LyricLine>,
+}
+
+impl Lyrics {
+    /// Parses LRC, falling back to plain text when there are no timestamps.
+    fn from_text(text: &str, source: LyricsSource) -> Option<Lyrics> {
+        let parsed = lrc::parse(text);
+        if !parsed.lines.is_empty() {
+            return Some(Lyrics {
+                source,
+                synced: true,
+                offset_ms: parsed.offset_ms,
+                lines: parsed.lines,
+            });
+        }
+
+        let lines: Vec<LyricLine> = text
+            .lines()
+            .map(|line| LyricLine {
+                time_ms: 0,
+                text: line.trim().to_string(),
+                words: Vec::new(),
+            })
+            .collect();
+        if lines.iter().all(|line| line.text.is_empty()) {
+            return None;
+        }
+
+        Some(Lyrics {
+            source,
+            synced: false,
+            offset_ms: 0,
+            lines,
+        })
+    }
+}
+
+/// LRC files come in whatever encoding the tool that wrote them used:
+/// UTF-8 (with or without BOM), UTF-16 with BOM, or a legacy code page, which
+/// is read as Latin-1.
+fn decode_file(bytes: &[u8]) -> String {
+    match bytes {
+        [0xff, 0xfe, ..] | [0xfe, 0xff, ..] => tags::decode_text(1, bytes),
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        _ => match std::str::from_utf8(bytes) {
+            Ok(text) => text.to_string(),
+            Err(_) => tags::decode_text(0, bytes),
+        },
+    }
+}
+
+/// `Song.lrc` beside `Song.flac`, matching the extension case-insensitively.
+pub fn find_sidecar(track: &Path) -> Option<PathBuf> {
+    let stem = track.file_stem()?;
+    let dir = track.parent()?;
+
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_stem() == Some(stem)
+                && path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .map(|e| e.eq_ignore_ascii_case("lrc"))
+                    .unwrap_or(false)
+        })
+}
+
+/// Lyrics from the track's sidecar file or tags. Synced lyrics from any
+/// source beat unsynced ones; otherwise the sidecar wins.
+pub fn read_local(track: &Path) -> Option<Lyrics> {
+    let mut found = Vec::new();
+
+    if let Some(sidecar) = find_sidecar(track) {
+        match fs::read(&sidecar) {
+            Ok(bytes) => found.extend(Lyrics::from_text(
+                &decode_file(&bytes),
+                LyricsSource::Sidecar,
+            )),
+            Err(e) => eprintln!("Failed to read {}: {}", sidecar.display(), e),
+        }
+    }
+
+    if let Ok(track_tags) = tags::read_tags(track) {
+        if !track_tags.synced_lyrics.is_empty() {
+            let mut lines: Vec<LyricLine> = track_tags
+                .synced_lyrics
+                .into_iter()
+                .map(|(time_ms, text)| LyricLine {
+                    time_ms,
+                    text: text.trim().to_string(),
+                    words: Vec::new(),
+                })
+                .collect();
+            lines.sort_by_key(|line| line.time_ms);
+            found.push(Lyrics {
+                source: LyricsSource::Embedded,
+                synced: true,
+                offset_ms: 0,
+                lines,
+            });
+        }
+        if let Some(text) = track_tags.lyrics {
+            found.extend(Lyrics::from_text(&text, LyricsSource::Embedded));
+        }
+    }
+
+    let synced = found.iter().position(|lyrics| lyrics.synced);
+    match synced {
+        Some(index) => Some(found.swap_remove(index)),
+        None => found.into_iter().next(),
+    }
+}
+
+/// Lyrics for a track from the configured providers.
+#[tauri::command]
+pub async fn get_lyrics(
+    artist: String,
+    title: String,
+    album: Option<String>,
+    duration_ms: Option<u64>,
+    providers: tauri::State<'_, Arc<LyricsProviders>>,
+) -> Result<Option<Lyrics>, String> {
+    let query = LyricsQuery {
+        artist,
+        title,
+        album: album.unwrap_or_default(),
+        duration_ms,
+    };
+    Ok(providers.lookup(&query).await)
+}
+
+/// Sets the order lyrics providers are tried in; providers left out are
+/// disabled.
+#[tauri::command]
+pub fn set_lyrics_providers(
+    providers: Vec<String>,
+    config: tauri::State<'_, SharedConfig>,
+) -> Result<Vec<String>, String> {
+    if let Some(unknown) = providers
+        .iter()
+        .find(|name| !providers::KNOWN_PROVIDERS.contains(&name.as_str()))
+    {
+        return Err(format!("Unknown lyrics provider: {}", unknown));
+    }
+
+    let mut config = config.lock().map_err(|e| e.to_string())?;
+    config.lyrics.providers = providers.clone();
+    config.save()?;
+    Ok(providers)
+}
+
+/// Lyrics for the playing track as loaded by the sync engine, with the
+/// user's offset for it.
+#[tauri::command]
+pub fn get_current_lyrics(engine: tauri::State<'_, Arc<LyricsEngine>>) -> Option<CurrentLyrics> {
+    engine.current()
+}
+
+/// The current line as of the last `lyrics-line` event, so a window opened
+/// mid-track can highlight it before the next one.
+#[tauri::command]
+pub fn get_lyrics_position(engine: tauri::State<'_, Arc<LyricsEngine>>) -> Option<LyricsPosition> {
+    engine.position()
+}
+
+/// Sets the per-track lyrics offset in milliseconds; positive shows lyrics
+/// earlier. Applies to the playing track unless `uri` is given.
+#[tauri::command]
+pub fn set_lyrics_offset(
+    offset_ms: i64,
+    uri: Option<String>,
+    engine: tauri::State<'_, Arc<LyricsEngine>>,
+) -> Result<i64, String> {
+    let uri = uri
+        .or_else(|| engine.current().map(|current| current.uri))
+        .ok_or("Nothing is playing")?;
+    engine.set_offset(&uri, offset_ms)?;
+    Ok(offset_ms)
+}
+
+#[tauri::command]
+pub fn get_lyrics_offset(uri: String, engine: tauri::State<'_, Arc<LyricsEngine>>) -> i64 {
+    engine.offset_for(&uri)
+}
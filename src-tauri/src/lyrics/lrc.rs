@@ -0,0 +1,240 @@
+//! Parser for LRC lyrics, including the common extensions: several
+//! timestamps on one line (`[00:12.00][01:30.50]Chorus`), `[offset:±ms]`,
+//! and enhanced LRC word timing (`[00:12.00]<00:12.00>Hel<00:12.40>lo`).
+
+use super::{LyricLine, LyricWord};
+
+#[derive(Debug, Default)]
+pub struct ParsedLrc {
+    pub lines: Vec<LyricLine>,
+    /// From `[offset:…]`, already applied to `lines`. Positive values show
+    /// lyrics earlier.
+    pub offset_ms: i64,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+/// Parses `mm:ss`, `mm:ss.x`, `mm:ss.xx`, `mm:ss.xxx` or `mm:ss:xx`.
+fn parse_timestamp(tag: &str) -> Option<u64> {
+    let (minutes, rest) = tag.split_once(':')?;
+    let minutes: u64 = minutes.trim().parse().ok()?;
+
+    let (seconds, fraction) = match rest.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, Some(fraction)),
+        None => (rest, None),
+    };
+    let seconds: u64 = seconds.trim().parse().ok()?;
+    if seconds >= 60 {
+        return None;
+    }
+
+    let millis = match fraction.map(str::trim) {
+        None | Some("") => 0,
+        Some(digits) if digits.len() <= 3 && digits.bytes().all(|b| b.is_ascii_digit()) => {
+            // ".5" is half a second, ".05" five hundredths.
+            let value: u64 = digits.parse().ok()?;
+            value * 10u64.pow(3 - digits.len() as u32)
+        }
+        _ => return None,
+    };
+
+    Some(minutes * 60_000 + seconds * 1000 + millis)
+}
+
+/// Splits enhanced-LRC text into timed words. Returns the plain text and the
+/// words, which are empty when the line has no word timing.
+fn parse_words(text: &str) -> (String, Vec<LyricWord>) {
+    if !text.contains('<') {
+        return (text.trim().to_string(), Vec::new());
+    }
+
+    let mut words: Vec<LyricWord> = Vec::new();
+    let mut plain = String::new();
+    let mut rest = text;
+
+    // Text before the first marker has no timing of its own.
+    if let Some(start) = rest.find('<') {
+        plain.push_str(&rest[..start]);
+        rest = &rest[start..];
+    }
+
+    while let Some(inner) = rest.strip_prefix('<') {
+        let Some(end) = inner.find('>') else {
+            plain.push_str(rest);
+            break;
+        };
+        let Some(time_ms) = parse_timestamp(&inner[..end]) else {
+            // Not a timestamp, e.g. a literal "<3"; keep it and the text up
+            // to the next marker, as part of the word it follows.
+            let next = 1 + inner.find('<').unwrap_or(inner.len());
+            let literal = &rest[..next];
+            plain.push_str(literal);
+            if let Some(word) = words.last_mut() {
+                word.text.push_str(literal);
+            }
+            rest = &rest[next..];
+            continue;
+        };
+
+        let after = &inner[end + 1..];
+        let next = after.find('<').unwrap_or(after.len());
+        let word = &after[..next];
+        plain.push_str(word);
+        // A trailing marker with no text only marks when the line ends.
+        if !word.trim().is_empty() {
+            words.push(LyricWord {
+                time_ms,
+                text: word.to_string(),
+            });
+        }
+        rest = &after[next..];
+    }
+
+    (plain.trim().to_string(), words)
+}
+
+pub fn parse(input: &str) -> ParsedLrc {
+    let mut parsed = ParsedLrc::default();
+
+    for raw in input.lines() {
+        let mut rest = raw.trim_start_matches('\u{feff}').trim();
+        let mut stamps = Vec::new();
+
+        while let Some(inner) = rest.strip_prefix('[') {
+            let Some(end) = inner.find(']') else {
+                break;
+            };
+            let tag = &inner[..end];
+            rest = &inner[end + 1..];
+
+            if let Some(time_ms) = parse_timestamp(tag) {
+                stamps.push(time_ms);
+                continue;
+            }
+
+            let Some((key, value)) = tag.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "offset" => parsed.offset_ms = value.trim_start_matches('+').parse().unwrap_or(0),
+                "ti" if !value.is_empty() => parsed.title = Some(value.to_string()),
+                "ar" if !value.is_empty() => parsed.artist = Some(value.to_string()),
+                "al" if !value.is_empty() => parsed.album = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        if stamps.is_empty() {
+            continue;
+        }
+
+        let (text, words) = parse_words(rest);
+        let first = stamps[0];
+        for stamp in stamps {
+            // Word times are absolute; shift them along with repeated lines.
+            let shifted = words
+                .iter()
+                .map(|w| LyricWord {
+                    time_ms: (w.time_ms + stamp).saturating_sub(first),
+                    text: w.text.clone(),
+                })
+                .collect();
+            parsed.lines.push(LyricLine {
+                time_ms: stamp,
+                text: text.clone(),
+                words: shifted,
+            });
+        }
+    }
+
+    if parsed.offset_ms != 0 {
+        let shift = |time: u64| (time as i64 - parsed.offset_ms).max(0) as u64;
+        for line in &mut parsed.lines {
+            line.time_ms = shift(line.time_ms);
+            for word in &mut line.words {
+                word.time_ms = shift(word.time_ms);
+            }
+        }
+    }
+
+    parsed.lines.sort_by_key(|line| line.time_ms);
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word_texts(words: &[LyricWord]) -> Vec<(u64, &str)> {
+        words.iter().map(|w| (w.time_ms, w.text.as_str())).collect()
+    }
+
+    #[test]
+    fn splits_enhanced_lrc_words() {
+        let (plain, words) = parse_words("<00:12.00>Hel<00:12.40>lo <00:13.05>world<00:14.00>");
+        assert_eq!(plain, "Hello world");
+        assert_eq!(
+            word_texts(&words),
+            [(12_000, "Hel"), (12_400, "lo "), (13_050, "world")]
+        );
+    }
+
+    #[test]
+    fn keeps_text_before_the_first_marker() {
+        let (plain, words) = parse_words("Oh <00:01.00>yeah");
+        assert_eq!(plain, "Oh yeah");
+        assert_eq!(word_texts(&words), [(1_000, "yeah")]);
+    }
+
+    #[test]
+    fn literal_angle_bracket_keeps_the_rest_of_the_line() {
+        let (plain, words) = parse_words("<00:01.00>I <3 you <00:02.00>so much");
+        assert_eq!(plain, "I <3 you so much");
+        assert_eq!(
+            word_texts(&words),
+            [(1_000, "I <3 you "), (2_000, "so much")]
+        );
+
+        let (plain, words) = parse_words("a <b> c <00:03.00>d");
+        assert_eq!(plain, "a <b> c d");
+        assert_eq!(word_texts(&words), [(3_000, "d")]);
+    }
+
+    #[test]
+    fn unterminated_marker_is_text() {
+        let (plain, words) = parse_words("<00:01.00>up <00:02");
+        assert_eq!(plain, "up <00:02");
+        assert_eq!(word_texts(&words), [(1_000, "up ")]);
+    }
+
+    #[test]
+    fn plain_lines_have_no_words() {
+        let (plain, words) = parse_words("  just text  ");
+        assert_eq!(plain, "just text");
+        assert!(words.is_empty());
+    }
+
+    #[test]
+    fn repeated_line_shifts_word_times() {
+        let parsed = parse("[00:10.00][01:00.00]<00:10.00>la <00:10.50>la");
+        assert_eq!(parsed.lines.len(), 2);
+        assert_eq!(parsed.lines[0].text, "la la");
+        assert_eq!(
+            word_texts(&parsed.lines[1].words),
+            [(60_000, "la "), (60_500, "la")]
+        );
+    }
+
+    #[test]
+    fn offset_applies_to_word_times() {
+        let parsed = parse("[offset:+500]\n[00:10.00]<00:10.00>hi <00:11.00>there");
+        assert_eq!(parsed.offset_ms, 500);
+        assert_eq!(parsed.lines[0].time_ms, 9_500);
+        assert_eq!(
+            word_texts(&parsed.lines[0].words),
+            [(9_500, "hi "), (10_500, "there")]
+        );
+    }
+}
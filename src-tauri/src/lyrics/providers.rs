@@ -0,0 +1,389 @@
+//! Where lyrics come from. Providers are tried in the order set in the
+//! config; the first synced lyrics win, otherwise the first plain ones.
+//! Remote results, including "not found", are cached on disk so each track
+//! is only fetched once.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{read_local, Lyrics, LyricsSource};
+use crate::{
+    config::{self, SharedConfig},
+    library::Library,
+};
+
+pub const LOCAL: &str = "local";
+pub const LRCLIB: &str = "lrclib";
+pub const KNOWN_PROVIDERS: &[&str] = &[LOCAL, LRCLIB];
+
+/// Missing lyrics get added upstream over time, so "not found" is only
+/// trusted for a while.
+const NEGATIVE_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How far, in seconds, a search hit's duration may be from the track's
+/// before it is considered a different recording.
+const DURATION_TOLERANCE_SECS: f64 = 3.0;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct LyricsQuery {
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+    pub duration_ms: Option<u64>,
+}
+
+impl LyricsQuery {
+    /// `scope` tells apart providers of the same name that answer
+    /// differently, e.g. two LRCLIB mirrors. The duration is in whole
+    /// seconds, which is as precise as lookups get.
+    fn cache_key(&self, scope: &str) -> String {
+        let key = format!(
+            "{}\0{}\0{}\0{}\0{}",
+            scope,
+            self.artist.trim().to_lowercase(),
+            self.title.trim().to_lowercase(),
+            self.album.trim().to_lowercase(),
+            self.duration_ms.map(|ms| (ms + 500) / 1000).unwrap_or(0)
+        );
+        format!("{:x}", Sha256::digest(key.as_bytes()))
+    }
+}
+
+#[async_trait]
+pub trait LyricsProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Whether results should go through the disk cache. Local lookups are
+    /// cheap and must notice edited files, so they are not cached.
+    fn cacheable(&self) -> bool {
+        true
+    }
+
+    /// Part of the cache key: whatever besides the query changes the answer.
+    fn cache_scope(&self) -> String {
+        String::new()
+    }
+
+    /// `Ok(None)` means the provider has no lyrics for the track; errors are
+    /// for failures worth retrying later.
+    async fn fetch(&self, query: &LyricsQuery) -> Result<Option<Lyrics>, String>;
+}
+
+/// Sidecar `.lrc` files and embedded tags of the matching library track.
+pub struct LocalProvider {
+    library: Arc<Library>,
+}
+
+#[async_trait]
+impl LyricsProvider for LocalProvider {
+    fn name(&self) -> &'static str {
+        LOCAL
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    async fn fetch(&self, query: &LyricsQuery) -> Result<Option<Lyrics>, String> {
+        let track = self
+            .library
+            .index()
+            .find_track(&query.artist, &query.album, &query.title);
+        let Some(track) = track else {
+            return Ok(None);
+        };
+
+        tauri::async_runtime::spawn_blocking(move || read_local(&track.path))
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Response of LRCLIB's `/api/get` and the items of `/api/search`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LrclibRecord {
+    #[serde(default)]
+    instrumental: bool,
+    /// In seconds.
+    duration: Option<f64>,
+    plain_lyrics: Option<String>,
+    synced_lyrics: Option<String>,
+}
+
+impl LrclibRecord {
+    fn into_lyrics(self) -> Option<Lyrics> {
+        if self.instrumental {
+            return None;
+        }
+        let synced = self
+            .synced_lyrics
+            .as_deref()
+            .and_then(|text| Lyrics::from_text(text, LyricsSource::Lrclib))
+            .filter(|lyrics| lyrics.synced);
+        synced.or_else(|| {
+            self.plain_lyrics
+                .as_deref()
+                .and_then(|text| Lyrics::from_text(text, LyricsSource::Lrclib))
+        })
+    }
+}
+
+/// Client for the LRCLIB API, or anything serving the same endpoints.
+pub struct LrclibProvider {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl LrclibProvider {
+    async fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &[(&str, String)],
+    ) -> Result<Option<T>, String> {
+        let url = format!("{}{}", self.base_url.trim_end_matches('/'), path);
+        let response = self
+            .http
+            .get(&url)
+            .query(params)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response.error_for_status().map_err(|e| e.to_string())?;
+        response.json().await.map(Some).map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait]
+impl LyricsProvider for LrclibProvider {
+    fn name(&self) -> &'static str {
+        LRCLIB
+    }
+
+    fn cache_scope(&self) -> String {
+        self.base_url.trim_end_matches('/').to_string()
+    }
+
+    async fn fetch(&self, query: &LyricsQuery) -> Result<Option<Lyrics>, String> {
+        let mut params = vec![
+            ("artist_name", query.artist.clone()),
+            ("track_name", query.title.clone()),
+        ];
+        if !query.album.is_empty() {
+            params.push(("album_name", query.album.clone()));
+        }
+
+        // The exact lookup needs the duration and only matches when every
+        // field agrees, so anything it misses goes on to search.
+        if let Some(duration_ms) = query.duration_ms {
+            params.push(("duration", (duration_ms / 1000).to_string()));
+            let record: Option<LrclibRecord> = self.get_json("/api/get", &params).await?;
+            if let Some(lyrics) = record.and_then(LrclibRecord::into_lyrics) {
+                return Ok(Some(lyrics));
+            }
+        }
+
+        let records: Option<Vec<LrclibRecord>> = self.get_json("/api/search", &params).await?;
+        let mut records = records.unwrap_or_default();
+
+        // With a known duration, only hits close to it are the same
+        // recording; take the closest. Otherwise keep LRCLIB's ranking.
+        if let Some(duration_ms) = query.duration_ms {
+            let target = duration_ms as f64 / 1000.0;
+            let distance = |record: &LrclibRecord| record.duration.map(|d| (d - target).abs());
+            records.retain(|record| distance(record).is_some_and(|d| d <= DURATION_TOLERANCE_SECS));
+            records.sort_by(|a, b| {
+                distance(a)
+                    .unwrap_or(f64::MAX)
+                    .total_cmp(&distance(b).unwrap_or(f64::MAX))
+            });
+        }
+
+        Ok(records.into_iter().find_map(LrclibRecord::into_lyrics))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    fetched_at: u64,
+    lyrics: Option<Lyrics>,
+}
+
+fn cache_dir() -> PathBuf {
+    config::cache_dir().join("lyrics")
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Runs lookups against the configured providers.
+pub struct LyricsProviders {
+    config: SharedConfig,
+    library: Arc<Library>,
+    http: reqwest::Client,
+}
+
+impl LyricsProviders {
+    pub fn new(config: SharedConfig, library: Arc<Library>) -> Self {
+        let http = reqwest::Client::builder()
+            .user_agent(concat!(
+                "spotify-widget/",
+                env!("CARGO_PKG_VERSION"),
+                " (https://github.com/hld19/spotify-widget)"
+            ))
+            .build()
+            .unwrap_or_default();
+
+        LyricsProviders {
+            config,
+            library,
+            http,
+        }
+    }
+
+    /// Providers in configured order. Unknown names are skipped.
+    fn providers(&self) -> Vec<Box<dyn LyricsProvider>> {
+        let settings = self
+            .config
+            .lock()
+            .map(|config| config.lyrics.clone())
+            .unwrap_or_default();
+
+        settings
+            .providers
+            .iter()
+            .filter_map(|name| -> Option<Box<dyn LyricsProvider>> {
+                match name.as_str() {
+                    LOCAL => Some(Box::new(LocalProvider {
+                        library: self.library.clone(),
+                    })),
+                    LRCLIB => Some(Box::new(LrclibProvider {
+                        http: self.http.clone(),
+                        base_url: settings.lrclib_url.clone(),
+                    })),
+                    other => {
+                        eprintln!("Unknown lyrics provider: {}", other);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn read_cache(path: &PathBuf) -> Option<Option<Lyrics>> {
+        let entry: CacheEntry = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+        if entry.lyrics.is_none()
+            && now_secs().saturating_sub(entry.fetched_at) > NEGATIVE_TTL.as_secs()
+        {
+            return None;
+        }
+        Some(entry.lyrics)
+    }
+
+    async fn fetch_cached(
+        provider: &dyn LyricsProvider,
+        query: &LyricsQuery,
+    ) -> Result<Option<Lyrics>, String> {
+        if !provider.cacheable() {
+            return provider.fetch(query).await;
+        }
+
+        let path = cache_dir().join(format!(
+            "{}-{}.json",
+            provider.name(),
+            query.cache_key(&provider.cache_scope())
+        ));
+        if let Some(cached) = Self::read_cache(&path) {
+            return Ok(cached);
+        }
+
+        let lyrics = provider.fetch(query).await?;
+        let entry = CacheEntry {
+            fetched_at: now_secs(),
+            lyrics,
+        };
+        match serde_json::to_vec(&entry) {
+            Ok(contents) => {
+                if let Err(e) = config::write_atomic(&path, &contents) {
+                    eprintln!("Failed to cache lyrics {}: {}", path.display(), e);
+                }
+            }
+            Err(e) => eprintln!("Failed to serialize lyrics: {}", e),
+        }
+        Ok(entry.lyrics)
+    }
+
+    pub async fn lookup(&self, query: &LyricsQuery) -> Option<Lyrics> {
+        let mut fallback = None;
+
+        for provider in self.providers() {
+            match Self::fetch_cached(provider.as_ref(), query).await {
+                Ok(Some(lyrics)) if lyrics.synced => return Some(lyrics),
+                // Keep looking for synced lyrics further down the list.
+                Ok(Some(lyrics)) => {
+                    fallback.get_or_insert(lyrics);
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!(
+                    "Lyrics provider {} failed for {} - {}: {}",
+                    provider.name(),
+                    query.artist,
+                    query.title,
+                    e
+                ),
+            }
+        }
+
+        if fallback.is_none() {
+            println!("No lyrics found: {} - {}", query.artist, query.title);
+        }
+        fallback
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(duration_ms: Option<u64>) -> LyricsQuery {
+        LyricsQuery {
+            artist: "Artist".to_string(),
+            title: "Title".to_string(),
+            album: "Album".to_string(),
+            duration_ms,
+        }
+    }
+
+    #[test]
+    fn cache_key_separates_mirrors_and_recordings() {
+        let key = query(Some(200_000)).cache_key("https://lrclib.net");
+        assert_eq!(key, query(Some(200_200)).cache_key("https://lrclib.net"));
+        assert_ne!(key, query(Some(245_000)).cache_key("https://lrclib.net"));
+        assert_ne!(key, query(None).cache_key("https://lrclib.net"));
+        assert_ne!(key, query(Some(200_000)).cache_key("http://localhost:3000"));
+
+        let shouted = LyricsQuery {
+            artist: " ARTIST ".to_string(),
+            ..query(Some(200_000))
+        };
+        assert_eq!(key, shouted.cache_key("https://lrclib.net"));
+    }
+}
@@ -0,0 +1,581 @@
+//! Follows playback and works out which lyric line (and word) is current.
+//!
+//! The poller only reports progress every second or so, which is too coarse
+//! for word timing. Between polls the position is extrapolated from a
+//! monotonic clock, and each poll re-anchors it. Small backwards corrections
+//! are ignored so the highlighted line never flickers back and forth; larger
+//! jumps are treated as seeks.
+
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    Notify,
+};
+
+use super::{Lyrics, LyricsProviders, LyricsQuery};
+use crate::{
+    config,
+    playback::{PlaybackEvent, PlaybackHub, PlaybackSnapshot},
+};
+
+const OFFSETS_FILE: &str = "lyrics-offsets.json";
+
+/// Reported positions this far behind the extrapolated one are treated as
+/// poll latency rather than a seek.
+const JITTER_TOLERANCE_MS: u64 = 400;
+
+/// Upper bound on how long the engine sleeps while playing, so pauses and
+/// seeks noticed by the poller are reflected promptly.
+const MAX_TICK: Duration = Duration::from_millis(500);
+const IDLE_TICK: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrentLyrics {
+    pub uri: String,
+    pub lyrics: Option<Lyrics>,
+    /// The user's offset for this track; positive shows lyrics earlier.
+    pub offset_ms: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LyricsPosition {
+    pub uri: String,
+    /// `None` before the first line.
+    pub line_index: Option<usize>,
+    /// `None` when the line has no word timing or its first word is still
+    /// ahead.
+    pub word_index: Option<usize>,
+    /// `None` after the last line.
+    pub time_to_next_line_ms: Option<u64>,
+    pub position_ms: u64,
+    pub is_playing: bool,
+}
+
+pub struct LyricsEngine {
+    current: RwLock<Option<CurrentLyrics>>,
+    /// Per-track offsets keyed by track URI, persisted as JSON.
+    offsets: Mutex<HashMap<String, i64>>,
+    offset_changed: Notify,
+    /// Every `lyrics-line` event, for backend subscribers.
+    lines: broadcast::Sender<LyricsPosition>,
+    /// The last `lyrics-line` event.
+    position: RwLock<Option<LyricsPosition>>,
+}
+
+impl LyricsEngine {
+    fn offsets_path() -> PathBuf {
+        config::data_dir().join(OFFSETS_FILE)
+    }
+
+    pub fn load() -> Self {
+        let offsets = fs::read_to_string(Self::offsets_path())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        LyricsEngine {
+            current: RwLock::new(None),
+            offsets: Mutex::new(offsets),
+            offset_changed: Notify::new(),
+            lines: broadcast::channel(16).0,
+            position: RwLock::new(None),
+        }
+    }
+
+    pub fn subscribe_lines(&self) -> broadcast::Receiver<LyricsPosition> {
+        self.lines.subscribe()
+    }
+
+    pub fn current(&self) -> Option<CurrentLyrics> {
+        self.current.read().ok().and_then(|c| c.clone())
+    }
+
+    /// The current line as of the last `lyrics-line` event.
+    pub fn position(&self) -> Option<LyricsPosition> {
+        self.position.read().ok().and_then(|p| p.clone())
+    }
+
+    fn set_current(&self, current: Option<CurrentLyrics>) {
+        if let Ok(mut slot) = self.current.write() {
+            *slot = current;
+        }
+        if let Ok(mut position) = self.position.write() {
+            *position = None;
+        }
+    }
+
+    pub fn offset_for(&self, uri: &str) -> i64 {
+        self.offsets
+            .lock()
+            .ok()
+            .and_then(|offsets| offsets.get(uri).copied())
+            .unwrap_or(0)
+    }
+
+    /// Stores the offset for `uri` (zero removes it) and wakes the engine so
+    /// the change shows immediately.
+    pub fn set_offset(&self, uri: &str, offset_ms: i64) -> Result<(), String> {
+        {
+            let mut offsets = self.offsets.lock().map_err(|e| e.to_string())?;
+            if offset_ms == 0 {
+                offsets.remove(uri);
+            } else {
+                offsets.insert(uri.to_string(), offset_ms);
+            }
+            let contents = serde_json::to_vec_pretty(&*offsets).map_err(|e| e.to_string())?;
+            config::write_atomic(&Self::offsets_path(), &contents)?;
+        }
+
+        if let Ok(mut current) = self.current.write() {
+            if let Some(current) = current.as_mut().filter(|c| c.uri == uri) {
+                current.offset_ms = offset_ms;
+            }
+        }
+        self.offset_changed.notify_one();
+        Ok(())
+    }
+}
+
+/// Extrapolated playback position for one track.
+struct Clock {
+    uri: String,
+    base_ms: u64,
+    at: Instant,
+    playing: bool,
+    duration_ms: u64,
+}
+
+impl Clock {
+    fn new(snapshot: &PlaybackSnapshot) -> Self {
+        Clock {
+            uri: snapshot.uri.clone(),
+            base_ms: snapshot.progress_ms,
+            at: Instant::now(),
+            playing: snapshot.is_playing,
+            duration_ms: snapshot.duration_ms,
+        }
+    }
+
+    fn position_ms(&self) -> u64 {
+        if !self.playing {
+            return self.base_ms;
+        }
+        let position = self.base_ms + self.at.elapsed().as_millis() as u64;
+        if self.duration_ms > 0 {
+            position.min(self.duration_ms)
+        } else {
+            position
+        }
+    }
+
+    fn observe(&mut self, snapshot: &PlaybackSnapshot) {
+        if snapshot.uri != self.uri {
+            *self = Clock::new(snapshot);
+            return;
+        }
+
+        let estimate = self.position_ms();
+        let reported = snapshot.progress_ms;
+        let jitter = reported < estimate && estimate - reported <= JITTER_TOLERANCE_MS;
+
+        if snapshot.is_playing != self.playing || !jitter {
+            self.base_ms = reported;
+            self.at = Instant::now();
+            self.playing = snapshot.is_playing;
+        }
+    }
+}
+
+/// Last entry in `times` at or before `position`.
+fn index_at(times: impl Iterator<Item = u64>, position: u64) -> Option<usize> {
+    times
+        .take_while(|&time| time <= position)
+        .count()
+        .checked_sub(1)
+}
+
+fn locate(lyrics: &Lyrics, uri: &str, clock: &Clock, offset_ms: i64) -> LyricsPosition {
+    let position_ms = clock.position_ms();
+    let shifted = (position_ms as i64 + offset_ms).max(0) as u64;
+
+    let line_index = index_at(lyrics.lines.iter().map(|l| l.time_ms), shifted);
+    let word_index =
+        line_index.and_then(|i| index_at(lyrics.lines[i].words.iter().map(|w| w.time_ms), shifted));
+    let next_line = line_index.map(|i| i + 1).unwrap_or(0);
+    let time_to_next_line_ms = lyrics
+        .lines
+        .get(next_line)
+        .map(|line| line.time_ms.saturating_sub(shifted));
+
+    LyricsPosition {
+        uri: uri.to_string(),
+        line_index,
+        word_index,
+        time_to_next_line_ms,
+        position_ms,
+        is_playing: clock.playing,
+    }
+}
+
+/// How long until the highlighted line or word next changes.
+fn next_wake(lyrics: &Lyrics, position: &LyricsPosition, offset_ms: i64) -> Duration {
+    if !position.is_playing {
+        return IDLE_TICK;
+    }
+    let shifted = (position.position_ms as i64 + offset_ms).max(0) as u64;
+
+    let next_word = position
+        .line_index
+        .and_then(|i| lyrics.lines[i].words.iter().find(|w| w.time_ms > shifted))
+        .map(|w| w.time_ms - shifted);
+    let until = match (position.time_to_next_line_ms, next_word) {
+        (Some(line), Some(word)) => line.min(word),
+        (line, word) => line.or(word).unwrap_or(u64::MAX),
+    };
+
+    Duration::from_millis(until.max(1)).min(MAX_TICK)
+}
+
+fn emit_lyrics(app_handle: &AppHandle, current: &CurrentLyrics) {
+    if let Err(e) = app_handle.emit("lyrics-changed", current) {
+        eprintln!("Failed to emit lyrics-changed: {}", e);
+    }
+}
+
+/// Loads lyrics on every track change and emits `lyrics-line` whenever the
+/// current line or word changes, plus on every seek, pause and resume.
+pub fn spawn_sync_engine(
+    app_handle: AppHandle,
+    hub: Arc<PlaybackHub>,
+    providers: Arc<LyricsProviders>,
+    engine: Arc<LyricsEngine>,
+) {
+    let mut events = hub.subscribe();
+
+    tauri::async_runtime::spawn(async move {
+        let mut clock: Option<Clock> = None;
+        let mut last: Option<LyricsPosition> = None;
+
+        loop {
+            let current = engine.current();
+            let synced = current
+                .as_ref()
+                .and_then(|c| c.lyrics.as_ref().filter(|l| l.synced).map(|l| (c, l)));
+
+            let wait = match (synced, &clock) {
+                (Some((current, lyrics)), Some(clock)) => {
+                    let position = locate(lyrics, &current.uri, clock, current.offset_ms);
+                    let wait = next_wake(lyrics, &position, current.offset_ms);
+
+                    if changed(last.as_ref(), &position) {
+                        if let Err(e) = app_handle.emit("lyrics-line", &position) {
+                            eprintln!("Failed to emit lyrics-line: {}", e);
+                        }
+                        let _ = engine.lines.send(position.clone());
+                        if let Ok(mut slot) = engine.position.write() {
+                            *slot = Some(position.clone());
+                        }
+                    }
+                    last = Some(position);
+                    wait
+                }
+                _ => IDLE_TICK,
+            };
+
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(PlaybackEvent::TrackChanged(snapshot)) => {
+                        clock = Some(Clock::new(&snapshot));
+                        last = None;
+                        engine.set_current(None);
+
+                        let query = LyricsQuery {
+                            artist: snapshot.artists.first().cloned().unwrap_or_default(),
+                            title: snapshot.title.clone(),
+                            album: snapshot.album.clone().unwrap_or_default(),
+                            duration_ms: Some(snapshot.duration_ms).filter(|&d| d > 0),
+                        };
+                        let lyrics = providers.lookup(&query).await;
+                        let current = CurrentLyrics {
+                            offset_ms: engine.offset_for(&snapshot.uri),
+                            uri: snapshot.uri,
+                            lyrics,
+                        };
+                        emit_lyrics(&app_handle, &current);
+                        engine.set_current(Some(current));
+                    }
+                    Ok(PlaybackEvent::Progress(snapshot)) => match clock.as_mut() {
+                        Some(clock) => clock.observe(&snapshot),
+                        None => clock = Some(Clock::new(&snapshot)),
+                    },
+                    Ok(PlaybackEvent::Stopped) => {
+                        clock = None;
+                        last = None;
+                        engine.set_current(None);
+                    }
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                },
+                _ = engine.offset_changed.notified() => {
+                    // Force a fresh event with the new offset applied.
+                    last = None;
+                }
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    });
+}
+
+/// Only line, word and play-state changes are worth an event; the position
+/// itself moves on every tick.
+fn changed(last: Option<&LyricsPosition>, next: &LyricsPosition) -> bool {
+    match last {
+        None => true,
+        Some(last) => {
+            last.uri != next.uri
+                || last.line_index != next.line_index
+                || last.word_index != next.word_index
+                || last.is_playing != next.is_playing
+                || last.position_ms.abs_diff(next.position_ms) > 2 * MAX_TICK.as_millis() as u64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lyrics::{LyricLine, LyricWord, LyricsSource};
+
+    fn snapshot(uri: &str, progress_ms: u64, is_playing: bool) -> PlaybackSnapshot {
+        PlaybackSnapshot {
+            track_id: None,
+            uri: uri.to_string(),
+            title: "Title".to_string(),
+            artists: vec!["Artist".to_string()],
+            album: None,
+            image_url: None,
+            duration_ms: 200_000,
+            progress_ms,
+            is_playing,
+            is_local: false,
+            item_type: "track".to_string(),
+            device_id: None,
+            device_name: None,
+            volume_percent: None,
+            context_uri: None,
+            context_type: None,
+            shuffle: false,
+            repeat: "off".to_string(),
+            observed_at: 0,
+        }
+    }
+
+    /// A playing clock anchored at `base_ms` as of `elapsed_ms` ago.
+    fn running(base_ms: u64, elapsed_ms: u64) -> Clock {
+        let mut clock = Clock::new(&snapshot("spotify:track:a", base_ms, true));
+        clock.at = Instant::now() - Duration::from_millis(elapsed_ms);
+        clock
+    }
+
+    fn paused(position_ms: u64) -> Clock {
+        Clock::new(&snapshot("spotify:track:a", position_ms, false))
+    }
+
+    fn line(time_ms: u64, words: &[u64]) -> LyricLine {
+        LyricLine {
+            time_ms,
+            text: String::new(),
+            words: words
+                .iter()
+                .map(|&time_ms| LyricWord {
+                    time_ms,
+                    text: String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn lyrics() -> Lyrics {
+        Lyrics {
+            source: LyricsSource::Sidecar,
+            synced: true,
+            offset_ms: 0,
+            lines: vec![
+                line(1_000, &[]),
+                line(3_000, &[3_000, 3_400, 4_000]),
+                line(6_000, &[]),
+            ],
+        }
+    }
+
+    fn playing_at(position_ms: u64) -> LyricsPosition {
+        LyricsPosition {
+            is_playing: true,
+            ..locate(&lyrics(), "spotify:track:a", &paused(position_ms), 0)
+        }
+    }
+
+    #[test]
+    fn paused_clock_holds_its_position() {
+        let mut clock = paused(42_000);
+        clock.at = Instant::now() - Duration::from_secs(10);
+        assert_eq!(clock.position_ms(), 42_000);
+    }
+
+    #[test]
+    fn playing_clock_extrapolates_and_stops_at_the_end() {
+        let position = running(10_000, 1_500).position_ms();
+        assert!((11_500..12_000).contains(&position), "{}", position);
+
+        assert_eq!(running(199_000, 5_000).position_ms(), 200_000);
+    }
+
+    #[test]
+    fn small_backward_correction_is_ignored() {
+        let mut clock = running(10_000, 1_000);
+        clock.observe(&snapshot("spotify:track:a", 10_700, true));
+        assert_eq!(clock.base_ms, 10_000);
+        assert!(clock.position_ms() >= 11_000);
+    }
+
+    #[test]
+    fn forward_report_reanchors() {
+        let mut clock = running(10_000, 1_000);
+        clock.observe(&snapshot("spotify:track:a", 11_300, true));
+        assert_eq!(clock.base_ms, 11_300);
+        assert!((11_300..11_500).contains(&clock.position_ms()));
+    }
+
+    #[test]
+    fn large_backward_jump_is_a_seek() {
+        let mut clock = running(10_000, 1_000);
+        clock.observe(&snapshot("spotify:track:a", 2_000, true));
+        assert!((2_000..2_200).contains(&clock.position_ms()));
+
+        let mut clock = running(10_000, 1_000);
+        clock.observe(&snapshot("spotify:track:a", 90_000, true));
+        assert!((90_000..90_200).contains(&clock.position_ms()));
+    }
+
+    #[test]
+    fn pause_and_resume_take_the_reported_position() {
+        let mut clock = running(10_000, 1_000);
+        // Within the jitter window, but a play-state change always re-anchors.
+        clock.observe(&snapshot("spotify:track:a", 10_800, false));
+        assert!(!clock.playing);
+        assert_eq!(clock.position_ms(), 10_800);
+
+        clock.observe(&snapshot("spotify:track:a", 10_800, true));
+        assert!(clock.playing);
+        assert!((10_800..11_000).contains(&clock.position_ms()));
+    }
+
+    #[test]
+    fn new_track_resets_the_clock() {
+        let mut clock = running(100_000, 1_000);
+        clock.observe(&snapshot("spotify:track:b", 500, false));
+        assert_eq!(clock.uri, "spotify:track:b");
+        assert_eq!(clock.position_ms(), 500);
+    }
+
+    #[test]
+    fn locates_lines_and_words() {
+        let lyrics = lyrics();
+        let at = |ms| locate(&lyrics, "spotify:track:a", &paused(ms), 0);
+
+        let before = at(400);
+        assert_eq!(before.line_index, None);
+        assert_eq!(before.word_index, None);
+        assert_eq!(before.time_to_next_line_ms, Some(600));
+
+        let plain = at(1_000);
+        assert_eq!(plain.line_index, Some(0));
+        assert_eq!(plain.word_index, None);
+        assert_eq!(plain.time_to_next_line_ms, Some(2_000));
+
+        let word = at(3_500);
+        assert_eq!(word.line_index, Some(1));
+        assert_eq!(word.word_index, Some(1));
+        assert_eq!(word.time_to_next_line_ms, Some(2_500));
+
+        let last = at(9_000);
+        assert_eq!(last.line_index, Some(2));
+        assert_eq!(last.time_to_next_line_ms, None);
+        assert!(!last.is_playing);
+    }
+
+    #[test]
+    fn offset_shifts_lookup_but_not_the_reported_position() {
+        let lyrics = lyrics();
+        let early = locate(&lyrics, "spotify:track:a", &paused(2_800), 300);
+        assert_eq!(early.line_index, Some(1));
+        assert_eq!(early.word_index, Some(0));
+        assert_eq!(early.position_ms, 2_800);
+
+        let late = locate(&lyrics, "spotify:track:a", &paused(500), -1_000);
+        assert_eq!(late.line_index, None);
+        assert_eq!(late.time_to_next_line_ms, Some(1_000));
+    }
+
+    #[test]
+    fn wakes_for_the_next_word_or_line() {
+        let lyrics = lyrics();
+
+        // Next word (3 400) comes before the next line (6 000).
+        let position = playing_at(3_100);
+        assert_eq!(next_wake(&lyrics, &position, 0), Duration::from_millis(300));
+
+        // Line 0 has no words, so the next line decides.
+        let position = playing_at(2_800);
+        assert_eq!(next_wake(&lyrics, &position, 0), Duration::from_millis(200));
+
+        // Far-off changes are capped so seeks are noticed promptly.
+        let position = playing_at(4_200);
+        assert_eq!(next_wake(&lyrics, &position, 0), MAX_TICK);
+        let position = playing_at(9_000);
+        assert_eq!(next_wake(&lyrics, &position, 0), MAX_TICK);
+    }
+
+    #[test]
+    fn paused_playback_sleeps_long() {
+        let lyrics = lyrics();
+        let position = locate(&lyrics, "spotify:track:a", &paused(3_100), 0);
+        assert_eq!(next_wake(&lyrics, &position, 0), IDLE_TICK);
+    }
+
+    #[test]
+    fn only_meaningful_changes_are_emitted() {
+        let last = playing_at(3_500);
+        assert!(changed(None, &last));
+        assert!(!changed(Some(&last), &playing_at(3_600)));
+        assert!(changed(Some(&last), &playing_at(4_000)));
+        assert!(changed(Some(&last), &playing_at(6_000)));
+
+        let paused = LyricsPosition {
+            is_playing: false,
+            ..last.clone()
+        };
+        assert!(changed(Some(&last), &paused));
+
+        let other = LyricsPosition {
+            uri: "spotify:track:b".to_string(),
+            ..last.clone()
+        };
+        assert!(changed(Some(&last), &other));
+
+        // Same word, but a seek within it still needs an update.
+        let drifted = LyricsPosition {
+            position_ms: last.position_ms + 1_100,
+            ..last.clone()
+        };
+        assert!(changed(Some(&last), &drifted));
+    }
+}
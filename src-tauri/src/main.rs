@@ -1,21 +1,58 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod api;
+mod art_protocol;
+mod artwork;
+mod config;
+mod history;
+mod library;
+mod lyrics;
+#[cfg(target_os = "linux")]
+mod mpris;
+mod now_playing;
+mod overlay;
+mod palette;
+mod playback;
+mod player;
+mod scrobble;
+mod spotify;
+mod tags;
+mod thumbnails;
+mod webhooks;
+mod windows;
+mod ws;
+
 use axum::{
     extract::{Query, State},
+    middleware,
     response::{Html, IntoResponse, Json},
     routing::{get, post},
     Router,
 };
 use oauth2::{
     basic::BasicClient, reqwest::async_http_client, AuthUrl, AuthorizationCode, ClientId,
-    CsrfToken, PkceCodeChallenge, RedirectUrl, Scope, TokenUrl, RefreshToken,
+    CsrfToken, PkceCodeChallenge, RedirectUrl, Scope, TokenUrl, RefreshToken, TokenResponse,
 };
 use serde::{Deserialize};
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
 use tauri::{AppHandle, Emitter, Manager};
 use tauri_plugin_global_shortcut::{Code, GlobalShortcutExt, Modifiers, Shortcut, ShortcutState};
 use tokio::net::TcpListener;
 
+use config::{Config, SharedConfig};
+use library::Library;
+use lyrics::{LyricsEngine, LyricsProviders};
+use palette::PaletteCache;
+use playback::PlaybackHub;
+use scrobble::Scrobbler;
+use spotify::SpotifyClient;
+use thumbnails::ThumbnailCache;
+use webhooks::Webhooks;
+use ws::EventStream;
+
 fn create_success_page() -> String {
     r#"
     <!DOCTYPE html>
@@ -371,12 +408,6 @@ struct AppState {
     csrf_token: Option<String>,
 }
 
-#[tauri::command]
-async fn select_music_directory() -> Result<Option<String>, String> {
-    println!("Directory selection requested - user should manually enter path");
-    Ok(None)
-}
-
 #[tauri::command]
 async fn resize_window_for_tabs(app_handle: AppHandle, show_tabs: bool, compact_mode: Option<bool>) -> Result<(), String> {
     use tauri::LogicalSize;
@@ -420,100 +451,34 @@ async fn resize_window_for_tabs(app_handle: AppHandle, show_tabs: bool, compact_
     }
 }
 
-#[tauri::command]
-async fn find_local_album_art(
-    artist: String,
-    album: String,
-    track: String,
-    music_directory: Option<String>,
-) -> Result<Option<String>, String> {
-    use std::path::PathBuf;
-    
-    println!("Searching for album art: {} - {} - {}", artist, album, track);
-    
-    
-    let music_dirs = if let Some(custom_dir) = music_directory {
-        vec![PathBuf::from(custom_dir)]
-    } else if cfg!(target_os = "windows") {
-        vec![
-            dirs::audio_dir().unwrap_or_else(|| PathBuf::from("C:\\Users\\Public\\Music")),
-            dirs::document_dir().map(|d| d.join("Music")).unwrap_or_else(|| PathBuf::from("C:\\Users\\Public\\Documents\\Music")),
-        ]
-    } else {
-        vec![
-            dirs::audio_dir().unwrap_or_else(|| PathBuf::from("~/Music")),
-            dirs::document_dir().map(|d| d.join("Music")).unwrap_or_else(|| PathBuf::from("~/Documents/Music")),
-        ]
-    };
-    
-    
-    let album_jpg = format!("{}.jpg", album);
-    let album_png = format!("{}.png", album);
-    let album_jpeg = format!("{}.jpeg", album);
-    
-    let art_filenames = vec![
-        "folder.jpg", "folder.png", "folder.jpeg",
-        "cover.jpg", "cover.png", "cover.jpeg",
-        "album.jpg", "album.png", "album.jpeg",
-        "albumart.jpg", "albumart.png", "albumart.jpeg",
-        "front.jpg", "front.png", "front.jpeg",
-        &album_jpg,
-        &album_png,
-        &album_jpeg,
-    ];
-    
-    
-    for music_dir in music_dirs {
-        if !music_dir.exists() {
-            continue;
-        }
-        
-        let search_paths = vec![
-            music_dir.join(&artist).join(&album),
-            music_dir.join(format!("{} - {}", artist, album)),
-            music_dir.join(&album),
-            music_dir.join(&artist),
-        ];
-        
-        for search_path in search_paths {
-            if !search_path.exists() {
-                continue;
-            }
-            
-            for filename in &art_filenames {
-                let art_path = search_path.join(filename);
-                if art_path.exists() {
-                    println!("Found album art: {}", art_path.display());
-                    
-                    return Ok(Some(format!("file://{}", art_path.display())));
-                }
-            }
-        }
-    }
-    
-    println!("No album art found for: {} - {}", artist, album);
-    Ok(None)
-}
-
-#[tauri::command]
-async fn login(client_id: String, state: tauri::State<'_, Arc<tokio::sync::Mutex<AppState>>>) -> Result<(), String> {
-    let mut state = state.inner().lock().await;
-
-    // Create OAuth client with the provided client ID
+fn build_oauth_client(client_id: String) -> OAuthClient {
     let redirect_url = "http://127.0.0.1:14700/callback";
     let auth_url = AuthUrl::new("https://accounts.spotify.com/authorize".to_string())
         .expect("Invalid auth URL");
     let token_url = Some(TokenUrl::new("https://accounts.spotify.com/api/token".to_string())
         .expect("Invalid token URL"));
 
-    let client = BasicClient::new(
+    BasicClient::new(
         ClientId::new(client_id),
         None,
         auth_url,
         token_url,
     )
     .set_redirect_uri(RedirectUrl::new(redirect_url.to_string())
-        .expect("Invalid redirect URL"));
+        .expect("Invalid redirect URL"))
+}
+
+#[tauri::command]
+async fn login(
+    client_id: String,
+    state: tauri::State<'_, Arc<tokio::sync::Mutex<AppState>>>,
+    spotify: tauri::State<'_, Arc<SpotifyClient>>,
+) -> Result<(), String> {
+    let mut state = state.inner().lock().await;
+
+    // Create OAuth client with the provided client ID
+    spotify.set_client_id(client_id.clone()).await;
+    let client = build_oauth_client(client_id);
 
     let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
     state.pkce_verifier = Some(pkce_verifier.secret().to_string());
@@ -525,6 +490,7 @@ async fn login(client_id: String, state: tauri::State<'_, Arc<tokio::sync::Mutex
         .add_scope(Scope::new("user-read-playback-state".to_string()))
         .add_scope(Scope::new("user-modify-playback-state".to_string()))
         .add_scope(Scope::new("user-read-recently-played".to_string()))
+        .add_scope(Scope::new("user-library-modify".to_string()))
         .url();
 
     state.csrf_token = Some(csrf_token.secret().to_string());
@@ -553,10 +519,30 @@ struct RefreshTokenRequest {
     refresh_token: String,
 }
 
+/// Port of the embedded OAuth and API server.
+const SERVER_PORT: u16 = 14700;
+
+/// Serves `router` on `addr` until the app exits.
+async fn serve(addr: SocketAddr, router: Router) {
+    println!("Starting server on {}", addr);
+    match TcpListener::bind(&addr).await {
+        Ok(listener) => {
+            let service = router.into_make_service_with_connect_info::<SocketAddr>();
+            if let Err(e) = axum::serve(listener, service).await {
+                eprintln!("Server error on {}: {}", addr, e);
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to bind to {}: {}", addr, e);
+        }
+    }
+}
+
 #[derive(Clone)]
 struct AxumState {
     app_state: Arc<tokio::sync::Mutex<AppState>>,
     app_handle: AppHandle,
+    spotify: Arc<SpotifyClient>,
 }
 
 async fn callback(
@@ -590,6 +576,11 @@ async fn callback(
     match token_result {
         Ok(token) => {
             println!("OAuth token exchange successful");
+            state.spotify.store_token(
+                token.access_token().secret().to_string(),
+                token.refresh_token().map(|t| t.secret().to_string()),
+                token.expires_in(),
+            ).await;
             if let Err(e) = state.app_handle.emit("spotify-auth-token", &token) {
                 eprintln!("Failed to emit token: {}", e);
             }
@@ -609,7 +600,16 @@ async fn refresh_token(
     println!("Refreshing OAuth token");
     
     let token_result = {
-        let app_state = state.app_state.lock().await;
+        let mut app_state = state.app_state.lock().await;
+
+        // After a restart there has been no login yet, so rebuild the client
+        // from the client ID saved with the session.
+        if app_state.client.is_none() {
+            if let Some(client_id) = state.spotify.session().await.client_id {
+                app_state.client = Some(build_oauth_client(client_id));
+            }
+        }
+
         if let Some(ref client) = app_state.client {
             client
                 .exchange_refresh_token(&RefreshToken::new(payload.refresh_token))
@@ -624,6 +624,11 @@ async fn refresh_token(
     match token_result {
         Ok(token) => {
             println!("Token refresh successful");
+            state.spotify.store_token(
+                token.access_token().secret().to_string(),
+                token.refresh_token().map(|t| t.secret().to_string()),
+                token.expires_in(),
+            ).await;
             (axum::http::StatusCode::OK, Json(token)).into_response()
         }
         Err(e) => {
@@ -647,7 +652,64 @@ fn main() {
 
     let state_clone = state.clone();
 
+    let config: SharedConfig = Arc::new(std::sync::Mutex::new(Config::load()));
+    let spotify = Arc::new(SpotifyClient::load());
+    let playback_hub = Arc::new(PlaybackHub::new());
+    let library = Arc::new(Library::load());
+
+    let spotify_clone = spotify.clone();
+    let playback_hub_clone = playback_hub.clone();
+    let config_clone = config.clone();
+    let library_clone = library.clone();
+    let thumbnail_cache = Arc::new(ThumbnailCache::new());
+    let palette_cache = Arc::new(PaletteCache::new());
+    let protocol_config = config.clone();
+    let protocol_thumbnails = thumbnail_cache.clone();
+    let theme_thumbnails = thumbnail_cache.clone();
+    let theme_palettes = palette_cache.clone();
+    let now_playing_thumbnails = thumbnail_cache.clone();
+    let lyrics_engine = Arc::new(LyricsEngine::load());
+    let history_db = history::open();
+    let recorder_history = history_db.clone();
+    let scrobbler = Arc::new(Scrobbler::new(config.clone()));
+    let playback_scrobbler = scrobbler.clone();
+    let webhooks = Arc::new(Webhooks::new(config.clone()));
+    let playback_webhooks = webhooks.clone();
+    let sync_lyrics_engine = lyrics_engine.clone();
+    let lyrics_providers = Arc::new(LyricsProviders::new(config.clone(), library.clone()));
+    let sync_lyrics_providers = lyrics_providers.clone();
+    let api_router = api::router(
+        config.clone(),
+        spotify.clone(),
+        playback_hub.clone(),
+        lyrics_engine.clone(),
+        history_db.clone(),
+    );
+    api::ensure_api_keys(&config);
+    let remote_address = api::remote_bind_address(&config, SERVER_PORT);
+    let event_stream = Arc::new(EventStream::new());
+    let ws_router = ws::router(
+        config.clone(),
+        event_stream.clone(),
+        playback_hub.clone(),
+        spotify.clone(),
+    );
+    let ws_lyrics_engine = lyrics_engine.clone();
+    let overlay_router = overlay::router(config.clone(), playback_hub.clone());
+
     tauri::Builder::default()
+        .plugin(tauri_plugin_dialog::init())
+        .register_asynchronous_uri_scheme_protocol(
+            art_protocol::SCHEME,
+            move |_ctx, request, responder| {
+                art_protocol::handle(
+                    protocol_config.clone(),
+                    protocol_thumbnails.clone(),
+                    request,
+                    responder,
+                )
+            },
+        )
         .setup(move |app| {
             println!("Setting up Tauri application...");
             
@@ -669,6 +731,59 @@ fn main() {
                 eprintln!("Failed to center window: {}", e);
             }
 
+            let main_app_handle = app_handle.clone();
+            window.on_window_event(move |event| {
+                if let tauri::WindowEvent::Destroyed = event {
+                    windows::close_detached_windows(&main_app_handle);
+                }
+            });
+
+            playback::spawn_poller(
+                app_handle.clone(),
+                spotify_clone.clone(),
+                playback_hub_clone.clone(),
+            );
+            palette::spawn_theme_watcher(
+                app_handle.clone(),
+                playback_hub_clone.clone(),
+                config_clone.clone(),
+                library_clone.clone(),
+                theme_thumbnails,
+                theme_palettes,
+            );
+            now_playing::spawn_writer(
+                playback_hub_clone.clone(),
+                config_clone.clone(),
+                now_playing_thumbnails,
+            );
+            history::spawn_recorder(playback_hub_clone.clone(), recorder_history);
+            scrobble::spawn_scrobbler(playback_hub_clone.clone(), playback_scrobbler);
+            webhooks::spawn_webhooks(playback_hub_clone.clone(), playback_webhooks);
+            #[cfg(target_os = "linux")]
+            mpris::spawn_mpris(
+                app_handle.clone(),
+                playback_hub_clone.clone(),
+                spotify_clone.clone(),
+            );
+            ws::spawn_forwarders(
+                event_stream,
+                playback_hub_clone.clone(),
+                ws_lyrics_engine,
+                spotify_clone.clone(),
+            );
+            lyrics::spawn_sync_engine(
+                app_handle.clone(),
+                playback_hub_clone,
+                sync_lyrics_providers,
+                sync_lyrics_engine,
+            );
+            library::spawn_watcher(
+                app_handle.clone(),
+                library_clone.clone(),
+                config_clone.clone(),
+            );
+            library::spawn_scan(app_handle.clone(), library_clone, config_clone);
+
            
             let shortcut_prev =
                 Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::ArrowLeft);
@@ -720,27 +835,32 @@ fn main() {
             let axum_state = AxumState {
                 app_state: state_clone,
                 app_handle,
+                spotify: spotify_clone,
             };
 
 
             tauri::async_runtime::spawn(async move {
-                let router = Router::new()
+                let remote = Router::new()
+                    .nest("/api/v1", api_router)
+                    .merge(ws_router)
+                    .merge(overlay_router);
+                let local = Router::new()
                     .route("/callback", get(callback))
                     .route("/refresh-token", post(refresh_token))
-                    .with_state(axum_state);
-
-                let addr = SocketAddr::from(([127, 0, 0, 1], 14700));
-                println!("Starting OAuth server on {}", addr);
-                
-                match TcpListener::bind(&addr).await {
-                    Ok(listener) => {
-                        if let Err(e) = axum::serve(listener, router).await {
-                            eprintln!("Server error: {}", e);
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to bind to {}: {}", addr, e);
+                    .route_layer(middleware::from_fn(api::loopback_only))
+                    .with_state(axum_state)
+                    .merge(remote.clone());
+
+                let loopback = SocketAddr::from((Ipv4Addr::LOCALHOST, SERVER_PORT));
+                match remote_address {
+                    // A wildcard address includes loopback, and the port
+                    // cannot be bound on both.
+                    Some(addr) if addr.ip().is_unspecified() => serve(addr, local).await,
+                    Some(addr) => {
+                        tauri::async_runtime::spawn(serve(addr, remote));
+                        serve(loopback, local).await;
                     }
+                    None => serve(loopback, local).await,
                 }
             });
             
@@ -748,7 +868,78 @@ fn main() {
             Ok(())
         })
         .manage(state)
-        .invoke_handler(tauri::generate_handler![login, find_local_album_art, select_music_directory, resize_window_for_tabs])
+        .manage(config)
+        .manage(spotify)
+        .manage(playback_hub)
+        .manage(library)
+        .manage(thumbnail_cache)
+        .manage(palette_cache)
+        .manage(lyrics_engine)
+        .manage(lyrics_providers)
+        .manage(history_db)
+        .manage(scrobbler)
+        .manage(webhooks)
+        .invoke_handler(tauri::generate_handler![
+            login,
+            resize_window_for_tabs,
+            library::select_music_directory,
+            library::add_library_root,
+            library::remove_library_root,
+            library::list_library_roots,
+            library::scan_library,
+            library::get_library_status,
+            library::find_local_album_art,
+            library::match_local_album,
+            lyrics::get_lyrics,
+            lyrics::set_lyrics_providers,
+            lyrics::get_current_lyrics,
+            lyrics::get_lyrics_position,
+            lyrics::set_lyrics_offset,
+            lyrics::get_lyrics_offset,
+            thumbnails::get_thumbnail,
+            palette::get_palette,
+            history::get_history,
+            history::clear_history,
+            history::import_spotify_history,
+            history::import_local_history,
+            history::export_history,
+            history::get_top_tracks,
+            history::get_top_artists,
+            history::get_top_albums,
+            history::get_listening_by_day,
+            history::get_listening_heatmap,
+            history::get_listening_streaks,
+            history::get_skip_rates,
+            history::get_discovery_stats,
+            history::generate_listening_report,
+            scrobble::get_scrobble_settings,
+            scrobble::set_scrobble_settings,
+            scrobble::get_scrobble_status,
+            scrobble::lastfm_begin_auth,
+            scrobble::lastfm_complete_auth,
+            playback::get_playback_state,
+            playback::get_queue,
+            player::player_play,
+            player::player_pause,
+            player::player_next,
+            player::player_previous,
+            player::player_seek,
+            player::player_set_volume,
+            player::player_add_to_queue,
+            api::get_api_settings,
+            api::set_api_settings,
+            api::regenerate_api_key,
+            api::regenerate_overlay_key,
+            now_playing::get_now_playing_file_settings,
+            now_playing::set_now_playing_file_settings,
+            webhooks::get_webhook_settings,
+            webhooks::set_webhook_settings,
+            webhooks::get_webhook_log,
+            webhooks::test_webhook,
+            windows::open_lyrics_window,
+            windows::open_queue_window,
+            windows::open_history_window,
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
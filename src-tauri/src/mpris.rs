@@ -0,0 +1,457 @@
+//! MPRIS on the D-Bus session bus, so Linux media keys, lock screens and
+//! `playerctl` can see and control the widget. Properties are read from the
+//! playback hub and methods go through the Rust player; changes are
+//! announced with `PropertiesChanged` as the poller notices them.
+
+use std::{collections::HashMap, fmt, sync::Arc};
+use tauri::{AppHandle, Manager};
+use tokio::sync::broadcast::error::RecvError;
+use zbus::{
+    connection, fdo, interface,
+    object_server::{InterfaceRef, SignalEmitter},
+    zvariant::{ObjectPath, Value},
+    Connection,
+};
+
+use crate::{
+    now_playing,
+    playback::{PlaybackEvent, PlaybackHub, PlaybackSnapshot},
+    player::{self, PlayRequest},
+    spotify::SpotifyClient,
+};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.spotify_widget";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+/// Position changes between polls larger than this, beyond what playback
+/// explains, are reported as seeks.
+const SEEK_THRESHOLD_MS: u64 = 2_000;
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// MPRIS positions are in microseconds. The snapshot is up to a poll old,
+/// so playing tracks are extrapolated.
+fn position_us(snapshot: &PlaybackSnapshot) -> i64 {
+    let mut position = snapshot.progress_ms;
+    if snapshot.is_playing {
+        position += now_ms().saturating_sub(snapshot.observed_at);
+    }
+    position.min(snapshot.duration_ms) as i64 * 1000
+}
+
+/// Object paths only allow `[A-Za-z0-9_]` in each element.
+fn track_id(snapshot: &PlaybackSnapshot) -> ObjectPath<'static> {
+    let id: String = snapshot
+        .uri
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    ObjectPath::try_from(format!("/com/spotify/widget/track/{}", id))
+        .unwrap_or_else(|_| ObjectPath::from_static_str_unchecked(NO_TRACK))
+}
+
+fn loop_status(repeat: &str) -> &'static str {
+    match repeat {
+        "track" => "Track",
+        "context" => "Playlist",
+        _ => "None",
+    }
+}
+
+fn failed(error: impl fmt::Display) -> fdo::Error {
+    fdo::Error::Failed(error.to_string())
+}
+
+struct Root {
+    app_handle: AppHandle,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {
+        if let Some(window) = self.app_handle.get_webview_window("main") {
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+    }
+
+    fn quit(&self) {
+        self.app_handle.exit(0);
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_raise(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn identity(&self) -> &str {
+        "Spotify Widget"
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec!["spotify".to_string()]
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+struct Player {
+    hub: Arc<PlaybackHub>,
+    spotify: Arc<SpotifyClient>,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    async fn next(&self) -> fdo::Result<()> {
+        player::next(&self.spotify).await.map_err(failed)
+    }
+
+    async fn previous(&self) -> fdo::Result<()> {
+        player::previous(&self.spotify).await.map_err(failed)
+    }
+
+    async fn pause(&self) -> fdo::Result<()> {
+        player::pause(&self.spotify).await.map_err(failed)
+    }
+
+    async fn play(&self) -> fdo::Result<()> {
+        player::play(&self.spotify, PlayRequest::default())
+            .await
+            .map_err(failed)
+    }
+
+    async fn play_pause(&self) -> fdo::Result<()> {
+        match self.hub.current() {
+            Some(snapshot) if snapshot.is_playing => self.pause().await,
+            _ => self.play().await,
+        }
+    }
+
+    /// Spotify has no stop, so this pauses.
+    async fn stop(&self) -> fdo::Result<()> {
+        self.pause().await
+    }
+
+    /// Seeking past the end skips to the next track, as MPRIS specifies.
+    async fn seek(&self, offset: i64) -> fdo::Result<()> {
+        let Some(snapshot) = self.hub.current() else {
+            return Ok(());
+        };
+        let target = position_us(&snapshot) + offset;
+        if target >= snapshot.duration_ms as i64 * 1000 {
+            return self.next().await;
+        }
+        player::seek(&self.spotify, (target.max(0) / 1000) as u64)
+            .await
+            .map_err(failed)
+    }
+
+    /// Ignored unless `track_id` is the current track and `position` is
+    /// within it.
+    async fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        let Some(snapshot) = self.hub.current() else {
+            return Ok(());
+        };
+        let in_range = (0..=snapshot.duration_ms as i64 * 1000).contains(&position);
+        if track_id != self::track_id(&snapshot) || !in_range {
+            return Ok(());
+        }
+        player::seek(&self.spotify, (position / 1000) as u64)
+            .await
+            .map_err(failed)
+    }
+
+    async fn open_uri(&self, uri: &str) -> fdo::Result<()> {
+        let mut request = PlayRequest::default();
+        if uri.starts_with("spotify:track:") || uri.starts_with("spotify:episode:") {
+            request.uris = Some(vec![uri.to_string()]);
+        } else {
+            request.context_uri = Some(uri.to_string());
+        }
+        player::play(&self.spotify, request).await.map_err(failed)
+    }
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &str {
+        match self.hub.current() {
+            Some(snapshot) if snapshot.is_playing => "Playing",
+            Some(_) => "Paused",
+            None => "Stopped",
+        }
+    }
+
+    #[zbus(property)]
+    fn loop_status(&self) -> &str {
+        self.hub
+            .current()
+            .map(|snapshot| loop_status(&snapshot.repeat))
+            .unwrap_or("None")
+    }
+
+    #[zbus(property)]
+    async fn set_loop_status(&self, status: String) -> fdo::Result<()> {
+        let state = match status.as_str() {
+            "Track" => "track",
+            "Playlist" => "context",
+            _ => "off",
+        };
+        player::set_repeat(&self.spotify, state)
+            .await
+            .map_err(failed)
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn shuffle(&self) -> bool {
+        self.hub.current().is_some_and(|snapshot| snapshot.shuffle)
+    }
+
+    #[zbus(property)]
+    async fn set_shuffle(&self, shuffle: bool) -> fdo::Result<()> {
+        player::set_shuffle(&self.spotify, shuffle)
+            .await
+            .map_err(failed)
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, Value<'static>> {
+        let mut metadata = HashMap::new();
+        let Some(snapshot) = self.hub.current() else {
+            metadata.insert(
+                "mpris:trackid".to_string(),
+                Value::from(ObjectPath::from_static_str_unchecked(NO_TRACK)),
+            );
+            return metadata;
+        };
+
+        metadata.insert(
+            "mpris:trackid".to_string(),
+            Value::from(track_id(&snapshot)),
+        );
+        metadata.insert(
+            "mpris:length".to_string(),
+            Value::from(snapshot.duration_ms as i64 * 1000),
+        );
+        metadata.insert(
+            "xesam:title".to_string(),
+            Value::from(snapshot.title.clone()),
+        );
+        metadata.insert(
+            "xesam:artist".to_string(),
+            Value::from(snapshot.artists.clone()),
+        );
+        if let Some(album) = snapshot.album.clone() {
+            metadata.insert("xesam:album".to_string(), Value::from(album));
+        }
+        if let Some(art) = snapshot.image_url.clone() {
+            let art = if art.starts_with('/') {
+                format!("file://{}", art)
+            } else {
+                art
+            };
+            metadata.insert("mpris:artUrl".to_string(), Value::from(art));
+        }
+        let url = now_playing::web_url(&snapshot.uri);
+        if !url.is_empty() {
+            metadata.insert("xesam:url".to_string(), Value::from(url));
+        }
+        metadata
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.hub
+            .current()
+            .and_then(|snapshot| snapshot.volume_percent)
+            .map(|percent| percent as f64 / 100.0)
+            .unwrap_or(0.0)
+    }
+
+    #[zbus(property)]
+    async fn set_volume(&self, volume: f64) -> fdo::Result<()> {
+        let percent = (volume.clamp(0.0, 1.0) * 100.0).round() as u8;
+        player::set_volume(&self.spotify, percent)
+            .await
+            .map_err(failed)
+    }
+
+    /// Clients read this when they need it; changes are not signalled.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        self.hub.current().map(|s| position_us(&s)).unwrap_or(0)
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        self.hub.current().is_some()
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        self.hub.current().is_some()
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        self.hub.current().is_some()
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        self.hub.current().is_some()
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        self.hub.current().is_some()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// Emits `PropertiesChanged` for whatever differs between two snapshots,
+/// and `Seeked` when the position jumped.
+async fn announce(
+    player: &InterfaceRef<Player>,
+    previous: Option<&PlaybackSnapshot>,
+    current: Option<&PlaybackSnapshot>,
+) -> zbus::Result<()> {
+    let emitter = player.signal_emitter();
+    let iface = player.get().await;
+
+    let (previous, current) = match (previous, current) {
+        (Some(previous), Some(current)) if previous.uri == current.uri => (previous, current),
+        (None, None) => return Ok(()),
+        _ => {
+            iface.metadata_changed(emitter).await?;
+            iface.playback_status_changed(emitter).await?;
+            iface.can_go_next_changed(emitter).await?;
+            iface.can_go_previous_changed(emitter).await?;
+            iface.can_play_changed(emitter).await?;
+            iface.can_pause_changed(emitter).await?;
+            iface.can_seek_changed(emitter).await?;
+            return Ok(());
+        }
+    };
+
+    if previous.is_playing != current.is_playing {
+        iface.playback_status_changed(emitter).await?;
+    }
+    if previous.shuffle != current.shuffle {
+        iface.shuffle_changed(emitter).await?;
+    }
+    if previous.repeat != current.repeat {
+        iface.loop_status_changed(emitter).await?;
+    }
+    if previous.volume_percent != current.volume_percent {
+        iface.volume_changed(emitter).await?;
+    }
+
+    let elapsed = if previous.is_playing {
+        current.observed_at.saturating_sub(previous.observed_at)
+    } else {
+        0
+    };
+    let expected = previous.progress_ms + elapsed;
+    if current.progress_ms.abs_diff(expected) > SEEK_THRESHOLD_MS {
+        Player::seeked(emitter, position_us(current)).await?;
+    }
+    Ok(())
+}
+
+async fn connect(
+    app_handle: AppHandle,
+    hub: Arc<PlaybackHub>,
+    spotify: Arc<SpotifyClient>,
+) -> zbus::Result<Connection> {
+    connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Root { app_handle })?
+        .serve_at(OBJECT_PATH, Player { hub, spotify })?
+        .build()
+        .await
+}
+
+/// Registers on the session bus and keeps the properties current. Does
+/// nothing but log when there is no session bus.
+pub fn spawn_mpris(app_handle: AppHandle, hub: Arc<PlaybackHub>, spotify: Arc<SpotifyClient>) {
+    let mut events = hub.subscribe();
+
+    tauri::async_runtime::spawn(async move {
+        let connection = match connect(app_handle, hub, spotify).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                eprintln!("Failed to start MPRIS server: {}", e);
+                return;
+            }
+        };
+        let player = match connection
+            .object_server()
+            .interface::<_, Player>(OBJECT_PATH)
+            .await
+        {
+            Ok(player) => player,
+            Err(e) => {
+                eprintln!("Failed to look up MPRIS player interface: {}", e);
+                return;
+            }
+        };
+        println!("MPRIS server registered as {}", BUS_NAME);
+
+        let mut last: Option<PlaybackSnapshot> = None;
+        loop {
+            // Every poll ends in Progress, which carries the full snapshot.
+            let current = match events.recv().await {
+                Ok(PlaybackEvent::Progress(snapshot)) => Some(snapshot),
+                Ok(PlaybackEvent::Stopped) => None,
+                Ok(PlaybackEvent::TrackChanged(_)) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            if let Err(e) = announce(&player, last.as_ref(), current.as_ref()).await {
+                eprintln!("Failed to emit MPRIS property changes: {}", e);
+            }
+            last = current;
+        }
+    });
+}
@@ -0,0 +1,291 @@
+//! Writes the current track to text files for streaming tools that can
+//! only read files. Each file is rendered from a template on every track
+//! change and emptied when playback stops; the cover can be copied to a
+//! fixed path as well. All writes are atomic, so readers never see a
+//! half-written file.
+//!
+//! Placeholders: `{title}`, `{artist}` (the first), `{artists}`, `{album}`,
+//! `{duration}` (`m:ss`), `{uri}`, `{url}` and `{device}`. `{{` and `}}`
+//! are literal braces.
+
+use std::{fs, io::ErrorKind, path::Path, sync::Arc};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    config::{self, NowPlayingFileSettings, SharedConfig},
+    playback::{PlaybackEvent, PlaybackHub, PlaybackSnapshot},
+    thumbnails::{self, ThumbnailCache},
+};
+
+pub(crate) const PLACEHOLDERS: &[&str] = &[
+    "title", "artist", "artists", "album", "duration", "uri", "url", "device",
+];
+
+const COVER_SIZE: u32 = 640;
+
+fn format_duration(ms: u64) -> String {
+    let seconds = ms / 1000;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// The `open.spotify.com` link for a `spotify:<type>:<id>` URI.
+pub(crate) fn web_url(uri: &str) -> String {
+    match uri.split(':').collect::<Vec<_>>()[..] {
+        ["spotify", kind, id] if kind != "local" => {
+            format!("https://open.spotify.com/{}/{}", kind, id)
+        }
+        _ => String::new(),
+    }
+}
+
+pub(crate) fn placeholder(name: &str, snapshot: &PlaybackSnapshot) -> Option<String> {
+    Some(match name {
+        "title" => snapshot.title.clone(),
+        "artist" => snapshot.artists.first().cloned().unwrap_or_default(),
+        "artists" => snapshot.artists.join(", "),
+        "album" => snapshot.album.clone().unwrap_or_default(),
+        "duration" => format_duration(snapshot.duration_ms),
+        "uri" => snapshot.uri.clone(),
+        "url" => web_url(&snapshot.uri),
+        "device" => snapshot.device_name.clone().unwrap_or_default(),
+        _ => return None,
+    })
+}
+
+pub(crate) fn render(
+    template: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        output.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            output.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        if tail.starts_with('}') {
+            return Err(format!("Unmatched '}}' in template {:?}", template));
+        }
+        let end = tail
+            .find('}')
+            .ok_or_else(|| format!("Unclosed '{{' in template {:?}", template))?;
+        let name = &tail[1..end];
+        let value = lookup(name).ok_or_else(|| format!("Unknown placeholder {{{}}}", name))?;
+        output.push_str(&value);
+        rest = &tail[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+pub fn validate(settings: &NowPlayingFileSettings) -> Result<(), String> {
+    for file in &settings.files {
+        if !file.path.is_absolute() {
+            return Err(format!("Not an absolute path: {}", file.path.display()));
+        }
+        let known = |name: &str| PLACEHOLDERS.contains(&name).then(String::new);
+        render(&file.template, known)?;
+    }
+    if let Some(cover) = &settings.cover_path {
+        if !cover.is_absolute() {
+            return Err(format!("Not an absolute path: {}", cover.display()));
+        }
+    }
+    Ok(())
+}
+
+fn remove_if_present(path: &Path) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.to_string()),
+        _ => Ok(()),
+    }
+}
+
+async fn write_cover(
+    thumbnails: Arc<ThumbnailCache>,
+    config: &SharedConfig,
+    snapshot: &PlaybackSnapshot,
+    path: &Path,
+) -> Result<(), String> {
+    let Some(source) = &snapshot.image_url else {
+        return remove_if_present(path);
+    };
+    let thumbnail =
+        thumbnails::thumbnail_for_source(thumbnails, config, source, COVER_SIZE).await?;
+    let bytes = fs::read(thumbnail).map_err(|e| e.to_string())?;
+    config::write_atomic(path, &bytes)
+}
+
+/// Writes every file for `snapshot`, or clears them when it is `None`.
+pub async fn update(
+    config: &SharedConfig,
+    thumbnails: Arc<ThumbnailCache>,
+    snapshot: Option<&PlaybackSnapshot>,
+) {
+    let settings = config
+        .lock()
+        .map(|config| config.now_playing_files.clone())
+        .unwrap_or_default();
+    if !settings.enabled {
+        return;
+    }
+
+    for file in &settings.files {
+        let contents = match snapshot {
+            Some(snapshot) => render(&file.template, |name| placeholder(name, snapshot)),
+            None => Ok(String::new()),
+        };
+        let result = contents.and_then(|c| config::write_atomic(&file.path, c.as_bytes()));
+        if let Err(e) = result {
+            eprintln!(
+                "Failed to write now playing file {}: {}",
+                file.path.display(),
+                e
+            );
+        }
+    }
+
+    if let Some(path) = &settings.cover_path {
+        let result = match snapshot {
+            Some(snapshot) => write_cover(thumbnails, config, snapshot, path).await,
+            None => remove_if_present(path),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to write cover to {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub fn spawn_writer(hub: Arc<PlaybackHub>, config: SharedConfig, thumbnails: Arc<ThumbnailCache>) {
+    let mut events = hub.subscribe();
+
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(PlaybackEvent::TrackChanged(snapshot)) => {
+                    update(&config, thumbnails.clone(), Some(&snapshot)).await
+                }
+                Ok(PlaybackEvent::Stopped) => update(&config, thumbnails.clone(), None).await,
+                Ok(PlaybackEvent::Progress(_)) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub fn get_now_playing_file_settings(
+    config: tauri::State<'_, SharedConfig>,
+) -> Result<NowPlayingFileSettings, String> {
+    let config = config.lock().map_err(|e| e.to_string())?;
+    Ok(config.now_playing_files.clone())
+}
+
+/// Saves the settings and writes the files for the current track right
+/// away.
+#[tauri::command]
+pub async fn set_now_playing_file_settings(
+    settings: NowPlayingFileSettings,
+    config: tauri::State<'_, SharedConfig>,
+    hub: tauri::State<'_, Arc<PlaybackHub>>,
+    thumbnails: tauri::State<'_, Arc<ThumbnailCache>>,
+) -> Result<(), String> {
+    validate(&settings)?;
+    {
+        let mut config = config.lock().map_err(|e| e.to_string())?;
+        config.now_playing_files = settings;
+        config.save()?;
+    }
+    update(
+        config.inner(),
+        thumbnails.inner().clone(),
+        hub.current().as_ref(),
+    )
+    .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "title" => Some("Jóga".to_string()),
+            "artist" => Some("Björk".to_string()),
+            "empty" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn fills_placeholders() {
+        assert_eq!(
+            render("{artist} - {title}", lookup).unwrap(),
+            "Björk - Jóga"
+        );
+        assert_eq!(render("[{empty}]", lookup).unwrap(), "[]");
+        assert_eq!(
+            render("no placeholders", lookup).unwrap(),
+            "no placeholders"
+        );
+        assert_eq!(render("", lookup).unwrap(), "");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(render("{{title}}", lookup).unwrap(), "{title}");
+        assert_eq!(render("{{{title}}}", lookup).unwrap(), "{Jóga}");
+        assert_eq!(
+            render(r#"{{"song": "{title}"}}"#, lookup).unwrap(),
+            r#"{"song": "Jóga"}"#
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        let error = render("{artist} - {year}", lookup).unwrap_err();
+        assert!(error.contains("{year}"), "{}", error);
+        assert!(render("{}", lookup).is_err());
+        assert!(render("{Title}", lookup).is_err());
+    }
+
+    #[test]
+    fn rejects_unbalanced_braces() {
+        assert!(render("{title", lookup)
+            .unwrap_err()
+            .starts_with("Unclosed"));
+        assert!(render("title}", lookup)
+            .unwrap_err()
+            .starts_with("Unmatched"));
+        assert!(render("{{title}", lookup).is_err());
+        // The name runs to the first '}', so a nested '{' is part of it.
+        assert!(render("{ti{tle}}", lookup).is_err());
+    }
+
+    #[test]
+    fn keeps_multibyte_text_around_placeholders() {
+        assert_eq!(
+            render("♪ {title} — {artist} ♪", lookup).unwrap(),
+            "♪ Jóga — Björk ♪"
+        );
+        assert_eq!(render("日本{{語}}", lookup).unwrap(), "日本{語}");
+        assert!(render("日本{語", lookup).is_err());
+    }
+
+    #[test]
+    fn formats_durations_and_links() {
+        assert_eq!(format_duration(0), "0:00");
+        assert_eq!(format_duration(245_999), "4:05");
+        assert_eq!(format_duration(3_600_000), "60:00");
+        assert_eq!(
+            web_url("spotify:track:4uLU6hMCjMI75M1A2tKUQC"),
+            "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC"
+        );
+        assert_eq!(web_url("spotify:local:Artist:Album:Title:180"), "");
+    }
+}
@@ -0,0 +1,157 @@
+//! Now-playing overlay for OBS browser sources, served by the embedded
+//! server at `/overlay`. The page has a transparent background and updates
+//! over the `/ws` event stream. Add `?key=<overlay key>` to the URL, and
+//! optionally `layout`, `theme`, `accent` (hex colour) and `progress=0`.
+//! The overlay key is read-only; the API key is refused here, so it never
+//! ends up in a browser source.
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{
+    api::{self, Access},
+    config::SharedConfig,
+    playback::{PlaybackHub, PlaybackSnapshot},
+};
+
+const PAGE: &str = include_str!("overlay/page.html");
+
+pub const LAYOUTS: &[&str] = &["card", "compact", "art", "text"];
+pub const THEMES: &[&str] = &["dark", "light", "glass", "minimal"];
+
+#[derive(Clone)]
+struct OverlayState {
+    config: SharedConfig,
+    hub: Arc<PlaybackHub>,
+}
+
+#[derive(Deserialize)]
+struct OverlayQuery {
+    key: Option<String>,
+    layout: Option<String>,
+    theme: Option<String>,
+    accent: Option<String>,
+    progress: Option<String>,
+}
+
+#[derive(Serialize)]
+struct NowPlaying {
+    playing: bool,
+    track: Option<PlaybackSnapshot>,
+}
+
+fn pick(value: Option<&str>, allowed: &[&'static str], what: &str) -> Result<&'static str, String> {
+    match value {
+        None | Some("") => Ok(allowed[0]),
+        Some(value) => allowed
+            .iter()
+            .copied()
+            .find(|allowed| *allowed == value)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown {} {:?}; expected one of {}",
+                    what,
+                    value,
+                    allowed.join(", ")
+                )
+            }),
+    }
+}
+
+/// `#rgb` or `#rrggbb`, with or without the `#`.
+fn parse_accent(value: Option<&str>) -> Result<Option<String>, String> {
+    let Some(value) = value.filter(|v| !v.is_empty()) else {
+        return Ok(None);
+    };
+    let hex = value.trim_start_matches('#');
+    if matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(Some(format!("#{}", hex)))
+    } else {
+        Err(format!("Invalid accent colour {:?}", value))
+    }
+}
+
+/// The response to send instead when a request lacks the overlay key.
+fn reject_without_overlay_key(
+    state: &OverlayState,
+    headers: &HeaderMap,
+    query: &OverlayQuery,
+) -> Option<Response> {
+    match api::check_key(&state.config, headers, query.key.as_deref()) {
+        Ok(Access::ReadOnly) => None,
+        Ok(Access::Control) => Some(
+            (
+                StatusCode::FORBIDDEN,
+                "Use the overlay key here, not the API key",
+            )
+                .into_response(),
+        ),
+        Err(e) => Some(e.into_response()),
+    }
+}
+
+/// Only validated values reach the page, so they need no escaping.
+fn render(query: &OverlayQuery) -> Result<String, String> {
+    let layout = pick(query.layout.as_deref(), LAYOUTS, "layout")?;
+    let theme = pick(query.theme.as_deref(), THEMES, "theme")?;
+    let accent = parse_accent(query.accent.as_deref())?;
+    let show_progress = !matches!(query.progress.as_deref(), Some("0" | "false" | "off"));
+
+    let mut classes = format!("layout-{} theme-{}", layout, theme);
+    if !show_progress {
+        classes.push_str(" no-progress");
+    }
+    let style = accent
+        .map(|accent| format!("--accent: {};", accent))
+        .unwrap_or_default();
+
+    Ok(PAGE
+        .replace("{{classes}}", &classes)
+        .replace("{{style}}", &style))
+}
+
+async fn page(
+    State(state): State<OverlayState>,
+    Query(query): Query<OverlayQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(rejection) = reject_without_overlay_key(&state, &headers, &query) {
+        return rejection;
+    }
+    match render(&query) {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+/// For overlays that poll instead of using the event stream.
+async fn now_playing(
+    State(state): State<OverlayState>,
+    Query(query): Query<OverlayQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(rejection) = reject_without_overlay_key(&state, &headers, &query) {
+        return rejection;
+    }
+    let track = state.hub.current();
+    Json(NowPlaying {
+        playing: track.as_ref().map(|t| t.is_playing).unwrap_or(false),
+        track,
+    })
+    .into_response()
+}
+
+/// The `/overlay` routes, to merge into the embedded server.
+pub fn router(config: SharedConfig, hub: Arc<PlaybackHub>) -> Router {
+    Router::new()
+        .route("/overlay", get(page))
+        .route("/overlay/now-playing.json", get(now_playing))
+        .with_state(OverlayState { config, hub })
+}
@@ -0,0 +1,372 @@
+//! Colour palettes extracted from cover art so the widget can tint itself to
+//! the current track. Palettes are computed from the smallest thumbnail,
+//! which is plenty for colour statistics, and cached per image.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    config::{self, SharedConfig},
+    library::Library,
+    playback::{PlaybackEvent, PlaybackHub, PlaybackSnapshot},
+    thumbnails::{self, ThumbnailCache},
+};
+
+/// Thumbnail size sampled for colours.
+const SAMPLE_SIZE: u32 = 64;
+
+/// WCAG AA contrast for normal body text.
+const MIN_TEXT_CONTRAST: f32 = 4.5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Palette {
+    /// All colours are `#rrggbb`.
+    pub dominant: String,
+    pub vibrant: String,
+    pub muted: String,
+    /// Black or white, whichever reads better on `dominant`.
+    pub text: String,
+    /// WCAG contrast ratio of `text` on `dominant`, 1.0 to 21.0.
+    pub text_contrast: f32,
+    /// False when even the better of black and white falls short of
+    /// [`MIN_TEXT_CONTRAST`]; the UI should add a scrim behind text.
+    pub text_meets_aa: bool,
+}
+
+#[derive(Clone, Serialize)]
+struct ThemePaletteEvent {
+    uri: String,
+    /// `None` when the track has no art we can reach.
+    palette: Option<Palette>,
+}
+
+#[derive(Clone, Copy)]
+struct Rgb(f32, f32, f32);
+
+impl Rgb {
+    fn hex(self) -> String {
+        let channel = |c: f32| c.round().clamp(0.0, 255.0) as u8;
+        format!(
+            "#{:02x}{:02x}{:02x}",
+            channel(self.0),
+            channel(self.1),
+            channel(self.2)
+        )
+    }
+
+    /// Saturation and lightness from HSL, both 0.0 to 1.0.
+    fn saturation_lightness(self) -> (f32, f32) {
+        let (r, g, b) = (self.0 / 255.0, self.1 / 255.0, self.2 / 255.0);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let lightness = (max + min) / 2.0;
+        let delta = max - min;
+        let saturation = if delta == 0.0 {
+            0.0
+        } else {
+            delta / (1.0 - (2.0 * lightness - 1.0).abs())
+        };
+        (saturation.clamp(0.0, 1.0), lightness)
+    }
+
+    /// WCAG relative luminance.
+    fn luminance(self) -> f32 {
+        let linear = |c: f32| {
+            let c = c / 255.0;
+            if c <= 0.03928 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * linear(self.0) + 0.7152 * linear(self.1) + 0.0722 * linear(self.2)
+    }
+}
+
+fn contrast(a: Rgb, b: Rgb) -> f32 {
+    let (la, lb) = (a.luminance(), b.luminance());
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
+/// Colours are bucketed to 4 bits per channel; each bucket keeps a running
+/// sum so its representative colour is the average of its pixels rather
+/// than the bucket corner.
+#[derive(Default, Clone, Copy)]
+struct Bucket {
+    count: u32,
+    sum: [u64; 3],
+}
+
+impl Bucket {
+    fn colour(&self) -> Rgb {
+        let n = self.count.max(1) as f32;
+        Rgb(
+            self.sum[0] as f32 / n,
+            self.sum[1] as f32 / n,
+            self.sum[2] as f32 / n,
+        )
+    }
+}
+
+pub fn extract(image_path: &Path) -> Result<Palette, String> {
+    let image = image::open(image_path)
+        .map_err(|e| e.to_string())?
+        .to_rgba8();
+
+    let mut buckets = vec![Bucket::default(); 4096];
+    for pixel in image.pixels() {
+        let [r, g, b, a] = pixel.0;
+        if a < 128 {
+            continue;
+        }
+        let index = ((r as usize >> 4) << 8) | ((g as usize >> 4) << 4) | (b as usize >> 4);
+        let bucket = &mut buckets[index];
+        bucket.count += 1;
+        bucket.sum[0] += r as u64;
+        bucket.sum[1] += g as u64;
+        bucket.sum[2] += b as u64;
+    }
+
+    let populated: Vec<&Bucket> = buckets.iter().filter(|b| b.count > 0).collect();
+    let dominant = populated
+        .iter()
+        .max_by_key(|b| b.count)
+        .map(|b| b.colour())
+        .ok_or("Image has no opaque pixels")?;
+
+    // Weighted by population so a few stray pixels cannot win, but with
+    // enough pull from the colour itself that a small vivid accent beats a
+    // large slightly-less-vivid area.
+    let pick = |score: &dyn Fn(f32, f32) -> f32| {
+        populated
+            .iter()
+            .map(|b| {
+                let (s, l) = b.colour().saturation_lightness();
+                (score(s, l) * (b.count as f32).sqrt(), b.colour())
+            })
+            .filter(|(score, _)| *score > 0.0)
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, colour)| colour)
+    };
+    let vibrant = pick(&|s, l| {
+        if s < 0.35 || !(0.25..=0.8).contains(&l) {
+            0.0
+        } else {
+            s * (1.0 - (l - 0.5).abs())
+        }
+    })
+    .unwrap_or(dominant);
+    let muted = pick(&|s, l| {
+        if s > 0.4 || !(0.2..=0.8).contains(&l) {
+            0.0
+        } else {
+            (1.0 - s) * (1.0 - (l - 0.5).abs())
+        }
+    })
+    .unwrap_or(dominant);
+
+    let white = Rgb(255.0, 255.0, 255.0);
+    let black = Rgb(0.0, 0.0, 0.0);
+    let (text, text_contrast) = if contrast(white, dominant) >= contrast(black, dominant) {
+        (white, contrast(white, dominant))
+    } else {
+        (black, contrast(black, dominant))
+    };
+
+    Ok(Palette {
+        dominant: dominant.hex(),
+        vibrant: vibrant.hex(),
+        muted: muted.hex(),
+        text: text.hex(),
+        text_contrast,
+        text_meets_aa: text_contrast >= MIN_TEXT_CONTRAST,
+    })
+}
+
+fn palette_dir() -> PathBuf {
+    config::cache_dir().join("palettes")
+}
+
+/// Palettes kept in memory before the oldest are forgotten. Each one is
+/// also on disk, so an evicted palette costs a file read, not an extraction.
+const MAX_PALETTES: usize = 512;
+
+/// Palettes by thumbnail file stem, oldest evicted first.
+#[derive(Default)]
+struct KnownPalettes {
+    palettes: HashMap<String, Palette>,
+    order: VecDeque<String>,
+}
+
+/// Palettes keyed by thumbnail file stem, which already names the image by
+/// content hash. Misses fall through to JSON files on disk, then to
+/// extraction.
+pub struct PaletteCache {
+    known: Mutex<KnownPalettes>,
+}
+
+impl PaletteCache {
+    pub fn new() -> Self {
+        PaletteCache {
+            known: Mutex::new(KnownPalettes::default()),
+        }
+    }
+
+    fn remember(&self, key: String, palette: Palette) {
+        let Ok(mut known) = self.known.lock() else {
+            return;
+        };
+        if known.palettes.insert(key.clone(), palette).is_none() {
+            known.order.push_back(key);
+        }
+        while known.order.len() > MAX_PALETTES {
+            if let Some(oldest) = known.order.pop_front() {
+                known.palettes.remove(&oldest);
+            }
+        }
+    }
+
+    fn for_thumbnail(&self, thumbnail: &Path) -> Result<Palette, String> {
+        let key = thumbnail
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or("Invalid thumbnail path")?
+            .to_string();
+
+        if let Some(palette) = self
+            .known
+            .lock()
+            .ok()
+            .and_then(|k| k.palettes.get(&key).cloned())
+        {
+            return Ok(palette);
+        }
+
+        let file = palette_dir().join(format!("{}.json", key));
+        let palette = match fs::read_to_string(&file)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+        {
+            Some(palette) => palette,
+            None => {
+                let palette = extract(thumbnail)?;
+                let contents = serde_json::to_vec(&palette).map_err(|e| e.to_string())?;
+                if let Err(e) = config::write_atomic(&file, &contents) {
+                    eprintln!("Failed to cache palette {}: {}", file.display(), e);
+                }
+                palette
+            }
+        };
+
+        self.remember(key, palette.clone());
+        Ok(palette)
+    }
+
+    pub async fn for_source(
+        self: Arc<Self>,
+        thumbnails: Arc<ThumbnailCache>,
+        config: &SharedConfig,
+        source: &str,
+    ) -> Result<Palette, String> {
+        let thumbnail =
+            thumbnails::thumbnail_for_source(thumbnails, config, source, SAMPLE_SIZE).await?;
+        tauri::async_runtime::spawn_blocking(move || self.for_thumbnail(&thumbnail))
+            .await
+            .map_err(|e| e.to_string())?
+    }
+}
+
+impl Default for PaletteCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where the art for a snapshot comes from: Spotify's image, or for local
+/// files the best match in the library index. The index lookup can wait on
+/// the index lock, so it runs on the blocking pool.
+async fn art_source(snapshot: &PlaybackSnapshot, library: Arc<Library>) -> Option<String> {
+    if let Some(url) = &snapshot.image_url {
+        return Some(url.clone());
+    }
+    if !snapshot.is_local {
+        return None;
+    }
+
+    let artist = snapshot.artists.first().cloned().unwrap_or_default();
+    let album = snapshot.album.clone().unwrap_or_default();
+    let title = snapshot.title.clone();
+    let found = tauri::async_runtime::spawn_blocking(move || {
+        library.index().find_album_art(&artist, &album, &title)
+    })
+    .await
+    .ok()??;
+    found
+        .album
+        .art
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+/// Emits `theme-palette` on every track change.
+pub fn spawn_theme_watcher(
+    app_handle: AppHandle,
+    hub: Arc<PlaybackHub>,
+    config: SharedConfig,
+    library: Arc<Library>,
+    thumbnails: Arc<ThumbnailCache>,
+    palettes: Arc<PaletteCache>,
+) {
+    let mut events = hub.subscribe();
+
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let snapshot = match events.recv().await {
+                Ok(PlaybackEvent::TrackChanged(snapshot)) => snapshot,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+
+            let palette = match art_source(&snapshot, library.clone()).await {
+                Some(source) => palettes
+                    .clone()
+                    .for_source(thumbnails.clone(), &config, &source)
+                    .await
+                    .map_err(|e| eprintln!("Failed to extract palette for {}: {}", source, e))
+                    .ok(),
+                None => None,
+            };
+
+            let event = ThemePaletteEvent {
+                uri: snapshot.uri,
+                palette,
+            };
+            if let Err(e) = app_handle.emit("theme-palette", event) {
+                eprintln!("Failed to emit theme-palette: {}", e);
+            }
+        }
+    });
+}
+
+/// Palette for `source`, which may be a local image or audio file path, or
+/// an `http(s)` URL.
+#[tauri::command]
+pub async fn get_palette(
+    source: String,
+    config: tauri::State<'_, SharedConfig>,
+    thumbnails: tauri::State<'_, Arc<ThumbnailCache>>,
+    palettes: tauri::State<'_, Arc<PaletteCache>>,
+) -> Result<Palette, String> {
+    palettes
+        .inner()
+        .clone()
+        .for_source(thumbnails.inner().clone(), config.inner(), &source)
+        .await
+}
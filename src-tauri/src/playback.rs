@@ -0,0 +1,229 @@
+use serde::Serialize;
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::broadcast;
+
+use crate::spotify::{PlaybackState, Queue, SpotifyClient};
+
+const POLL_PLAYING: Duration = Duration::from_secs(1);
+const POLL_IDLE: Duration = Duration::from_secs(3);
+const POLL_SIGNED_OUT: Duration = Duration::from_secs(5);
+
+/// What the backend currently believes is playing. Every window, and every
+/// backend subsystem, reads playback through this instead of polling Spotify
+/// on its own.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaybackSnapshot {
+    pub track_id: Option<String>,
+    pub uri: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub image_url: Option<String>,
+    pub duration_ms: u64,
+    pub progress_ms: u64,
+    pub is_playing: bool,
+    pub is_local: bool,
+    pub item_type: String,
+    pub device_id: Option<String>,
+    pub device_name: Option<String>,
+    pub volume_percent: Option<u32>,
+    pub context_uri: Option<String>,
+    pub context_type: Option<String>,
+    pub shuffle: bool,
+    pub repeat: String,
+    /// Wall-clock time of the poll, in Unix milliseconds.
+    pub observed_at: u64,
+}
+
+impl PlaybackSnapshot {
+    fn from_state(state: PlaybackState) -> Option<Self> {
+        let item = state.item?;
+        let device = state.device;
+        let context = state.context;
+
+        Some(PlaybackSnapshot {
+            track_id: item.id.clone(),
+            artists: item.artist_names(),
+            album: item.album_name(),
+            image_url: item.image_url(),
+            uri: item.uri,
+            title: item.name,
+            duration_ms: item.duration_ms,
+            progress_ms: state.progress_ms.unwrap_or(0),
+            is_playing: state.is_playing,
+            is_local: item.is_local,
+            item_type: item.item_type,
+            device_id: device.as_ref().and_then(|d| d.id.clone()),
+            device_name: device.as_ref().map(|d| d.name.clone()),
+            volume_percent: device.as_ref().and_then(|d| d.volume_percent),
+            context_uri: context.as_ref().map(|c| c.uri.clone()),
+            context_type: context.as_ref().map(|c| c.context_type.clone()),
+            shuffle: state.shuffle_state,
+            repeat: state.repeat_state,
+            observed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressUpdate {
+    pub uri: String,
+    pub progress_ms: u64,
+    pub duration_ms: u64,
+    pub is_playing: bool,
+}
+
+impl From<&PlaybackSnapshot> for ProgressUpdate {
+    fn from(snapshot: &PlaybackSnapshot) -> Self {
+        ProgressUpdate {
+            uri: snapshot.uri.clone(),
+            progress_ms: snapshot.progress_ms,
+            duration_ms: snapshot.duration_ms,
+            is_playing: snapshot.is_playing,
+        }
+    }
+}
+
+/// Broadcast to backend subscribers after every poll.
+#[derive(Debug, Clone)]
+pub enum PlaybackEvent {
+    TrackChanged(PlaybackSnapshot),
+    Progress(PlaybackSnapshot),
+    Stopped,
+}
+
+pub struct PlaybackHub {
+    current: RwLock<Option<PlaybackSnapshot>>,
+    events: broadcast::Sender<PlaybackEvent>,
+}
+
+impl PlaybackHub {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(64);
+        PlaybackHub {
+            current: RwLock::new(None),
+            events,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<PlaybackEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn current(&self) -> Option<PlaybackSnapshot> {
+        self.current.read().ok().and_then(|c| c.clone())
+    }
+
+    /// Stores the new snapshot and works out which events it implies.
+    fn update(&self, next: Option<PlaybackSnapshot>) -> Vec<PlaybackEvent> {
+        let mut current = match self.current.write() {
+            Ok(current) => current,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let mut events = Vec::new();
+        match (&*current, &next) {
+            (Some(_), None) => events.push(PlaybackEvent::Stopped),
+            (previous, Some(snapshot)) => {
+                if previous.as_ref().map(|p| &p.uri) != Some(&snapshot.uri) {
+                    events.push(PlaybackEvent::TrackChanged(snapshot.clone()));
+                }
+                events.push(PlaybackEvent::Progress(snapshot.clone()));
+            }
+            (None, None) => {}
+        }
+
+        *current = next;
+        events
+    }
+}
+
+impl Default for PlaybackHub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn emit_event(app_handle: &AppHandle, event: &PlaybackEvent) {
+    let result = match event {
+        PlaybackEvent::TrackChanged(snapshot) => app_handle.emit("track-changed", snapshot),
+        PlaybackEvent::Progress(snapshot) => {
+            app_handle.emit("progress", ProgressUpdate::from(snapshot))
+        }
+        PlaybackEvent::Stopped => app_handle.emit("playback-stopped", ()),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Failed to emit playback event: {}", e);
+    }
+}
+
+/// Polls the Spotify player endpoint for the lifetime of the app, faster
+/// while something is playing.
+pub fn spawn_poller(app_handle: AppHandle, spotify: Arc<SpotifyClient>, hub: Arc<PlaybackHub>) {
+    tauri::async_runtime::spawn(async move {
+        let mut last_error: Option<String> = None;
+
+        loop {
+            if !spotify.is_authenticated().await {
+                if hub.current().is_some() {
+                    for event in hub.update(None) {
+                        emit_event(&app_handle, &event);
+                        let _ = hub.events.send(event);
+                    }
+                }
+                tokio::time::sleep(POLL_SIGNED_OUT).await;
+                continue;
+            }
+
+            let delay = match spotify.current_playback().await {
+                Ok(state) => {
+                    last_error = None;
+                    let snapshot = state.and_then(PlaybackSnapshot::from_state);
+                    let playing = snapshot.as_ref().map(|s| s.is_playing).unwrap_or(false);
+
+                    for event in hub.update(snapshot) {
+                        emit_event(&app_handle, &event);
+                        // No receivers is fine; subsystems subscribe lazily.
+                        let _ = hub.events.send(event);
+                    }
+
+                    if playing {
+                        POLL_PLAYING
+                    } else {
+                        POLL_IDLE
+                    }
+                }
+                Err(e) => {
+                    let e = e.to_string();
+                    if last_error.as_deref() != Some(e.as_str()) {
+                        eprintln!("Failed to poll playback state: {}", e);
+                        last_error = Some(e);
+                    }
+                    POLL_IDLE
+                }
+            };
+
+            tokio::time::sleep(delay).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_playback_state(
+    hub: tauri::State<'_, Arc<PlaybackHub>>,
+) -> Result<Option<PlaybackSnapshot>, String> {
+    Ok(hub.current())
+}
+
+#[tauri::command]
+pub async fn get_queue(spotify: tauri::State<'_, Arc<SpotifyClient>>) -> Result<Queue, String> {
+    spotify.queue().await.map_err(|e| e.to_string())
+}
@@ -0,0 +1,226 @@
+//! Playback control through the Spotify Web API. The Tauri commands and
+//! the local HTTP API both go through these functions.
+
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{fmt, sync::Arc};
+
+use crate::spotify::{SpotifyClient, SpotifyError};
+
+/// What to start playing. Empty resumes the current playback.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PlayRequest {
+    /// An album, playlist, artist or show URI.
+    pub context_uri: Option<String>,
+    /// Track or episode URIs, played in order.
+    pub uris: Option<Vec<String>>,
+    /// Index into the context or `uris` to start from.
+    pub offset: Option<u64>,
+    pub position_ms: Option<u64>,
+    pub device_id: Option<String>,
+}
+
+/// A command refused before reaching Spotify, or one Spotify failed.
+#[derive(Debug, Clone)]
+pub enum PlayerError {
+    Invalid(String),
+    Spotify(SpotifyError),
+}
+
+impl fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayerError::Invalid(message) => write!(f, "{}", message),
+            PlayerError::Spotify(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+type PlayerResult = Result<(), PlayerError>;
+
+fn device_query(device_id: Option<&str>) -> String {
+    match device_id {
+        Some(id) => format!("?device_id={}", urlencoding::encode(id)),
+        None => String::new(),
+    }
+}
+
+fn check_uri(uri: &str) -> PlayerResult {
+    if uri.starts_with("spotify:") {
+        Ok(())
+    } else {
+        Err(PlayerError::Invalid(format!("Not a Spotify URI: {}", uri)))
+    }
+}
+
+pub async fn play(spotify: &SpotifyClient, request: PlayRequest) -> PlayerResult {
+    let mut body = serde_json::Map::new();
+    if let Some(context_uri) = request.context_uri {
+        check_uri(&context_uri)?;
+        body.insert("context_uri".to_string(), json!(context_uri));
+    }
+    if let Some(uris) = request.uris {
+        for uri in &uris {
+            check_uri(uri)?;
+        }
+        body.insert("uris".to_string(), json!(uris));
+    }
+    if let Some(offset) = request.offset {
+        body.insert("offset".to_string(), json!({ "position": offset }));
+    }
+    if let Some(position_ms) = request.position_ms {
+        body.insert("position_ms".to_string(), json!(position_ms));
+    }
+
+    let path = format!(
+        "/me/player/play{}",
+        device_query(request.device_id.as_deref())
+    );
+    let body = (!body.is_empty()).then_some(Value::Object(body));
+    spotify
+        .send(Method::PUT, &path, body)
+        .await
+        .map_err(PlayerError::Spotify)
+}
+
+pub async fn pause(spotify: &SpotifyClient) -> PlayerResult {
+    spotify
+        .send(Method::PUT, "/me/player/pause", None)
+        .await
+        .map_err(PlayerError::Spotify)
+}
+
+pub async fn next(spotify: &SpotifyClient) -> PlayerResult {
+    spotify
+        .send(Method::POST, "/me/player/next", None)
+        .await
+        .map_err(PlayerError::Spotify)
+}
+
+pub async fn previous(spotify: &SpotifyClient) -> PlayerResult {
+    spotify
+        .send(Method::POST, "/me/player/previous", None)
+        .await
+        .map_err(PlayerError::Spotify)
+}
+
+pub async fn seek(spotify: &SpotifyClient, position_ms: u64) -> PlayerResult {
+    let path = format!("/me/player/seek?position_ms={}", position_ms);
+    spotify
+        .send(Method::PUT, &path, None)
+        .await
+        .map_err(PlayerError::Spotify)
+}
+
+pub async fn set_volume(spotify: &SpotifyClient, volume_percent: u8) -> PlayerResult {
+    if volume_percent > 100 {
+        return Err(PlayerError::Invalid(format!(
+            "Volume must be 0-100, got {}",
+            volume_percent
+        )));
+    }
+    let path = format!("/me/player/volume?volume_percent={}", volume_percent);
+    spotify
+        .send(Method::PUT, &path, None)
+        .await
+        .map_err(PlayerError::Spotify)
+}
+
+pub async fn set_shuffle(spotify: &SpotifyClient, shuffle: bool) -> PlayerResult {
+    let path = format!("/me/player/shuffle?state={}", shuffle);
+    spotify
+        .send(Method::PUT, &path, None)
+        .await
+        .map_err(PlayerError::Spotify)
+}
+
+/// `state` is `track`, `context` or `off`.
+pub async fn set_repeat(spotify: &SpotifyClient, state: &str) -> PlayerResult {
+    if !matches!(state, "track" | "context" | "off") {
+        return Err(PlayerError::Invalid(format!(
+            "Unknown repeat state: {}",
+            state
+        )));
+    }
+    let path = format!("/me/player/repeat?state={}", state);
+    spotify
+        .send(Method::PUT, &path, None)
+        .await
+        .map_err(PlayerError::Spotify)
+}
+
+pub async fn add_to_queue(spotify: &SpotifyClient, uri: &str) -> PlayerResult {
+    check_uri(uri)?;
+    let path = format!("/me/player/queue?uri={}", urlencoding::encode(uri));
+    spotify
+        .send(Method::POST, &path, None)
+        .await
+        .map_err(PlayerError::Spotify)
+}
+
+/// Saves a track to the user's Liked Songs.
+pub async fn like(spotify: &SpotifyClient, uri: &str) -> PlayerResult {
+    let id = uri.strip_prefix("spotify:track:").ok_or_else(|| {
+        PlayerError::Invalid(format!("Only Spotify tracks can be liked: {}", uri))
+    })?;
+    let path = format!("/me/tracks?ids={}", urlencoding::encode(id));
+    spotify
+        .send(Method::PUT, &path, None)
+        .await
+        .map_err(PlayerError::Spotify)
+}
+
+#[tauri::command]
+pub async fn player_play(
+    request: Option<PlayRequest>,
+    spotify: tauri::State<'_, Arc<SpotifyClient>>,
+) -> Result<(), String> {
+    play(&spotify, request.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn player_pause(spotify: tauri::State<'_, Arc<SpotifyClient>>) -> Result<(), String> {
+    pause(&spotify).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn player_next(spotify: tauri::State<'_, Arc<SpotifyClient>>) -> Result<(), String> {
+    next(&spotify).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn player_previous(spotify: tauri::State<'_, Arc<SpotifyClient>>) -> Result<(), String> {
+    previous(&spotify).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn player_seek(
+    position_ms: u64,
+    spotify: tauri::State<'_, Arc<SpotifyClient>>,
+) -> Result<(), String> {
+    seek(&spotify, position_ms).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn player_set_volume(
+    volume_percent: u8,
+    spotify: tauri::State<'_, Arc<SpotifyClient>>,
+) -> Result<(), String> {
+    set_volume(&spotify, volume_percent)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn player_add_to_queue(
+    uri: String,
+    spotify: tauri::State<'_, Arc<SpotifyClient>>,
+) -> Result<(), String> {
+    add_to_queue(&spotify, &uri)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,613 @@
+//! Scrobbling to Last.fm and ListenBrainz. Plays that pass the scrobble
+//! rules go into a persistent queue, which is flushed right away and
+//! retried with backoff while a service is unreachable.
+
+mod lastfm;
+mod listenbrainz;
+mod queue;
+mod tracker;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+use crate::{
+    config::{ScrobbleSettings, SharedConfig},
+    playback::PlaybackHub,
+};
+use lastfm::Lastfm;
+use listenbrainz::Listenbrainz;
+use queue::ScrobbleQueue;
+
+const MIN_RETRY: Duration = Duration::from_secs(30);
+const MAX_RETRY: Duration = Duration::from_secs(30 * 60);
+
+/// Queued scrobbles for a service that was just enabled or came back are
+/// also picked up by this periodic check.
+const IDLE_FLUSH: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scrobble {
+    /// The primary artist; `artists` has all of them.
+    pub artist: String,
+    pub artists: Vec<String>,
+    pub track: String,
+    pub album: Option<String>,
+    pub duration_ms: u64,
+    /// When the track started, in Unix seconds.
+    pub timestamp: u64,
+    pub spotify_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum SubmitError {
+    /// Network trouble, rate limits or server errors.
+    Retry(String),
+    /// The service refused the request; sending it again will not help.
+    Rejected(String),
+    /// The credentials were refused; nothing will get through until the
+    /// user reconnects the service.
+    Unauthorized(String),
+}
+
+impl std::fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubmitError::Retry(e) | SubmitError::Rejected(e) | SubmitError::Unauthorized(e) => {
+                f.write_str(e)
+            }
+        }
+    }
+}
+
+#[async_trait]
+pub trait ScrobbleService: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn max_batch(&self) -> usize;
+    async fn now_playing(&self, track: &Scrobble) -> Result<(), SubmitError>;
+    async fn scrobble(&self, batch: &[Scrobble]) -> Result<(), SubmitError>;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceStatus {
+    pub service: String,
+    pub enabled: bool,
+    pub pending: usize,
+    /// The service refused our credentials; scrobbles wait in the queue
+    /// until it is reconnected.
+    pub needs_auth: bool,
+}
+
+pub struct Scrobbler {
+    config: SharedConfig,
+    http: reqwest::Client,
+    queue: Mutex<ScrobbleQueue>,
+    /// Services that refused our credentials. Cleared when the settings
+    /// change or the service is reconnected.
+    needs_auth: Mutex<HashSet<&'static str>>,
+    wake: Notify,
+}
+
+impl Scrobbler {
+    pub fn new(config: SharedConfig) -> Self {
+        Scrobbler {
+            config,
+            http: reqwest::Client::new(),
+            queue: Mutex::new(ScrobbleQueue::load()),
+            needs_auth: Mutex::new(HashSet::new()),
+            wake: Notify::new(),
+        }
+    }
+
+    fn needs_auth(&self, service: &str) -> bool {
+        self.needs_auth
+            .lock()
+            .map(|services| services.contains(service))
+            .unwrap_or(false)
+    }
+
+    fn set_needs_auth(&self, service: &'static str, needed: bool) {
+        if let Ok(mut services) = self.needs_auth.lock() {
+            if needed {
+                services.insert(service);
+            } else {
+                services.remove(service);
+            }
+        }
+    }
+
+    fn settings(&self) -> ScrobbleSettings {
+        self.config
+            .lock()
+            .map(|config| config.scrobbling.clone())
+            .unwrap_or_default()
+    }
+
+    fn lastfm(&self, settings: &ScrobbleSettings) -> Lastfm {
+        Lastfm {
+            http: self.http.clone(),
+            settings: settings.lastfm.clone(),
+        }
+    }
+
+    /// Services that are enabled and have credentials.
+    fn services(&self) -> Vec<Box<dyn ScrobbleService>> {
+        let settings = self.settings();
+        let mut services: Vec<Box<dyn ScrobbleService>> = Vec::new();
+
+        let lastfm = &settings.lastfm;
+        if lastfm.enabled && !lastfm.api_key.is_empty() && !lastfm.session_key.is_empty() {
+            services.push(Box::new(self.lastfm(&settings)));
+        }
+        let listenbrainz = &settings.listenbrainz;
+        if listenbrainz.enabled && !listenbrainz.token.is_empty() {
+            services.push(Box::new(Listenbrainz {
+                http: self.http.clone(),
+                settings: listenbrainz.clone(),
+            }));
+        }
+        services
+    }
+
+    /// Best effort; "now playing" is not worth retrying.
+    async fn now_playing(&self, track: &Scrobble) {
+        for service in self.services() {
+            if self.needs_auth(service.name()) {
+                continue;
+            }
+            match service.now_playing(track).await {
+                Ok(()) => {}
+                Err(SubmitError::Unauthorized(e)) => {
+                    eprintln!("{} refused our credentials: {}", service.name(), e);
+                    self.set_needs_auth(service.name(), true);
+                }
+                Err(e) => eprintln!("Failed to send now playing to {}: {}", service.name(), e),
+            }
+        }
+    }
+
+    fn enqueue(&self, track: Scrobble) {
+        let services = self.services();
+        if services.is_empty() {
+            return;
+        }
+        if let Ok(mut queue) = self.queue.lock() {
+            for service in &services {
+                queue.push(service.name(), track.clone());
+            }
+        }
+        self.wake.notify_one();
+    }
+
+    fn dequeue(&self, service: &str, count: usize) {
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.remove(service, count);
+        }
+    }
+
+    /// Submits `batch`, the oldest scrobbles queued for `service`, and takes
+    /// whatever the service settled off the queue. A batch refused as a
+    /// whole is resent one scrobble at a time, so only the scrobbles the
+    /// service refuses on their own are dropped.
+    async fn submit(
+        &self,
+        service: &dyn ScrobbleService,
+        batch: &[Scrobble],
+    ) -> Result<(), SubmitError> {
+        match service.scrobble(batch).await {
+            Ok(()) => {
+                self.dequeue(service.name(), batch.len());
+                Ok(())
+            }
+            Err(SubmitError::Rejected(e)) if batch.len() > 1 => {
+                eprintln!(
+                    "{} rejected a batch of {} scrobbles, sending them one at a time: {}",
+                    service.name(),
+                    batch.len(),
+                    e
+                );
+                for track in batch {
+                    match service.scrobble(std::slice::from_ref(track)).await {
+                        Ok(()) => {}
+                        Err(SubmitError::Rejected(e)) => eprintln!(
+                            "{} rejected {} - {}, dropping it: {}",
+                            service.name(),
+                            track.artist,
+                            track.track,
+                            e
+                        ),
+                        Err(e) => return Err(e),
+                    }
+                    self.dequeue(service.name(), 1);
+                }
+                Ok(())
+            }
+            Err(SubmitError::Rejected(e)) => {
+                eprintln!(
+                    "{} rejected {} - {}, dropping it: {}",
+                    service.name(),
+                    batch[0].artist,
+                    batch[0].track,
+                    e
+                );
+                self.dequeue(service.name(), 1);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Submits queued scrobbles oldest first. Returns true when a service
+    /// asked us to retry later.
+    async fn flush(&self) -> bool {
+        let mut retry = false;
+
+        for service in self.services() {
+            if self.needs_auth(service.name()) {
+                continue;
+            }
+            loop {
+                let batch = match self.queue.lock() {
+                    Ok(queue) => queue.peek(service.name(), service.max_batch()),
+                    Err(_) => break,
+                };
+                if batch.is_empty() {
+                    break;
+                }
+
+                match self.submit(service.as_ref(), &batch).await {
+                    Ok(()) => {}
+                    Err(SubmitError::Unauthorized(e)) => {
+                        eprintln!(
+                            "{} refused our credentials, keeping scrobbles until it is reconnected: {}",
+                            service.name(),
+                            e
+                        );
+                        self.set_needs_auth(service.name(), true);
+                        break;
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to scrobble to {}: {}", service.name(), e);
+                        retry = true;
+                        break;
+                    }
+                }
+            }
+        }
+        retry
+    }
+
+    fn status(&self) -> Vec<ServiceStatus> {
+        let settings = self.settings();
+        let pending = |service: &str| {
+            self.queue
+                .lock()
+                .map(|queue| queue.pending(service))
+                .unwrap_or(0)
+        };
+        vec![
+            ServiceStatus {
+                service: "lastfm".to_string(),
+                enabled: settings.lastfm.enabled,
+                pending: pending("lastfm"),
+                needs_auth: self.needs_auth("lastfm"),
+            },
+            ServiceStatus {
+                service: "listenbrainz".to_string(),
+                enabled: settings.listenbrainz.enabled,
+                pending: pending("listenbrainz"),
+                needs_auth: self.needs_auth("listenbrainz"),
+            },
+        ]
+    }
+}
+
+/// Starts following playback and flushing the queue.
+pub fn spawn_scrobbler(hub: Arc<PlaybackHub>, scrobbler: Arc<Scrobbler>) {
+    tracker::spawn_tracker(hub, scrobbler.clone());
+
+    tauri::async_runtime::spawn(async move {
+        let mut backoff = MIN_RETRY;
+        loop {
+            let wait = if scrobbler.flush().await {
+                let wait = backoff;
+                backoff = (backoff * 2).min(MAX_RETRY);
+                wait
+            } else {
+                backoff = MIN_RETRY;
+                IDLE_FLUSH
+            };
+
+            tokio::select! {
+                _ = scrobbler.wake.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub fn get_scrobble_settings(
+    config: tauri::State<'_, SharedConfig>,
+) -> Result<ScrobbleSettings, String> {
+    let config = config.lock().map_err(|e| e.to_string())?;
+    Ok(config.scrobbling.clone())
+}
+
+#[tauri::command]
+pub fn set_scrobble_settings(
+    settings: ScrobbleSettings,
+    config: tauri::State<'_, SharedConfig>,
+    scrobbler: tauri::State<'_, Arc<Scrobbler>>,
+) -> Result<(), String> {
+    {
+        let mut config = config.lock().map_err(|e| e.to_string())?;
+        config.scrobbling = settings;
+        config.save()?;
+    }
+    // New credentials get another chance, and a newly enabled service may
+    // have scrobbles waiting.
+    if let Ok(mut services) = scrobbler.needs_auth.lock() {
+        services.clear();
+    }
+    scrobbler.wake.notify_one();
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_scrobble_status(scrobbler: tauri::State<'_, Arc<Scrobbler>>) -> Vec<ServiceStatus> {
+    scrobbler.status()
+}
+
+/// First step of Last.fm desktop auth: gets a request token and opens the
+/// page where the user grants access. Pass the token to
+/// `lastfm_complete_auth` once they have.
+#[tauri::command]
+pub async fn lastfm_begin_auth(
+    scrobbler: tauri::State<'_, Arc<Scrobbler>>,
+) -> Result<String, String> {
+    let settings = scrobbler.settings();
+    if settings.lastfm.api_key.is_empty() || settings.lastfm.api_secret.is_empty() {
+        return Err("Set a Last.fm API key and secret first".to_string());
+    }
+
+    let response = scrobbler
+        .lastfm(&settings)
+        .call("auth.getToken", BTreeMap::new())
+        .await
+        .map_err(|e| e.to_string())?;
+    let token = response
+        .get("token")
+        .and_then(|t| t.as_str())
+        .ok_or("Last.fm did not return a token")?
+        .to_string();
+
+    let url = format!(
+        "{}?api_key={}&token={}",
+        settings.lastfm.auth_url,
+        urlencoding::encode(&settings.lastfm.api_key),
+        urlencoding::encode(&token)
+    );
+    if let Err(e) = open::that(&url) {
+        eprintln!("Failed to open Last.fm auth page: {}", e);
+    }
+    Ok(token)
+}
+
+/// Exchanges an authorized token for a session key and enables Last.fm.
+/// Returns the Last.fm username.
+#[tauri::command]
+pub async fn lastfm_complete_auth(
+    token: String,
+    config: tauri::State<'_, SharedConfig>,
+    scrobbler: tauri::State<'_, Arc<Scrobbler>>,
+) -> Result<String, String> {
+    let settings = scrobbler.settings();
+    let mut params = BTreeMap::new();
+    params.insert("token".to_string(), token);
+
+    let response = scrobbler
+        .lastfm(&settings)
+        .call("auth.getSession", params)
+        .await
+        .map_err(|e| e.to_string())?;
+    let session = response
+        .get("session")
+        .ok_or("Last.fm did not return a session")?;
+    let key = session
+        .get("key")
+        .and_then(|k| k.as_str())
+        .ok_or("Last.fm session has no key")?;
+    let name = session
+        .get("name")
+        .and_then(|n| n.as_str())
+        .unwrap_or_default()
+        .to_string();
+
+    {
+        let mut config = config.lock().map_err(|e| e.to_string())?;
+        config.scrobbling.lastfm.session_key = key.to_string();
+        config.scrobbling.lastfm.username = Some(name.clone());
+        config.scrobbling.lastfm.enabled = true;
+        config.save()?;
+    }
+    println!("Connected Last.fm account {}", name);
+    scrobbler.set_needs_auth("lastfm", false);
+    scrobbler.wake.notify_one();
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::{extract::State, routing::post, Form, Json, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    const SECRET: &str = "shh";
+
+    type Requests = Arc<Mutex<Vec<HashMap<String, String>>>>;
+
+    /// Stands in for the Last.fm API: records every request, refuses the
+    /// session key "expired", and refuses batches with a track titled
+    /// "Refused".
+    async fn last_fm(
+        State(requests): State<Requests>,
+        Form(params): Form<HashMap<String, String>>,
+    ) -> Json<Value> {
+        requests.lock().unwrap().push(params.clone());
+        if params.get("sk").map(String::as_str) == Some("expired") {
+            return Json(json!({ "error": 9, "message": "Invalid session key" }));
+        }
+        if params.values().any(|v| v == "Refused") {
+            return Json(json!({ "error": 6, "message": "Invalid parameters" }));
+        }
+        Json(json!({ "scrobbles": {} }))
+    }
+
+    async fn stand_in() -> (String, Requests) {
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/2.0/", post(last_fm))
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/2.0/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, requests)
+    }
+
+    fn scrobbler(base_url: String, session_key: &str) -> Scrobbler {
+        let mut config = Config::default();
+        config.scrobbling.lastfm = crate::config::LastfmSettings {
+            enabled: true,
+            api_key: "key".to_string(),
+            api_secret: SECRET.to_string(),
+            session_key: session_key.to_string(),
+            base_url,
+            ..Default::default()
+        };
+        Scrobbler {
+            config: Arc::new(Mutex::new(config)),
+            http: reqwest::Client::new(),
+            queue: Mutex::new(ScrobbleQueue::in_memory()),
+            needs_auth: Mutex::new(HashSet::new()),
+            wake: Notify::new(),
+        }
+    }
+
+    fn track(title: &str, timestamp: u64) -> Scrobble {
+        Scrobble {
+            artist: "Artist".to_string(),
+            artists: vec!["Artist".to_string()],
+            track: title.to_string(),
+            album: Some("Album".to_string()),
+            duration_ms: 200_000,
+            timestamp,
+            spotify_id: None,
+        }
+    }
+
+    fn queue(scrobbler: &Scrobbler, tracks: impl IntoIterator<Item = Scrobble>) {
+        let mut queue = scrobbler.queue.lock().unwrap();
+        for track in tracks {
+            queue.push("lastfm", track);
+        }
+    }
+
+    fn pending(scrobbler: &Scrobbler) -> usize {
+        scrobbler.queue.lock().unwrap().pending("lastfm")
+    }
+
+    fn expected_signature(params: &HashMap<String, String>) -> String {
+        let mut keys: Vec<&String> = params
+            .keys()
+            .filter(|k| *k != "api_sig" && *k != "format")
+            .collect();
+        keys.sort();
+        let mut base: String = keys
+            .into_iter()
+            .map(|k| format!("{}{}", k, params[k]))
+            .collect();
+        base.push_str(SECRET);
+        format!("{:x}", md5::compute(base))
+    }
+
+    fn batch_size(params: &HashMap<String, String>) -> usize {
+        params.keys().filter(|k| k.starts_with("track[")).count()
+    }
+
+    #[tokio::test]
+    async fn lastfm_scrobbles_are_signed_and_batched() {
+        let (url, requests) = stand_in().await;
+        let scrobbler = scrobbler(url, "session");
+        queue(
+            &scrobbler,
+            (0..60).map(|i| track(&format!("Song {}", i), 1_000 + i)),
+        );
+
+        assert!(!scrobbler.flush().await);
+        assert_eq!(pending(&scrobbler), 0);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests.iter().map(batch_size).collect::<Vec<_>>(),
+            [50, 10]
+        );
+        for params in requests.iter() {
+            assert_eq!(params["method"], "track.scrobble");
+            assert_eq!(params["api_key"], "key");
+            assert_eq!(params["sk"], "session");
+            assert_eq!(params["format"], "json");
+            assert_eq!(params["api_sig"], expected_signature(params));
+        }
+        assert_eq!(requests[1]["track[0]"], "Song 50");
+        assert_eq!(requests[1]["timestamp[9]"], "1059");
+    }
+
+    #[tokio::test]
+    async fn only_individually_rejected_scrobbles_are_dropped() {
+        let (url, requests) = stand_in().await;
+        let scrobbler = scrobbler(url, "session");
+        queue(
+            &scrobbler,
+            [track("One", 1), track("Refused", 2), track("Three", 3)],
+        );
+
+        assert!(!scrobbler.flush().await);
+        assert_eq!(pending(&scrobbler), 0);
+
+        let requests = requests.lock().unwrap();
+        let sizes: Vec<usize> = requests.iter().map(batch_size).collect();
+        assert_eq!(sizes, [3, 1, 1, 1]);
+        let singles: Vec<&str> = requests[1..]
+            .iter()
+            .map(|p| p["track[0]"].as_str())
+            .collect();
+        assert_eq!(singles, ["One", "Refused", "Three"]);
+    }
+
+    #[tokio::test]
+    async fn auth_errors_keep_the_queue_until_reconnected() {
+        let (url, requests) = stand_in().await;
+        let scrobbler = scrobbler(url, "expired");
+        queue(&scrobbler, [track("One", 1), track("Two", 2)]);
+
+        assert!(!scrobbler.flush().await);
+        assert_eq!(pending(&scrobbler), 2);
+        assert!(scrobbler.needs_auth("lastfm"));
+
+        // Nothing more is sent until the service is reconnected.
+        scrobbler.flush().await;
+        scrobbler.now_playing(&track("Three", 3)).await;
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        let status = scrobbler.status();
+        assert!(status[0].needs_auth && status[0].pending == 2);
+    }
+}
@@ -0,0 +1,138 @@
+//! Last.fm API 2.0. Every write call is signed with the shared secret and
+//! needs a session key from the desktop auth flow.
+
+use async_trait::async_trait;
+use serde_json::Value;
+use std::{collections::BTreeMap, time::Duration};
+
+use super::{Scrobble, ScrobbleService, SubmitError};
+use crate::config::LastfmSettings;
+
+/// Last.fm accepts at most this many scrobbles per request.
+const MAX_BATCH: usize = 50;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Error codes that mean "try again later": operation failed, service
+/// offline, temporarily unavailable and rate limited.
+const RETRYABLE_ERRORS: &[i64] = &[8, 11, 16, 29];
+
+/// Error codes that mean our credentials no longer work: authentication
+/// failed, invalid session key, invalid API key and suspended API key.
+const AUTH_ERRORS: &[i64] = &[4, 9, 10, 26];
+
+pub struct Lastfm {
+    pub http: reqwest::Client,
+    pub settings: LastfmSettings,
+}
+
+impl Lastfm {
+    /// `api_sig`: MD5 of every parameter as `keyvalue`, sorted by key, with
+    /// the secret appended. `format` is not part of the signature.
+    fn sign(&self, params: &mut BTreeMap<String, String>) {
+        params.insert("api_key".to_string(), self.settings.api_key.clone());
+        let mut base: String = params.iter().map(|(k, v)| format!("{}{}", k, v)).collect();
+        base.push_str(&self.settings.api_secret);
+        params.insert("api_sig".to_string(), format!("{:x}", md5::compute(base)));
+        params.insert("format".to_string(), "json".to_string());
+    }
+
+    pub async fn call(
+        &self,
+        method: &str,
+        mut params: BTreeMap<String, String>,
+    ) -> Result<Value, SubmitError> {
+        params.insert("method".to_string(), method.to_string());
+        self.sign(&mut params);
+
+        let response = self
+            .http
+            .post(&self.settings.base_url)
+            .form(&params)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| SubmitError::Retry(e.to_string()))?;
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+
+        if let Some(code) = body.get("error").and_then(Value::as_i64) {
+            let message = body
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown error");
+            let error = format!("Last.fm error {}: {}", code, message);
+            return Err(if RETRYABLE_ERRORS.contains(&code) {
+                SubmitError::Retry(error)
+            } else if AUTH_ERRORS.contains(&code) {
+                SubmitError::Unauthorized(error)
+            } else {
+                SubmitError::Rejected(error)
+            });
+        }
+        if status.is_server_error() {
+            return Err(SubmitError::Retry(format!("Last.fm returned {}", status)));
+        }
+        if !status.is_success() {
+            return Err(SubmitError::Rejected(format!(
+                "Last.fm returned {}",
+                status
+            )));
+        }
+        Ok(body)
+    }
+
+    fn session_params(&self) -> BTreeMap<String, String> {
+        let mut params = BTreeMap::new();
+        params.insert("sk".to_string(), self.settings.session_key.clone());
+        params
+    }
+}
+
+#[async_trait]
+impl ScrobbleService for Lastfm {
+    fn name(&self) -> &'static str {
+        "lastfm"
+    }
+
+    fn max_batch(&self) -> usize {
+        MAX_BATCH
+    }
+
+    async fn now_playing(&self, track: &Scrobble) -> Result<(), SubmitError> {
+        let mut params = self.session_params();
+        params.insert("artist".to_string(), track.artist.clone());
+        params.insert("track".to_string(), track.track.clone());
+        if let Some(album) = &track.album {
+            params.insert("album".to_string(), album.clone());
+        }
+        if track.duration_ms > 0 {
+            params.insert(
+                "duration".to_string(),
+                (track.duration_ms / 1000).to_string(),
+            );
+        }
+        self.call("track.updateNowPlaying", params)
+            .await
+            .map(|_| ())
+    }
+
+    async fn scrobble(&self, batch: &[Scrobble]) -> Result<(), SubmitError> {
+        let mut params = self.session_params();
+        for (i, track) in batch.iter().enumerate() {
+            params.insert(format!("artist[{}]", i), track.artist.clone());
+            params.insert(format!("track[{}]", i), track.track.clone());
+            params.insert(format!("timestamp[{}]", i), track.timestamp.to_string());
+            if let Some(album) = &track.album {
+                params.insert(format!("album[{}]", i), album.clone());
+            }
+            if track.duration_ms > 0 {
+                params.insert(
+                    format!("duration[{}]", i),
+                    (track.duration_ms / 1000).to_string(),
+                );
+            }
+        }
+        self.call("track.scrobble", params).await.map(|_| ())
+    }
+}
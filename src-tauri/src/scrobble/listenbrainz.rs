@@ -0,0 +1,107 @@
+//! ListenBrainz `submit-listens`, authenticated with a user token.
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use super::{Scrobble, ScrobbleService, SubmitError};
+use crate::config::ListenbrainzSettings;
+
+/// Well under the documented per-request limit of 1000 listens.
+const MAX_BATCH: usize = 100;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub struct Listenbrainz {
+    pub http: reqwest::Client,
+    pub settings: ListenbrainzSettings,
+}
+
+fn track_metadata(track: &Scrobble) -> Value {
+    let mut info = json!({
+        "submission_client": "spotify-widget",
+        "submission_client_version": env!("CARGO_PKG_VERSION"),
+        "music_service": "spotify.com",
+    });
+    if track.duration_ms > 0 {
+        info["duration_ms"] = json!(track.duration_ms);
+    }
+    if let Some(id) = &track.spotify_id {
+        info["spotify_id"] = json!(format!("https://open.spotify.com/track/{}", id));
+    }
+    if track.artists.len() > 1 {
+        info["artist_names"] = json!(track.artists);
+    }
+
+    let mut metadata = json!({
+        "artist_name": track.artist,
+        "track_name": track.track,
+        "additional_info": info,
+    });
+    if let Some(album) = &track.album {
+        metadata["release_name"] = json!(album);
+    }
+    metadata
+}
+
+impl Listenbrainz {
+    async fn submit(&self, listen_type: &str, payload: Vec<Value>) -> Result<(), SubmitError> {
+        let url = format!(
+            "{}/1/submit-listens",
+            self.settings.base_url.trim_end_matches('/')
+        );
+        let response = self
+            .http
+            .post(&url)
+            .header("Authorization", format!("Token {}", self.settings.token))
+            .json(&json!({ "listen_type": listen_type, "payload": payload }))
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| SubmitError::Retry(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body = response.text().await.unwrap_or_default();
+        let error = format!("ListenBrainz returned {}: {}", status, body.trim());
+        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Err(SubmitError::Retry(error))
+        } else if status == reqwest::StatusCode::UNAUTHORIZED {
+            Err(SubmitError::Unauthorized(error))
+        } else {
+            Err(SubmitError::Rejected(error))
+        }
+    }
+}
+
+#[async_trait]
+impl ScrobbleService for Listenbrainz {
+    fn name(&self) -> &'static str {
+        "listenbrainz"
+    }
+
+    fn max_batch(&self) -> usize {
+        MAX_BATCH
+    }
+
+    async fn now_playing(&self, track: &Scrobble) -> Result<(), SubmitError> {
+        let listen = json!({ "track_metadata": track_metadata(track) });
+        self.submit("playing_now", vec![listen]).await
+    }
+
+    async fn scrobble(&self, batch: &[Scrobble]) -> Result<(), SubmitError> {
+        let listens = batch
+            .iter()
+            .map(|track| {
+                json!({
+                    "listened_at": track.timestamp,
+                    "track_metadata": track_metadata(track),
+                })
+            })
+            .collect();
+        let listen_type = if batch.len() == 1 { "single" } else { "import" };
+        self.submit(listen_type, listens).await
+    }
+}
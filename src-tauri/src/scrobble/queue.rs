@@ -0,0 +1,96 @@
+//! Scrobbles waiting to be submitted, persisted so nothing is lost while
+//! offline or across restarts.
+
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+
+use super::Scrobble;
+use crate::config;
+
+const QUEUE_FILE: &str = "scrobble-queue.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedScrobble {
+    pub service: String,
+    pub scrobble: Scrobble,
+}
+
+#[derive(Debug, Default)]
+pub struct ScrobbleQueue {
+    entries: Vec<QueuedScrobble>,
+    /// False for a queue that only lives in memory.
+    persist: bool,
+}
+
+impl ScrobbleQueue {
+    fn path() -> PathBuf {
+        config::data_dir().join(QUEUE_FILE)
+    }
+
+    pub fn load() -> Self {
+        let entries = fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        ScrobbleQueue {
+            entries,
+            persist: true,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        ScrobbleQueue::default()
+    }
+
+    fn save(&self) {
+        if !self.persist {
+            return;
+        }
+        let result = serde_json::to_vec(&self.entries)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| config::write_atomic(&Self::path(), &contents));
+        if let Err(e) = result {
+            eprintln!("Failed to save scrobble queue: {}", e);
+        }
+    }
+
+    pub fn push(&mut self, service: &str, scrobble: Scrobble) {
+        self.entries.push(QueuedScrobble {
+            service: service.to_string(),
+            scrobble,
+        });
+        self.save();
+    }
+
+    /// The oldest `limit` scrobbles for `service`, in submission order.
+    pub fn peek(&self, service: &str, limit: usize) -> Vec<Scrobble> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.service == service)
+            .take(limit)
+            .map(|entry| entry.scrobble.clone())
+            .collect()
+    }
+
+    /// Drops the oldest `count` scrobbles for `service`.
+    pub fn remove(&mut self, service: &str, count: usize) {
+        let mut removed = 0;
+        self.entries.retain(|entry| {
+            if removed < count && entry.service == service {
+                removed += 1;
+                false
+            } else {
+                true
+            }
+        });
+        self.save();
+    }
+
+    pub fn pending(&self, service: &str) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.service == service)
+            .count()
+    }
+}
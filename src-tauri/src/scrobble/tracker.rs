@@ -0,0 +1,140 @@
+//! Applies the scrobble rules to playback: a track longer than 30 seconds
+//! is scrobbled once it has played for half its length or four minutes,
+//! whichever comes first. Only time that passed while playing counts.
+
+use std::{sync::Arc, time::Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use super::{Scrobble, Scrobbler};
+use crate::playback::{PlaybackEvent, PlaybackHub, PlaybackSnapshot};
+
+const MIN_DURATION_MS: u64 = 30_000;
+const MAX_THRESHOLD_MS: u64 = 240_000;
+
+/// Poll jitter allowed on top of the wall-clock time between two polls.
+const PROGRESS_SLACK_MS: u64 = 1_500;
+
+/// Replaying a track shows up as the position jumping back by more than
+/// this from near the end.
+const RESTART_MARGIN_MS: u64 = 10_000;
+
+struct Session {
+    track: Scrobble,
+    uri: String,
+    duration_ms: u64,
+    played_ms: u64,
+    last_progress: u64,
+    last_seen: Instant,
+    playing: bool,
+    announced: bool,
+    scrobbled: bool,
+}
+
+impl Session {
+    fn threshold_ms(&self) -> u64 {
+        (self.duration_ms / 2).min(MAX_THRESHOLD_MS)
+    }
+
+    fn restarted(&self, progress_ms: u64) -> bool {
+        self.last_progress + RESTART_MARGIN_MS >= self.duration_ms
+            && progress_ms + RESTART_MARGIN_MS < self.last_progress
+    }
+}
+
+/// Only music with enough metadata to identify it can be scrobbled.
+fn scrobble_for(snapshot: &PlaybackSnapshot) -> Option<Scrobble> {
+    if snapshot.item_type != "track" || snapshot.duration_ms <= MIN_DURATION_MS {
+        return None;
+    }
+    let artist = snapshot.artists.first()?.clone();
+    if snapshot.title.trim().is_empty() {
+        return None;
+    }
+
+    Some(Scrobble {
+        artist,
+        artists: snapshot.artists.clone(),
+        track: snapshot.title.clone(),
+        album: snapshot.album.clone(),
+        duration_ms: snapshot.duration_ms,
+        // The first poll that sees a track may come well into it.
+        timestamp: snapshot.observed_at.saturating_sub(snapshot.progress_ms) / 1000,
+        spotify_id: snapshot.track_id.clone().filter(|_| !snapshot.is_local),
+    })
+}
+
+fn new_session(snapshot: &PlaybackSnapshot) -> Option<Session> {
+    Some(Session {
+        track: scrobble_for(snapshot)?,
+        uri: snapshot.uri.clone(),
+        duration_ms: snapshot.duration_ms,
+        played_ms: 0,
+        last_progress: snapshot.progress_ms,
+        last_seen: Instant::now(),
+        playing: snapshot.is_playing,
+        announced: false,
+        scrobbled: false,
+    })
+}
+
+pub fn spawn_tracker(hub: Arc<PlaybackHub>, scrobbler: Arc<Scrobbler>) {
+    let mut events = hub.subscribe();
+
+    tauri::async_runtime::spawn(async move {
+        let mut session: Option<Session> = None;
+
+        loop {
+            let snapshot = match events.recv().await {
+                Ok(PlaybackEvent::Progress(snapshot)) => snapshot,
+                Ok(PlaybackEvent::TrackChanged(_)) => continue,
+                Ok(PlaybackEvent::Stopped) => {
+                    session = None;
+                    continue;
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+
+            let restart = match &session {
+                Some(current) => {
+                    current.uri != snapshot.uri || current.restarted(snapshot.progress_ms)
+                }
+                None => true,
+            };
+            if restart {
+                session = new_session(&snapshot);
+            } else if let Some(current) = session.as_mut() {
+                if current.playing {
+                    let elapsed = current.last_seen.elapsed().as_millis() as u64;
+                    let advanced = snapshot.progress_ms.saturating_sub(current.last_progress);
+                    current.played_ms += advanced.min(elapsed + PROGRESS_SLACK_MS);
+                }
+                current.last_progress = snapshot.progress_ms;
+                current.last_seen = Instant::now();
+                current.playing = snapshot.is_playing;
+            }
+
+            let Some(current) = session.as_mut() else {
+                continue;
+            };
+            // Sent in the background so a slow service cannot hold up the
+            // play time tracking above.
+            if current.playing && !current.announced {
+                current.announced = true;
+                let scrobbler = scrobbler.clone();
+                let track = current.track.clone();
+                tauri::async_runtime::spawn(async move {
+                    scrobbler.now_playing(&track).await;
+                });
+            }
+            if !current.scrobbled && current.played_ms >= current.threshold_ms() {
+                current.scrobbled = true;
+                println!(
+                    "Scrobbling {} - {}",
+                    current.track.artist, current.track.track
+                );
+                scrobbler.enqueue(current.track.clone());
+            }
+        }
+    });
+}
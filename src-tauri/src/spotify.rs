@@ -0,0 +1,353 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{Notify, RwLock};
+
+use crate::config;
+
+const API_BASE: &str = "https://api.spotify.com/v1";
+const SESSION_FILE: &str = "session.json";
+
+/// Tokens are treated as expired slightly early so a request never races the
+/// real expiry.
+const EXPIRY_MARGIN_SECS: u64 = 30;
+
+/// OAuth session shared with the webview. The frontend keeps its own copy in
+/// localStorage; the backend copy lets background tasks call the Web API
+/// without a window being open.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub client_id: Option<String>,
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub expires_at: u64,
+}
+
+/// Why a Web API call failed, kept apart so callers such as the local API
+/// can tell a missing login from a rejected request.
+#[derive(Debug, Clone)]
+pub enum SpotifyError {
+    NotAuthenticated,
+    /// Spotify answered with a non-success status.
+    Api {
+        status: u16,
+        body: String,
+    },
+    /// The request never got an answer, or the answer did not parse.
+    Request(String),
+}
+
+impl fmt::Display for SpotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpotifyError::NotAuthenticated => write!(f, "Not authenticated with Spotify"),
+            SpotifyError::Api { status, body } => {
+                write!(f, "Spotify API error {}: {}", status, body)
+            }
+            SpotifyError::Request(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<SpotifyError> for String {
+    fn from(error: SpotifyError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Image {
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artist {
+    pub id: Option<String>,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Album {
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub images: Vec<Image>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Show {
+    pub name: String,
+    pub publisher: Option<String>,
+    #[serde(default)]
+    pub images: Vec<Image>,
+}
+
+/// A track or podcast episode. Episodes have a `show` instead of an album
+/// and artists, so everything type-specific is optional.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayableItem {
+    pub id: Option<String>,
+    pub name: String,
+    pub uri: String,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub artists: Vec<Artist>,
+    pub album: Option<Album>,
+    pub show: Option<Show>,
+    #[serde(default)]
+    pub images: Vec<Image>,
+    #[serde(default)]
+    pub is_local: bool,
+    #[serde(rename = "type", default)]
+    pub item_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Device {
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub device_type: String,
+    pub volume_percent: Option<u32>,
+    #[serde(default)]
+    pub is_active: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackContext {
+    pub uri: String,
+    #[serde(rename = "type")]
+    pub context_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackState {
+    pub device: Option<Device>,
+    pub context: Option<PlaybackContext>,
+    pub progress_ms: Option<u64>,
+    pub item: Option<PlayableItem>,
+    #[serde(default)]
+    pub is_playing: bool,
+    #[serde(default)]
+    pub shuffle_state: bool,
+    #[serde(default)]
+    pub repeat_state: String,
+    #[serde(default)]
+    pub currently_playing_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Queue {
+    pub currently_playing: Option<PlayableItem>,
+    #[serde(default)]
+    pub queue: Vec<PlayableItem>,
+}
+
+impl PlayableItem {
+    pub fn artist_names(&self) -> Vec<String> {
+        if self.artists.is_empty() {
+            if let Some(show) = &self.show {
+                return vec![show.publisher.clone().unwrap_or_else(|| show.name.clone())];
+            }
+        }
+        self.artists.iter().map(|a| a.name.clone()).collect()
+    }
+
+    pub fn album_name(&self) -> Option<String> {
+        self.album
+            .as_ref()
+            .map(|a| a.name.clone())
+            .or_else(|| self.show.as_ref().map(|s| s.name.clone()))
+    }
+
+    /// The largest available cover image.
+    pub fn image_url(&self) -> Option<String> {
+        let images = match (&self.album, &self.show) {
+            (Some(album), _) if !album.images.is_empty() => &album.images,
+            (_, Some(show)) if !show.images.is_empty() => &show.images,
+            _ => &self.images,
+        };
+        images
+            .iter()
+            .max_by_key(|i| i.width.unwrap_or(0))
+            .map(|i| i.url.clone())
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub struct SpotifyClient {
+    http: reqwest::Client,
+    session: RwLock<Session>,
+    token_stored: Notify,
+}
+
+impl SpotifyClient {
+    fn session_path() -> PathBuf {
+        config::data_dir().join(SESSION_FILE)
+    }
+
+    pub fn load() -> Self {
+        let session = fs::read_to_string(Self::session_path())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        SpotifyClient {
+            http: reqwest::Client::new(),
+            session: RwLock::new(session),
+            token_stored: Notify::new(),
+        }
+    }
+
+    pub async fn session(&self) -> Session {
+        self.session.read().await.clone()
+    }
+
+    pub async fn set_client_id(&self, client_id: String) {
+        let mut session = self.session.write().await;
+        session.client_id = Some(client_id);
+        persist(&session);
+    }
+
+    /// Records a token from the OAuth callback or a refresh. Spotify only
+    /// sometimes rotates the refresh token, so an absent one keeps the old.
+    pub async fn store_token(
+        &self,
+        access_token: String,
+        refresh_token: Option<String>,
+        expires_in: Option<Duration>,
+    ) {
+        let mut session = self.session.write().await;
+        session.access_token = Some(access_token);
+        if refresh_token.is_some() {
+            session.refresh_token = refresh_token;
+        }
+        session.expires_at = now_secs() + expires_in.map(|d| d.as_secs()).unwrap_or(3600);
+        persist(&session);
+        self.token_stored.notify_waiters();
+    }
+
+    /// Resolves the next time a token is stored.
+    pub async fn token_stored(&self) {
+        self.token_stored.notified().await
+    }
+
+    pub async fn access_token(&self) -> Option<String> {
+        let session = self.session.read().await;
+        if session.expires_at <= now_secs() + EXPIRY_MARGIN_SECS {
+            return None;
+        }
+        session.access_token.clone()
+    }
+
+    pub async fn is_authenticated(&self) -> bool {
+        self.access_token().await.is_some()
+    }
+
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+    ) -> Result<reqwest::RequestBuilder, SpotifyError> {
+        let token = self
+            .access_token()
+            .await
+            .ok_or(SpotifyError::NotAuthenticated)?;
+
+        Ok(self
+            .http
+            .request(method, format!("{}{}", API_BASE, path))
+            .bearer_auth(token))
+    }
+
+    /// GETs a Web API endpoint. `204 No Content` maps to `None`, which is how
+    /// the player endpoints report that nothing is playing.
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<Option<T>, SpotifyError> {
+        let response = self
+            .request(reqwest::Method::GET, path)
+            .await?
+            .send()
+            .await
+            .map_err(|e| SpotifyError::Request(e.to_string()))?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(SpotifyError::Api {
+                status: status.as_u16(),
+                body,
+            });
+        }
+
+        response
+            .json::<T>()
+            .await
+            .map(Some)
+            .map_err(|e| SpotifyError::Request(e.to_string()))
+    }
+
+    /// Sends a player command. Their responses carry no body worth reading,
+    /// so any success status is enough.
+    pub async fn send(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<(), SpotifyError> {
+        let mut request = self.request(method, path).await?;
+        request = match body {
+            Some(body) => request.json(&body),
+            // Spotify rejects bodiless PUT/POST requests without a length.
+            None => request.header(reqwest::header::CONTENT_LENGTH, 0),
+        };
+        let response = request
+            .send()
+            .await
+            .map_err(|e| SpotifyError::Request(e.to_string()))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(SpotifyError::Api {
+                status: status.as_u16(),
+                body,
+            });
+        }
+        Ok(())
+    }
+
+    pub async fn current_playback(&self) -> Result<Option<PlaybackState>, SpotifyError> {
+        self.get("/me/player?additional_types=episode").await
+    }
+
+    pub async fn queue(&self) -> Result<Queue, SpotifyError> {
+        self.get("/me/player/queue").await?.ok_or_else(|| {
+            SpotifyError::Request("Spotify returned an empty queue response".to_string())
+        })
+    }
+}
+
+fn persist(session: &Session) {
+    let result = serde_json::to_vec_pretty(session)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| config::write_private(&SpotifyClient::session_path(), &contents));
+
+    if let Err(e) = result {
+        eprintln!("Failed to persist Spotify session: {}", e);
+    }
+}
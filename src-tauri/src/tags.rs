@@ -0,0 +1,1547 @@
+//! Minimal readers for the tag formats found in typical music libraries:
+//! ID3v2 (MP3), FLAC metadata blocks, Ogg Vorbis/Opus comments and MP4 `ilst`
+//! atoms. Only the fields the widget uses are decoded, plus the embedded
+//! cover picture and lyrics.
+
+use base64::Engine;
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Upper bound on any single tag structure we are willing to buffer, so a
+/// corrupt length field cannot make us allocate gigabytes.
+const MAX_TAG_BYTES: u64 = 64 * 1024 * 1024;
+
+pub use id3::decode_text;
+
+/// The "Cover (front)" picture type shared by ID3 APIC frames and FLAC
+/// PICTURE blocks.
+pub const PICTURE_FRONT_COVER: u8 = 3;
+
+pub const AUDIO_EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "oga", "opus", "m4a", "mp4", "aac"];
+
+#[derive(Debug, Clone, Default)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub duration_ms: Option<u64>,
+    pub picture: Option<Picture>,
+    /// Plain lyrics text (ID3 USLT, Vorbis LYRICS, MP4 `©lyr`). Some taggers
+    /// store LRC here, so it may still carry timestamps.
+    pub lyrics: Option<String>,
+    /// ID3 SYLT lines as (milliseconds, text).
+    pub synced_lyrics: Vec<(u64, String)>,
+}
+
+#[derive(Clone, Default)]
+pub struct Picture {
+    /// As declared by the tag; often missing or wrong, so prefer
+    /// [`Picture::extension`] which sniffs the data first.
+    pub mime: String,
+    pub picture_type: u8,
+    pub data: Vec<u8>,
+}
+
+impl std::fmt::Debug for Picture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Picture")
+            .field("mime", &self.mime)
+            .field("picture_type", &self.picture_type)
+            .field("bytes", &self.data.len())
+            .finish()
+    }
+}
+
+impl Picture {
+    pub fn extension(&self) -> &'static str {
+        let data = &self.data;
+        if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            return "jpg";
+        }
+        if data.starts_with(b"\x89PNG") {
+            return "png";
+        }
+        if data.starts_with(b"GIF8") {
+            return "gif";
+        }
+        if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            return "webp";
+        }
+        if data.starts_with(b"BM") {
+            return "bmp";
+        }
+
+        let mime = self.mime.to_ascii_lowercase();
+        ["png", "gif", "webp", "bmp"]
+            .into_iter()
+            .find(|ext| mime.contains(ext))
+            .unwrap_or("jpg")
+    }
+
+    /// Lower is better: front covers first, then untyped pictures, then
+    /// anything else, with file icons last.
+    fn rank(&self) -> u8 {
+        match self.picture_type {
+            PICTURE_FRONT_COVER => 0,
+            0 => 1,
+            1 | 2 => 3,
+            _ => 2,
+        }
+    }
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| AUDIO_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+pub fn read_tags(path: &Path) -> Result<TrackTags, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0u8; 12];
+    let read = read_up_to(&mut reader, &mut magic)?;
+    reader.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+    let magic = &magic[..read];
+
+    let mut tags = TrackTags::default();
+    if magic.starts_with(b"ID3") {
+        let tag_end = id3::read(&mut reader, &mut tags)?;
+        // FLAC files occasionally carry a stray ID3 header in front.
+        let mut flac_magic = [0u8; 4];
+        if read_up_to(&mut reader, &mut flac_magic)? == 4 && &flac_magic == b"fLaC" {
+            reader
+                .seek(SeekFrom::Start(tag_end))
+                .map_err(|e| e.to_string())?;
+            flac::read(&mut reader, &mut tags)?;
+        }
+    } else if magic.starts_with(b"fLaC") {
+        flac::read(&mut reader, &mut tags)?;
+    } else if magic.starts_with(b"OggS") {
+        ogg::read(&mut reader, &mut tags)?;
+    } else if magic.len() >= 8 && &magic[4..8] == b"ftyp" {
+        mp4::read(&mut reader, &mut tags)?;
+    } else if magic.len() >= 2 && magic[0] == 0xff && magic[1] & 0xe0 == 0xe0 {
+        // Bare MPEG audio; only an ID3v1 trailer can carry tags.
+        id3::read_v1(&mut reader, &mut tags)?;
+    } else {
+        return Err(format!("Unrecognised audio format: {}", path.display()));
+    }
+
+    Ok(tags)
+}
+
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, String> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(filled)
+}
+
+fn read_exact_vec<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>, String> {
+    if len > MAX_TAG_BYTES {
+        return Err(format!("Tag block too large ({} bytes)", len));
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf).map_err(|e| e.to_string())?;
+    Ok(buf)
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn non_empty(value: String) -> Option<String> {
+    let trimmed = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.to_string())
+    }
+}
+
+/// Parses "3" or "3/12" style track and disc numbers.
+fn parse_number(value: &str) -> Option<u32> {
+    value.split('/').next()?.trim().parse().ok()
+}
+
+/// Applies a Vorbis-comment style `KEY=value` pair. Also used for MP4 atoms
+/// after mapping their four-character names.
+fn apply_comment(tags: &mut TrackTags, key: &str, value: String) {
+    let set = |slot: &mut Option<String>, value: String| {
+        if slot.is_none() {
+            *slot = non_empty(value);
+        }
+    };
+
+    match key.to_ascii_uppercase().as_str() {
+        "TITLE" => set(&mut tags.title, value),
+        "ARTIST" => set(&mut tags.artist, value),
+        "ALBUM" => set(&mut tags.album, value),
+        "ALBUMARTIST" | "ALBUM ARTIST" | "ALBUM_ARTIST" => set(&mut tags.album_artist, value),
+        "TRACKNUMBER" => tags.track_number = tags.track_number.or(parse_number(&value)),
+        "DISCNUMBER" => tags.disc_number = tags.disc_number.or(parse_number(&value)),
+        "LYRICS" | "UNSYNCEDLYRICS" | "UNSYNCED LYRICS" => set(&mut tags.lyrics, value),
+        "METADATA_BLOCK_PICTURE" => {
+            let picture = base64::engine::general_purpose::STANDARD
+                .decode(value.trim())
+                .ok()
+                .and_then(|block| parse_flac_picture(&block));
+            if let Some(picture) = picture {
+                offer_picture(tags, picture);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Keeps `picture` if it beats the one already found; on a tie the first
+/// picture in the file wins.
+fn offer_picture(tags: &mut TrackTags, picture: Picture) {
+    if picture.data.is_empty() {
+        return;
+    }
+    if tags
+        .picture
+        .as_ref()
+        .map(|current| picture.rank() < current.rank())
+        .unwrap_or(true)
+    {
+        tags.picture = Some(picture);
+    }
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+    let slice = data.get(*pos..pos.checked_add(len)?)?;
+    *pos += len;
+    Some(slice)
+}
+
+/// Parses the FLAC PICTURE block layout, which Vorbis comments also reuse
+/// (base64-encoded) as `METADATA_BLOCK_PICTURE`.
+fn parse_flac_picture(block: &[u8]) -> Option<Picture> {
+    let mut pos = 0;
+    let picture_type = be_u32(take(block, &mut pos, 4)?);
+    let mime_len = be_u32(take(block, &mut pos, 4)?) as usize;
+    let mime = String::from_utf8_lossy(take(block, &mut pos, mime_len)?).into_owned();
+    let description_len = be_u32(take(block, &mut pos, 4)?) as usize;
+    take(block, &mut pos, description_len)?;
+    take(block, &mut pos, 16)?; // width, height, colour depth, palette size
+    let data_len = be_u32(take(block, &mut pos, 4)?) as usize;
+    let data = take(block, &mut pos, data_len)?.to_vec();
+
+    Some(Picture {
+        mime,
+        picture_type: picture_type.min(u8::MAX as u32) as u8,
+        data,
+    })
+}
+
+fn apply_vorbis_comments(tags: &mut TrackTags, data: &[u8]) -> Result<(), String> {
+    let truncated = || "Truncated Vorbis comment block".to_string();
+
+    let vendor_len = le_u32(data.get(0..4).ok_or_else(truncated)?) as usize;
+    let mut pos = 4 + vendor_len;
+    let count = le_u32(data.get(pos..pos + 4).ok_or_else(truncated)?);
+    pos += 4;
+
+    for _ in 0..count {
+        let len = le_u32(data.get(pos..pos + 4).ok_or_else(truncated)?) as usize;
+        pos += 4;
+        let entry = data.get(pos..pos + len).ok_or_else(truncated)?;
+        pos += len;
+
+        let entry = String::from_utf8_lossy(entry);
+        if let Some((key, value)) = entry.split_once('=') {
+            apply_comment(tags, key, value.to_string());
+        }
+    }
+    Ok(())
+}
+
+mod id3 {
+    use super::*;
+
+    fn syncsafe(bytes: &[u8]) -> u32 {
+        bytes
+            .iter()
+            .take(4)
+            .fold(0u32, |acc, b| (acc << 7) | (*b as u32 & 0x7f))
+    }
+
+    /// Reverses ID3 unsynchronisation: every `FF 00` pair becomes `FF`.
+    fn remove_unsync(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            out.push(data[i]);
+            if data[i] == 0xff && data.get(i + 1) == Some(&0x00) {
+                i += 1;
+            }
+            i += 1;
+        }
+        out
+    }
+
+    /// Decodes text in one of the four ID3 encodings.
+    pub fn decode_text(encoding: u8, data: &[u8]) -> String {
+        match encoding {
+            1 | 2 => {
+                let (big_endian, body) = match data {
+                    [0xfe, 0xff, rest @ ..] => (true, rest),
+                    [0xff, 0xfe, rest @ ..] => (false, rest),
+                    _ => (encoding == 2, data),
+                };
+                let units: Vec<u16> = body
+                    .chunks_exact(2)
+                    .map(|c| {
+                        if big_endian {
+                            u16::from_be_bytes([c[0], c[1]])
+                        } else {
+                            u16::from_le_bytes([c[0], c[1]])
+                        }
+                    })
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            3 => String::from_utf8_lossy(data).into_owned(),
+            _ => data.iter().map(|&b| b as char).collect(),
+        }
+    }
+
+    /// Text frames may hold several null-separated values; we keep the first.
+    fn first_value(encoding: u8, data: &[u8]) -> String {
+        let text = decode_text(encoding, data);
+        text.split('\0').next().unwrap_or("").to_string()
+    }
+
+    /// Reads the tag at the current position and returns the offset just
+    /// past it.
+    pub fn read<R: Read + Seek>(reader: &mut R, tags: &mut TrackTags) -> Result<u64, String> {
+        let mut header = [0u8; 10];
+        reader.read_exact(&mut header).map_err(|e| e.to_string())?;
+
+        let version = header[3];
+        let flags = header[5];
+        let size = syncsafe(&header[6..10]) as u64;
+        let tag_end = 10 + size;
+
+        if !(2..=4).contains(&version) {
+            reader
+                .seek(SeekFrom::Start(tag_end))
+                .map_err(|e| e.to_string())?;
+            return Ok(tag_end);
+        }
+
+        let mut data = read_exact_vec(reader, size)?;
+        if flags & 0x80 != 0 && version < 4 {
+            data = remove_unsync(&data);
+        }
+
+        let mut pos = 0usize;
+        if flags & 0x40 != 0 && version >= 3 {
+            let ext = data.get(0..4).ok_or("Truncated ID3 extended header")?;
+            pos = if version == 3 {
+                4 + be_u32(ext) as usize
+            } else {
+                syncsafe(ext) as usize
+            };
+        }
+
+        let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+
+        while pos + header_len <= data.len() {
+            let frame = &data[pos..pos + header_len];
+            if frame[0] == 0 {
+                break; // padding
+            }
+
+            let id = String::from_utf8_lossy(&frame[..id_len]).into_owned();
+            let frame_size = match version {
+                2 => u32::from_be_bytes([0, frame[3], frame[4], frame[5]]) as usize,
+                3 => be_u32(&frame[4..8]) as usize,
+                _ => syncsafe(&frame[4..8]) as usize,
+            };
+            let format_flags = if version == 2 { 0 } else { frame[9] };
+
+            let start = pos + header_len;
+            let end = start + frame_size;
+            if end > data.len() {
+                break;
+            }
+            pos = end;
+
+            let mut body = data[start..end].to_vec();
+            if version == 4 {
+                // Compressed or encrypted frames are skipped.
+                if format_flags & 0x0c != 0 {
+                    continue;
+                }
+                if format_flags & 0x02 != 0 || flags & 0x80 != 0 {
+                    body = remove_unsync(&body);
+                }
+                if format_flags & 0x01 != 0 && body.len() >= 4 {
+                    body.drain(..4);
+                }
+            } else if version == 3 && format_flags & 0xc0 != 0 {
+                continue;
+            }
+
+            apply_frame(tags, &id, &body);
+        }
+
+        reader
+            .seek(SeekFrom::Start(tag_end))
+            .map_err(|e| e.to_string())?;
+        Ok(tag_end)
+    }
+
+    /// ID3v1 is a fixed 128-byte block at the end of the file with
+    /// 30-byte Latin-1 fields.
+    pub fn read_v1<R: Read + Seek>(reader: &mut R, tags: &mut TrackTags) -> Result<(), String> {
+        let file_len = reader.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+        if file_len < 128 {
+            return Ok(());
+        }
+        reader
+            .seek(SeekFrom::Start(file_len - 128))
+            .map_err(|e| e.to_string())?;
+        let block = read_exact_vec(reader, 128)?;
+        if !block.starts_with(b"TAG") {
+            return Ok(());
+        }
+
+        let field = |range: std::ops::Range<usize>| {
+            let bytes = &block[range];
+            let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+            decode_text(0, &bytes[..end])
+        };
+        apply_comment(tags, "TITLE", field(3..33));
+        apply_comment(tags, "ARTIST", field(33..63));
+        apply_comment(tags, "ALBUM", field(63..93));
+
+        // ID3v1.1 stores the track number in the last byte of the comment.
+        if block[125] == 0 && block[126] != 0 {
+            tags.track_number = tags.track_number.or(Some(block[126] as u32));
+        }
+        Ok(())
+    }
+
+    /// Skips a null-terminated string in the given encoding; UTF-16 strings
+    /// end with a two-byte null on an even offset.
+    fn skip_terminated(encoding: u8, data: &[u8]) -> Option<&[u8]> {
+        if encoding == 1 || encoding == 2 {
+            let end = data.chunks_exact(2).position(|c| c == [0, 0])?;
+            data.get(end * 2 + 2..)
+        } else {
+            let end = data.iter().position(|b| *b == 0)?;
+            data.get(end + 1..)
+        }
+    }
+
+    /// APIC (v2.3/2.4) stores a MIME type; v2.2's PIC a three-letter format.
+    fn parse_picture(id: &str, body: &[u8]) -> Option<Picture> {
+        let (&encoding, rest) = body.split_first()?;
+
+        let (mime, rest) = if id == "PIC" {
+            let format = String::from_utf8_lossy(rest.get(..3)?).to_ascii_lowercase();
+            (format!("image/{}", format), &rest[3..])
+        } else {
+            let end = rest.iter().position(|b| *b == 0)?;
+            let mime = String::from_utf8_lossy(&rest[..end]).into_owned();
+            (mime, &rest[end + 1..])
+        };
+        // "-->" means the frame holds a URL rather than the image itself.
+        if mime == "-->" {
+            return None;
+        }
+
+        let (&picture_type, rest) = rest.split_first()?;
+        let data = skip_terminated(encoding, rest)?;
+
+        Some(Picture {
+            mime,
+            picture_type,
+            data: data.to_vec(),
+        })
+    }
+
+    /// USLT: encoding, language, content descriptor, then the text.
+    fn parse_uslt(body: &[u8]) -> Option<String> {
+        let (&encoding, rest) = body.split_first()?;
+        let text = skip_terminated(encoding, rest.get(3..)?)?;
+        Some(decode_text(encoding, text))
+    }
+
+    /// SYLT: encoding, language, timestamp format, content type and
+    /// descriptor, then text/timestamp pairs. Only millisecond timestamps
+    /// are supported; MPEG frame counts need the bitrate to convert.
+    fn parse_sylt(body: &[u8]) -> Vec<(u64, String)> {
+        let mut lines = Vec::new();
+        let Some((&encoding, rest)) = body.split_first() else {
+            return lines;
+        };
+        if rest.get(3) != Some(&2) {
+            return lines;
+        }
+        let Some(mut rest) = rest.get(5..).and_then(|r| skip_terminated(encoding, r)) else {
+            return lines;
+        };
+
+        while !rest.is_empty() {
+            let Some(after_text) = skip_terminated(encoding, rest) else {
+                break;
+            };
+            let text_len = rest.len() - after_text.len();
+            let terminator = if encoding == 1 || encoding == 2 { 2 } else { 1 };
+            let text = decode_text(encoding, &rest[..text_len - terminator]);
+
+            let Some(stamp) = after_text.get(..4) else {
+                break;
+            };
+            lines.push((be_u32(stamp) as u64, text));
+            rest = &after_text[4..];
+        }
+        lines
+    }
+
+    fn apply_frame(tags: &mut TrackTags, id: &str, body: &[u8]) {
+        match id {
+            "APIC" | "PIC" => {
+                if let Some(picture) = parse_picture(id, body) {
+                    offer_picture(tags, picture);
+                }
+                return;
+            }
+            "USLT" | "ULT" => {
+                if let Some(text) = parse_uslt(body) {
+                    apply_comment(tags, "LYRICS", text);
+                }
+                return;
+            }
+            "SYLT" | "SLT" => {
+                if tags.synced_lyrics.is_empty() {
+                    tags.synced_lyrics = parse_sylt(body);
+                }
+                return;
+            }
+            _ => {}
+        }
+        if body.is_empty() || !id.starts_with('T') {
+            return;
+        }
+        let value = first_value(body[0], &body[1..]);
+
+        match id {
+            "TIT2" | "TT2" => apply_comment(tags, "TITLE", value),
+            "TPE1" | "TP1" => apply_comment(tags, "ARTIST", value),
+            "TALB" | "TAL" => apply_comment(tags, "ALBUM", value),
+            "TPE2" | "TP2" => apply_comment(tags, "ALBUMARTIST", value),
+            "TRCK" | "TRK" => apply_comment(tags, "TRACKNUMBER", value),
+            "TPOS" | "TPA" => apply_comment(tags, "DISCNUMBER", value),
+            "TLEN" | "TLE" if tags.duration_ms.is_none() => {
+                tags.duration_ms = value.trim().parse().ok().filter(|ms| *ms > 0);
+            }
+            _ => {}
+        }
+    }
+}
+
+mod flac {
+    use super::*;
+
+    const STREAMINFO: u8 = 0;
+    const VORBIS_COMMENT: u8 = 4;
+    const PICTURE: u8 = 6;
+
+    pub fn read<R: Read + Seek>(reader: &mut R, tags: &mut TrackTags) -> Result<(), String> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).map_err(|e| e.to_string())?;
+        if &magic != b"fLaC" {
+            return Err("Missing FLAC stream marker".to_string());
+        }
+
+        loop {
+            let mut header = [0u8; 4];
+            reader.read_exact(&mut header).map_err(|e| e.to_string())?;
+            let is_last = header[0] & 0x80 != 0;
+            let block_type = header[0] & 0x7f;
+            let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+
+            match block_type {
+                STREAMINFO | VORBIS_COMMENT => {
+                    let block = read_exact_vec(reader, len)?;
+                    if block_type == STREAMINFO {
+                        tags.duration_ms = streaminfo_duration(&block);
+                    } else {
+                        apply_vorbis_comments(tags, &block)?;
+                    }
+                }
+                PICTURE if len <= MAX_TAG_BYTES => {
+                    let block = read_exact_vec(reader, len)?;
+                    if let Some(picture) = parse_flac_picture(&block) {
+                        offer_picture(tags, picture);
+                    }
+                }
+                _ => {
+                    reader
+                        .seek(SeekFrom::Current(len as i64))
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+
+            if is_last {
+                return Ok(());
+            }
+        }
+    }
+
+    fn streaminfo_duration(block: &[u8]) -> Option<u64> {
+        if block.len() < 18 {
+            return None;
+        }
+        // 20 bits of sample rate, 3 of channels, 5 of bit depth, then a
+        // 36-bit total sample count.
+        let sample_rate =
+            ((block[10] as u64) << 12) | ((block[11] as u64) << 4) | ((block[12] as u64) >> 4);
+        let total_samples = ((block[13] as u64 & 0x0f) << 32)
+            | ((block[14] as u64) << 24)
+            | ((block[15] as u64) << 16)
+            | ((block[16] as u64) << 8)
+            | block[17] as u64;
+
+        if sample_rate == 0 || total_samples == 0 {
+            return None;
+        }
+        Some(total_samples.checked_mul(1000)? / sample_rate)
+    }
+}
+
+mod ogg {
+    use super::*;
+
+    struct Page {
+        serial: u32,
+        segments: Vec<u8>,
+        data: Vec<u8>,
+    }
+
+    fn read_page<R: Read>(reader: &mut R) -> Result<Option<Page>, String> {
+        let mut header = [0u8; 27];
+        if read_up_to(reader, &mut header)? < 27 {
+            return Ok(None);
+        }
+        if &header[0..4] != b"OggS" {
+            return Err("Lost Ogg page sync".to_string());
+        }
+
+        let serial = le_u32(&header[14..18]);
+        let mut segments = vec![0u8; header[26] as usize];
+        reader
+            .read_exact(&mut segments)
+            .map_err(|e| e.to_string())?;
+        let data_len: u64 = segments.iter().map(|s| *s as u64).sum();
+        let data = read_exact_vec(reader, data_len)?;
+
+        Ok(Some(Page {
+            serial,
+            segments,
+            data,
+        }))
+    }
+
+    /// Reassembles the first `count` packets of the first logical stream.
+    fn read_packets<R: Read>(reader: &mut R, count: usize) -> Result<(u32, Vec<Vec<u8>>), String> {
+        let mut packets = Vec::new();
+        let mut current = Vec::new();
+        let mut stream = None;
+        let mut buffered = 0u64;
+
+        while packets.len() < count {
+            let page = read_page(reader)?.ok_or("Ogg stream ended early")?;
+            if *stream.get_or_insert(page.serial) != page.serial {
+                continue;
+            }
+
+            let mut offset = 0usize;
+            for segment in &page.segments {
+                let len = *segment as usize;
+                current.extend_from_slice(&page.data[offset..offset + len]);
+                offset += len;
+                buffered += len as u64;
+                if buffered > MAX_TAG_BYTES {
+                    return Err("Ogg header packets too large".to_string());
+                }
+                // A lacing value below 255 terminates the packet.
+                if len < 255 {
+                    packets.push(std::mem::take(&mut current));
+                    if packets.len() == count {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok((stream.unwrap_or(0), packets))
+    }
+
+    pub fn read<R: Read + Seek>(reader: &mut R, tags: &mut TrackTags) -> Result<(), String> {
+        let (serial, packets) = read_packets(reader, 2)?;
+        let (ident, comments) = (&packets[0], &packets[1]);
+
+        let sample_rate = if ident.starts_with(b"\x01vorbis") && ident.len() >= 16 {
+            le_u32(&ident[12..16]) as u64
+        } else if ident.starts_with(b"OpusHead") {
+            48_000
+        } else {
+            return Err("Unsupported Ogg codec".to_string());
+        };
+
+        if let Some(body) = comments.strip_prefix(b"\x03vorbis") {
+            apply_vorbis_comments(tags, body)?;
+        } else if let Some(body) = comments.strip_prefix(b"OpusTags") {
+            apply_vorbis_comments(tags, body)?;
+        }
+
+        let pre_skip = if ident.starts_with(b"OpusHead") && ident.len() >= 12 {
+            u16::from_le_bytes([ident[10], ident[11]]) as u64
+        } else {
+            0
+        };
+        if let Some(granule) = last_granule(reader, serial)? {
+            // The granule comes straight from the file; a value that does not
+            // fit is treated as an unknown duration rather than trusted.
+            if sample_rate > 0 {
+                tags.duration_ms = granule
+                    .checked_sub(pre_skip)
+                    .filter(|samples| *samples > 0)
+                    .and_then(|samples| samples.checked_mul(1000))
+                    .map(|ms| ms / sample_rate);
+            }
+        }
+        Ok(())
+    }
+
+    /// The granule position of the final page gives the stream length in
+    /// samples; it lives somewhere in the last few kilobytes.
+    fn last_granule<R: Read + Seek>(reader: &mut R, serial: u32) -> Result<Option<u64>, String> {
+        let file_len = reader.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+        let window = file_len.min(64 * 1024);
+        reader
+            .seek(SeekFrom::Start(file_len - window))
+            .map_err(|e| e.to_string())?;
+        let tail = read_exact_vec(reader, window)?;
+
+        if tail.len() < 27 {
+            return Ok(None);
+        }
+        let mut pos = tail.len() - 27;
+        loop {
+            if &tail[pos..pos + 4] == b"OggS" && le_u32(&tail[pos + 14..pos + 18]) == serial {
+                let granule =
+                    u64::from_le_bytes(tail[pos + 6..pos + 14].try_into().unwrap_or([0; 8]));
+                if granule != u64::MAX {
+                    return Ok(Some(granule));
+                }
+            }
+            if pos == 0 {
+                return Ok(None);
+            }
+            pos -= 1;
+        }
+    }
+}
+
+mod mp4 {
+    use super::*;
+
+    struct Atom {
+        kind: [u8; 4],
+        /// Offset of the payload and its length, relative to the buffer (or
+        /// file) the atom was read from.
+        start: u64,
+        len: u64,
+    }
+
+    fn parse_header(bytes: &[u8], offset: u64, available: u64) -> Option<(Atom, u64)> {
+        if bytes.len() < 8 {
+            return None;
+        }
+        let size32 = be_u32(&bytes[0..4]) as u64;
+        let kind = [bytes[4], bytes[5], bytes[6], bytes[7]];
+
+        let (header_len, total) = match size32 {
+            0 => (8, available),
+            1 => {
+                let large = u64::from_be_bytes(bytes.get(8..16)?.try_into().ok()?);
+                (16, large)
+            }
+            n => (8, n),
+        };
+        if total < header_len || total > available {
+            return None;
+        }
+
+        Some((
+            Atom {
+                kind,
+                start: offset + header_len,
+                len: total - header_len,
+            },
+            total,
+        ))
+    }
+
+    /// Iterates the child atoms contained in `data`.
+    fn children(data: &[u8]) -> Vec<Atom> {
+        let mut atoms = Vec::new();
+        let mut pos = 0u64;
+        while pos + 8 <= data.len() as u64 {
+            let rest = &data[pos as usize..];
+            match parse_header(rest, pos, rest.len() as u64) {
+                Some((atom, total)) => {
+                    atoms.push(atom);
+                    pos += total;
+                }
+                None => break,
+            }
+        }
+        atoms
+    }
+
+    fn payload<'a>(data: &'a [u8], atom: &Atom) -> &'a [u8] {
+        &data[atom.start as usize..(atom.start + atom.len) as usize]
+    }
+
+    fn find<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+        children(data)
+            .into_iter()
+            .find(|a| &a.kind == kind)
+            .map(|a| payload(data, &a))
+    }
+
+    /// Finds `moov` by walking the top-level atoms, skipping over `mdat`
+    /// without reading it.
+    fn read_moov<R: Read + Seek>(reader: &mut R) -> Result<Vec<u8>, String> {
+        let file_len = reader.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+        let mut pos = 0u64;
+
+        while pos + 8 <= file_len {
+            reader
+                .seek(SeekFrom::Start(pos))
+                .map_err(|e| e.to_string())?;
+            let mut header = [0u8; 16];
+            let read = read_up_to(reader, &mut header)?;
+            let (atom, total) =
+                parse_header(&header[..read], pos, file_len - pos).ok_or("Malformed MP4 atom")?;
+
+            if &atom.kind == b"moov" {
+                reader
+                    .seek(SeekFrom::Start(atom.start))
+                    .map_err(|e| e.to_string())?;
+                return read_exact_vec(reader, atom.len);
+            }
+            pos += total;
+        }
+
+        Err("No moov atom found".to_string())
+    }
+
+    pub fn read<R: Read + Seek>(reader: &mut R, tags: &mut TrackTags) -> Result<(), String> {
+        let moov = read_moov(reader)?;
+
+        if let Some(mvhd) = find(&moov, b"mvhd") {
+            tags.duration_ms = mvhd_duration(mvhd);
+        }
+
+        let ilst = find(&moov, b"udta")
+            .and_then(|udta| find(udta, b"meta"))
+            // `meta` is a full box: four bytes of version and flags first.
+            .and_then(|meta| meta.get(4..))
+            .and_then(|meta| find(meta, b"ilst"));
+
+        let Some(ilst) = ilst else {
+            return Ok(());
+        };
+
+        for item in children(ilst) {
+            let body = payload(ilst, &item);
+            for data in children(body).iter().filter(|a| &a.kind == b"data") {
+                let data = payload(body, data);
+                if data.len() < 8 {
+                    continue;
+                }
+                if &item.kind == b"covr" {
+                    offer_picture(tags, cover_picture(data));
+                    continue;
+                }
+                apply_item(tags, &item.kind, &data[8..]);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_item(tags: &mut TrackTags, kind: &[u8; 4], value: &[u8]) {
+        let text = || String::from_utf8_lossy(value).into_owned();
+
+        match kind {
+            b"\xa9nam" => apply_comment(tags, "TITLE", text()),
+            b"\xa9ART" => apply_comment(tags, "ARTIST", text()),
+            b"\xa9alb" => apply_comment(tags, "ALBUM", text()),
+            b"aART" => apply_comment(tags, "ALBUMARTIST", text()),
+            b"\xa9lyr" => apply_comment(tags, "LYRICS", text()),
+            b"trkn" if value.len() >= 4 => {
+                let number = u16::from_be_bytes([value[2], value[3]]) as u32;
+                tags.track_number = tags.track_number.or(Some(number).filter(|n| *n > 0));
+            }
+            b"disk" if value.len() >= 4 => {
+                let number = u16::from_be_bytes([value[2], value[3]]) as u32;
+                tags.disc_number = tags.disc_number.or(Some(number).filter(|n| *n > 0));
+            }
+            _ => {}
+        }
+    }
+
+    /// `covr` has no picture types, so every image counts as a front cover
+    /// and the first one wins. The data atom's flags name the format.
+    fn cover_picture(data: &[u8]) -> Picture {
+        let mime = match be_u32(&data[0..4]) & 0x00ff_ffff {
+            14 => "image/png",
+            27 => "image/bmp",
+            _ => "image/jpeg",
+        };
+        Picture {
+            mime: mime.to_string(),
+            picture_type: PICTURE_FRONT_COVER,
+            data: data[8..].to_vec(),
+        }
+    }
+
+    fn mvhd_duration(mvhd: &[u8]) -> Option<u64> {
+        let (timescale, duration) = if mvhd.first() == Some(&1) {
+            let body = mvhd.get(20..32)?;
+            (
+                be_u32(&body[0..4]) as u64,
+                u64::from_be_bytes(body[4..12].try_into().ok()?),
+            )
+        } else {
+            let body = mvhd.get(12..20)?;
+            (be_u32(&body[0..4]) as u64, be_u32(&body[4..8]) as u64)
+        };
+
+        if timescale == 0 {
+            return None;
+        }
+        Some(duration.checked_mul(1000)? / timescale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    type Reader = fn(&mut Cursor<Vec<u8>>, &mut TrackTags) -> Result<(), String>;
+
+    fn read_with(reader: Reader, bytes: &[u8]) -> Result<TrackTags, String> {
+        let mut tags = TrackTags::default();
+        reader(&mut Cursor::new(bytes.to_vec()), &mut tags)?;
+        Ok(tags)
+    }
+
+    fn id3(cursor: &mut Cursor<Vec<u8>>, tags: &mut TrackTags) -> Result<(), String> {
+        id3::read(cursor, tags).map(|_| ())
+    }
+
+    /// Every prefix of a valid file must be rejected or read, never panic.
+    fn assert_truncations_survive(reader: Reader, bytes: &[u8]) {
+        for len in 0..bytes.len() {
+            let _ = read_with(reader, &bytes[..len]);
+        }
+    }
+
+    fn syncsafe(n: u32) -> [u8; 4] {
+        [
+            (n >> 21) as u8 & 0x7f,
+            (n >> 14) as u8 & 0x7f,
+            (n >> 7) as u8 & 0x7f,
+            n as u8 & 0x7f,
+        ]
+    }
+
+    /// A v2.3 frame with no flags set.
+    fn id3_raw_frame(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    fn id3_frame(id: &[u8; 4], text: &str) -> Vec<u8> {
+        let mut body = vec![3];
+        body.extend_from_slice(text.as_bytes());
+        id3_raw_frame(id, &body)
+    }
+
+    /// An ID3v2.3 tag holding `frames`, followed by an MPEG frame header.
+    fn id3_tag(mut frames: Vec<u8>) -> Vec<u8> {
+        frames.extend([0u8; 8]); // padding
+
+        let mut file = b"ID3\x03\x00\x00".to_vec();
+        file.extend(syncsafe(frames.len() as u32));
+        file.extend(frames);
+        file.extend([0xff, 0xfb, 0x90, 0x00]);
+        file
+    }
+
+    fn id3_fixture() -> Vec<u8> {
+        let mut frames = id3_frame(b"TIT2", "Song");
+        frames.extend(id3_frame(b"TPE1", "Artist"));
+        frames.extend(id3_frame(b"TALB", "Album"));
+        frames.extend(id3_frame(b"TRCK", "3/12"));
+        id3_tag(frames)
+    }
+
+    /// An APIC frame with a Latin-1 description.
+    fn apic_frame(mime: &str, picture_type: u8, data: &[u8]) -> Vec<u8> {
+        let mut body = vec![0];
+        body.extend_from_slice(mime.as_bytes());
+        body.push(0);
+        body.push(picture_type);
+        body.extend_from_slice(b"cover\0");
+        body.extend_from_slice(data);
+        id3_raw_frame(b"APIC", &body)
+    }
+
+    /// The FLAC PICTURE block layout, also used by `METADATA_BLOCK_PICTURE`.
+    fn flac_picture(picture_type: u32, mime: &str, data: &[u8]) -> Vec<u8> {
+        let mut block = picture_type.to_be_bytes().to_vec();
+        block.extend((mime.len() as u32).to_be_bytes());
+        block.extend_from_slice(mime.as_bytes());
+        block.extend(5u32.to_be_bytes());
+        block.extend_from_slice(b"cover");
+        block.extend([0u8; 16]);
+        block.extend((data.len() as u32).to_be_bytes());
+        block.extend_from_slice(data);
+        block
+    }
+
+    /// `text` in ID3 encoding `encoding`, with a terminator when asked for.
+    /// UTF-16 strings get a little-endian byte order mark.
+    fn id3_text(encoding: u8, text: &str, terminated: bool) -> Vec<u8> {
+        let mut out = match encoding {
+            1 => {
+                let mut out = vec![0xff, 0xfe];
+                out.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+                out
+            }
+            2 => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            3 => text.as_bytes().to_vec(),
+            _ => text.chars().map(|c| c as u8).collect(),
+        };
+        if terminated {
+            out.extend(if matches!(encoding, 1 | 2) {
+                &[0, 0][..]
+            } else {
+                &[0][..]
+            });
+        }
+        out
+    }
+
+    fn uslt_frame(encoding: u8, descriptor: &str, text: &str) -> Vec<u8> {
+        let mut body = vec![encoding];
+        body.extend_from_slice(b"eng");
+        body.extend(id3_text(encoding, descriptor, true));
+        body.extend(id3_text(encoding, text, false));
+        id3_raw_frame(b"USLT", &body)
+    }
+
+    /// A SYLT frame; `timestamp_format` 2 is milliseconds, 1 MPEG frames.
+    fn sylt_frame(encoding: u8, timestamp_format: u8, lines: &[(u32, &str)]) -> Vec<u8> {
+        let mut body = vec![encoding];
+        body.extend_from_slice(b"eng");
+        body.extend([timestamp_format, 1]);
+        body.extend(id3_text(encoding, "desc", true));
+        for (stamp, text) in lines {
+            body.extend(id3_text(encoding, text, true));
+            body.extend(stamp.to_be_bytes());
+        }
+        id3_raw_frame(b"SYLT", &body)
+    }
+
+    fn picture(picture_type: u8, data: &[u8]) -> Picture {
+        Picture {
+            mime: "image/jpeg".to_string(),
+            picture_type,
+            data: data.to_vec(),
+        }
+    }
+
+    fn vorbis_comments(entries: &[&str]) -> Vec<u8> {
+        let mut body = 4u32.to_le_bytes().to_vec();
+        body.extend_from_slice(b"test");
+        body.extend((entries.len() as u32).to_le_bytes());
+        for entry in entries {
+            body.extend((entry.len() as u32).to_le_bytes());
+            body.extend_from_slice(entry.as_bytes());
+        }
+        body
+    }
+
+    fn flac_block(kind: u8, last: bool, data: &[u8]) -> Vec<u8> {
+        let len = (data.len() as u32).to_be_bytes();
+        let mut block = vec![kind | if last { 0x80 } else { 0 }, len[1], len[2], len[3]];
+        block.extend_from_slice(data);
+        block
+    }
+
+    fn flac_fixture(sample_rate: u32, total_samples: u64) -> Vec<u8> {
+        flac_with_blocks(
+            sample_rate,
+            total_samples,
+            &[(
+                4,
+                vorbis_comments(&["TITLE=Song", "ARTIST=Artist", "TRACKNUMBER=7"]),
+            )],
+        )
+    }
+
+    /// STREAMINFO followed by `blocks`, given as (type, data).
+    fn flac_with_blocks(sample_rate: u32, total_samples: u64, blocks: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut streaminfo = [0u8; 34];
+        streaminfo[10] = (sample_rate >> 12) as u8;
+        streaminfo[11] = (sample_rate >> 4) as u8;
+        streaminfo[12] = ((sample_rate & 0x0f) << 4) as u8 | 0x02;
+        streaminfo[13] = 0xf0 | ((total_samples >> 32) & 0x0f) as u8;
+        streaminfo[14..18].copy_from_slice(&(total_samples as u32).to_be_bytes());
+
+        let mut file = b"fLaC".to_vec();
+        file.extend(flac_block(0, blocks.is_empty(), &streaminfo));
+        for (i, (kind, data)) in blocks.iter().enumerate() {
+            file.extend(flac_block(*kind, i + 1 == blocks.len(), data));
+        }
+        file
+    }
+
+    fn ogg_page(serial: u32, granule: u64, packets: &[&[u8]]) -> Vec<u8> {
+        let mut segments = Vec::new();
+        let mut data = Vec::new();
+        for packet in packets {
+            segments.extend(std::iter::repeat_n(255u8, packet.len() / 255));
+            segments.push((packet.len() % 255) as u8);
+            data.extend_from_slice(packet);
+        }
+
+        let mut page = b"OggS\x00\x00".to_vec();
+        page.extend(granule.to_le_bytes());
+        page.extend(serial.to_le_bytes());
+        page.extend([0u8; 8]); // sequence number and CRC, which we ignore
+        page.push(segments.len() as u8);
+        page.extend(segments);
+        page.extend(data);
+        page
+    }
+
+    fn ogg_vorbis_fixture(final_granule: u64) -> Vec<u8> {
+        let mut ident = b"\x01vorbis".to_vec();
+        ident.extend(0u32.to_le_bytes());
+        ident.push(2);
+        ident.extend(44_100u32.to_le_bytes());
+        ident.extend([0u8; 15]);
+
+        let mut comments = b"\x03vorbis".to_vec();
+        comments.extend(vorbis_comments(&["TITLE=Song", "ALBUM=Album"]));
+
+        let mut file = ogg_page(7, 0, &[&ident]);
+        file.extend(ogg_page(7, 0, &[&comments]));
+        file.extend(ogg_page(7, final_granule, &[&[0u8; 4]]));
+        file
+    }
+
+    fn ogg_opus_fixture(pre_skip: u16, final_granule: u64) -> Vec<u8> {
+        let mut ident = b"OpusHead\x01\x02".to_vec();
+        ident.extend(pre_skip.to_le_bytes());
+        ident.extend(48_000u32.to_le_bytes());
+        ident.extend([0u8; 3]);
+
+        let mut comments = b"OpusTags".to_vec();
+        comments.extend(vorbis_comments(&["TITLE=Song"]));
+
+        let mut file = ogg_page(9, 0, &[&ident]);
+        file.extend(ogg_page(9, 0, &[&comments]));
+        file.extend(ogg_page(9, final_granule, &[&[0u8; 4]]));
+        file
+    }
+
+    fn atom(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut atom = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend_from_slice(kind);
+        atom.extend_from_slice(payload);
+        atom
+    }
+
+    fn mp4_item(kind: &[u8; 4], value: &[u8]) -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+        data.extend_from_slice(value);
+        atom(kind, &atom(b"data", &data))
+    }
+
+    fn mp4_fixture(mvhd: &[u8]) -> Vec<u8> {
+        mp4_with_items(mvhd, &[])
+    }
+
+    /// The fixture's tags plus `extra` items.
+    fn mp4_with_items(mvhd: &[u8], extra: &[Vec<u8>]) -> Vec<u8> {
+        let mut ilst = mp4_item(b"\xa9nam", b"Song");
+        ilst.extend(mp4_item(b"\xa9ART", b"Artist"));
+        ilst.extend(mp4_item(b"trkn", &[0, 0, 0, 4, 0, 10, 0, 0]));
+        for item in extra {
+            ilst.extend_from_slice(item);
+        }
+        let mut meta = vec![0u8; 4];
+        meta.extend(atom(b"ilst", &ilst));
+
+        let mut moov = atom(b"mvhd", mvhd);
+        moov.extend(atom(b"udta", &atom(b"meta", &meta)));
+
+        let mut file = atom(b"ftyp", b"M4A \x00\x00\x00\x00");
+        file.extend(atom(b"mdat", &[0u8; 16]));
+        file.extend(atom(b"moov", &moov));
+        file
+    }
+
+    fn mvhd_v0(timescale: u32, duration: u32) -> Vec<u8> {
+        let mut mvhd = vec![0u8; 12];
+        mvhd.extend(timescale.to_be_bytes());
+        mvhd.extend(duration.to_be_bytes());
+        mvhd
+    }
+
+    fn mvhd_v1(timescale: u32, duration: u64) -> Vec<u8> {
+        let mut mvhd = vec![1u8; 1];
+        mvhd.extend([0u8; 19]);
+        mvhd.extend(timescale.to_be_bytes());
+        mvhd.extend(duration.to_be_bytes());
+        mvhd
+    }
+
+    #[test]
+    fn reads_id3v2_text_frames() {
+        let tags = read_with(id3, &id3_fixture()).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.track_number, Some(3));
+    }
+
+    #[test]
+    fn id3_frame_overrunning_the_tag_is_ignored() {
+        let mut file = id3_fixture();
+        // Claim the first frame is far larger than the tag.
+        file[14..18].copy_from_slice(&0x7fff_ffffu32.to_be_bytes());
+        let tags = read_with(id3, &file).unwrap();
+        assert_eq!(tags.title, None);
+    }
+
+    #[test]
+    fn truncated_id3_does_not_panic() {
+        assert_truncations_survive(id3, &id3_fixture());
+    }
+
+    #[test]
+    fn reads_flac_streaminfo_and_comments() {
+        let tags = read_with(flac::read, &flac_fixture(44_100, 441_000)).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.track_number, Some(7));
+        assert_eq!(tags.duration_ms, Some(10_000));
+    }
+
+    #[test]
+    fn flac_without_sample_rate_has_unknown_duration() {
+        let tags = read_with(flac::read, &flac_fixture(0, 441_000)).unwrap();
+        assert_eq!(tags.duration_ms, None);
+    }
+
+    #[test]
+    fn corrupt_flac_comment_block_is_an_error() {
+        let mut file = flac_fixture(44_100, 441_000);
+        // Vendor length pointing past the end of the block.
+        let vendor = 4 + 4 + 34 + 4;
+        file[vendor..vendor + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_with(flac::read, &file).is_err());
+    }
+
+    #[test]
+    fn truncated_flac_does_not_panic() {
+        assert_truncations_survive(flac::read, &flac_fixture(44_100, 441_000));
+    }
+
+    #[test]
+    fn reads_ogg_vorbis_comments_and_duration() {
+        let tags = read_with(ogg::read, &ogg_vorbis_fixture(88_200)).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.duration_ms, Some(2_000));
+    }
+
+    #[test]
+    fn opus_duration_subtracts_pre_skip() {
+        let tags = read_with(ogg::read, &ogg_opus_fixture(312, 48_312)).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.duration_ms, Some(1_000));
+    }
+
+    #[test]
+    fn corrupt_ogg_granules_give_unknown_duration() {
+        let tags = read_with(ogg::read, &ogg_opus_fixture(312, 100)).unwrap();
+        assert_eq!(tags.duration_ms, None);
+
+        let tags = read_with(ogg::read, &ogg_opus_fixture(0, u64::MAX - 1)).unwrap();
+        assert_eq!(tags.duration_ms, None);
+    }
+
+    #[test]
+    fn lost_ogg_sync_is_an_error() {
+        let mut file = ogg_vorbis_fixture(88_200);
+        let second_page = file.windows(4).rposition(|w| w == b"OggS").unwrap();
+        let second_page = file[..second_page]
+            .windows(4)
+            .rposition(|w| w == b"OggS")
+            .unwrap();
+        file[second_page] = b'X';
+        assert!(read_with(ogg::read, &file).is_err());
+    }
+
+    #[test]
+    fn truncated_ogg_does_not_panic() {
+        assert_truncations_survive(ogg::read, &ogg_vorbis_fixture(88_200));
+        assert_truncations_survive(ogg::read, &ogg_opus_fixture(312, 48_312));
+    }
+
+    #[test]
+    fn reads_mp4_items_and_duration() {
+        let tags = read_with(mp4::read, &mp4_fixture(&mvhd_v0(600, 1_800))).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.track_number, Some(4));
+        assert_eq!(tags.duration_ms, Some(3_000));
+
+        let tags = read_with(mp4::read, &mp4_fixture(&mvhd_v1(1_000, 5_000))).unwrap();
+        assert_eq!(tags.duration_ms, Some(5_000));
+    }
+
+    #[test]
+    fn overflowing_mp4_duration_is_unknown() {
+        let tags = read_with(mp4::read, &mp4_fixture(&mvhd_v1(1, u64::MAX))).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.duration_ms, None);
+
+        let tags = read_with(mp4::read, &mp4_fixture(&mvhd_v0(0, 1_800))).unwrap();
+        assert_eq!(tags.duration_ms, None);
+    }
+
+    #[test]
+    fn corrupt_mp4_atom_size_is_an_error() {
+        let mut file = mp4_fixture(&mvhd_v0(600, 1_800));
+        // The mdat atom claims to run past the end of the file.
+        file[16..20].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(read_with(mp4::read, &file).is_err());
+    }
+
+    #[test]
+    fn truncated_mp4_does_not_panic() {
+        assert_truncations_survive(mp4::read, &mp4_fixture(&mvhd_v0(600, 1_800)));
+    }
+
+    #[test]
+    fn id3_prefers_the_first_front_cover() {
+        let mut frames = apic_frame("image/jpeg", 4, &[0xff, 0xd8, 0xff, 1]);
+        frames.extend(apic_frame("image/png", 3, b"\x89PNG front"));
+        frames.extend(apic_frame("image/jpeg", 3, &[0xff, 0xd8, 0xff, 2]));
+        let picture = read_with(id3, &id3_tag(frames)).unwrap().picture.unwrap();
+        assert_eq!(picture.picture_type, PICTURE_FRONT_COVER);
+        assert_eq!(picture.mime, "image/png");
+        assert_eq!(picture.data, b"\x89PNG front");
+        assert_eq!(picture.extension(), "png");
+    }
+
+    #[test]
+    fn id3_picture_links_are_ignored() {
+        let frames = apic_frame("-->", 3, b"https://example.com/cover.jpg");
+        let tags = read_with(id3, &id3_tag(frames)).unwrap();
+        assert!(tags.picture.is_none());
+    }
+
+    #[test]
+    fn reads_flac_picture_blocks() {
+        let file = flac_with_blocks(
+            44_100,
+            441_000,
+            &[
+                (6, flac_picture(0, "image/jpeg", &[0xff, 0xd8, 0xff, 1])),
+                (6, flac_picture(3, "image/png", b"\x89PNG front")),
+            ],
+        );
+        let picture = read_with(flac::read, &file).unwrap().picture.unwrap();
+        assert_eq!(picture.picture_type, PICTURE_FRONT_COVER);
+        assert_eq!(picture.data, b"\x89PNG front");
+    }
+
+    #[test]
+    fn reads_metadata_block_picture_comments() {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(flac_picture(
+            3,
+            "image/jpeg",
+            &[0xff, 0xd8, 0xff, 7],
+        ));
+        let comment = format!("METADATA_BLOCK_PICTURE={}", encoded);
+        let file = flac_with_blocks(44_100, 441_000, &[(4, vorbis_comments(&[&comment]))]);
+        let picture = read_with(flac::read, &file).unwrap().picture.unwrap();
+        assert_eq!(picture.picture_type, PICTURE_FRONT_COVER);
+        assert_eq!(picture.data, [0xff, 0xd8, 0xff, 7]);
+        assert_eq!(picture.extension(), "jpg");
+    }
+
+    #[test]
+    fn corrupt_metadata_block_picture_is_skipped() {
+        let file = flac_with_blocks(
+            44_100,
+            441_000,
+            &[(4, vorbis_comments(&["METADATA_BLOCK_PICTURE=not base64!"]))],
+        );
+        assert!(read_with(flac::read, &file).unwrap().picture.is_none());
+    }
+
+    #[test]
+    fn reads_mp4_cover_art() {
+        let mut png = vec![0, 0, 0, 14, 0, 0, 0, 0];
+        png.extend_from_slice(b"\x89PNG cover");
+        let covr = atom(b"covr", &atom(b"data", &png));
+        let file = mp4_with_items(&mvhd_v0(600, 1_800), &[covr]);
+        let picture = read_with(mp4::read, &file).unwrap().picture.unwrap();
+        assert_eq!(picture.mime, "image/png");
+        assert_eq!(picture.picture_type, PICTURE_FRONT_COVER);
+        assert_eq!(picture.data, b"\x89PNG cover");
+    }
+
+    #[test]
+    fn pictures_rank_front_untyped_other_then_icons() {
+        let ranks: Vec<u8> = [3, 0, 8, 1, 2]
+            .into_iter()
+            .map(|kind| picture(kind, &[1]).rank())
+            .collect();
+        assert_eq!(ranks, [0, 1, 2, 3, 3]);
+    }
+
+    #[test]
+    fn offer_picture_keeps_the_best_and_earliest() {
+        let mut tags = TrackTags::default();
+        offer_picture(&mut tags, picture(1, b"icon"));
+        offer_picture(&mut tags, picture(4, b"back"));
+        assert_eq!(tags.picture.as_ref().unwrap().data, b"back");
+
+        offer_picture(&mut tags, picture(0, b"untyped"));
+        offer_picture(&mut tags, picture(0, b"second untyped"));
+        assert_eq!(tags.picture.as_ref().unwrap().data, b"untyped");
+
+        offer_picture(&mut tags, picture(3, b""));
+        assert_eq!(tags.picture.as_ref().unwrap().data, b"untyped");
+
+        offer_picture(&mut tags, picture(3, b"front"));
+        offer_picture(&mut tags, picture(0, b"late untyped"));
+        assert_eq!(tags.picture.as_ref().unwrap().data, b"front");
+    }
+
+    #[test]
+    fn reads_uslt_in_each_encoding() {
+        for encoding in 0..=3 {
+            let frames = uslt_frame(encoding, "Lyrics", "First line\nSecond line");
+            let tags = read_with(id3, &id3_tag(frames)).unwrap();
+            assert_eq!(
+                tags.lyrics.as_deref(),
+                Some("First line\nSecond line"),
+                "encoding {}",
+                encoding
+            );
+        }
+    }
+
+    #[test]
+    fn uslt_keeps_the_first_non_empty_frame() {
+        let mut frames = uslt_frame(3, "", "");
+        frames.extend(uslt_frame(3, "", "Kept"));
+        frames.extend(uslt_frame(3, "", "Ignored"));
+        let tags = read_with(id3, &id3_tag(frames)).unwrap();
+        assert_eq!(tags.lyrics.as_deref(), Some("Kept"));
+    }
+
+    #[test]
+    fn reads_sylt_millisecond_timestamps() {
+        let frames = sylt_frame(3, 2, &[(0, "Intro"), (1_500, "Zweite Zeile"), (62_250, "")]);
+        let tags = read_with(id3, &id3_tag(frames)).unwrap();
+        assert_eq!(
+            tags.synced_lyrics,
+            [
+                (0, "Intro".to_string()),
+                (1_500, "Zweite Zeile".to_string()),
+                (62_250, String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_sylt_text_in_each_encoding() {
+        for encoding in 0..=3 {
+            let frames = sylt_frame(encoding, 2, &[(10, "Caf\u{e9}"), (20, "na\u{ef}ve")]);
+            let tags = read_with(id3, &id3_tag(frames)).unwrap();
+            assert_eq!(
+                tags.synced_lyrics,
+                [
+                    (10, "Caf\u{e9}".to_string()),
+                    (20, "na\u{ef}ve".to_string())
+                ],
+                "encoding {}",
+                encoding
+            );
+        }
+    }
+
+    #[test]
+    fn sylt_in_mpeg_frames_is_skipped() {
+        let frames = sylt_frame(3, 1, &[(10, "Frame-timed")]);
+        let tags = read_with(id3, &id3_tag(frames)).unwrap();
+        assert!(tags.synced_lyrics.is_empty());
+    }
+
+    #[test]
+    fn truncated_sylt_keeps_complete_lines() {
+        let mut frames = sylt_frame(3, 2, &[(100, "Whole"), (200, "Cut")]);
+        // Drop the last timestamp's final byte and fix up the frame size.
+        frames.pop();
+        let size = (frames.len() - 10) as u32;
+        frames[4..8].copy_from_slice(&size.to_be_bytes());
+        let tags = read_with(id3, &id3_tag(frames)).unwrap();
+        assert_eq!(tags.synced_lyrics, [(100, "Whole".to_string())]);
+    }
+}
@@ -0,0 +1,237 @@
+//! Resized copies of cover art. Local scans are often 3000px or more, far too
+//! heavy for a widget that shows them at 100px, and remote images are fetched
+//! once instead of on every track change.
+//!
+//! Thumbnails are stored as `<sha256 of the source image>-<size>.jpg`, so the
+//! same cover reached through different paths or URLs is only resized once.
+
+use image::{codecs::jpeg::JpegEncoder, DynamicImage};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{
+    art_protocol, artwork,
+    config::{self, SharedConfig},
+    tags,
+};
+
+/// Edge lengths, in pixels, of the square boxes thumbnails are fitted into.
+pub const SIZES: [u32; 3] = [64, 300, 640];
+
+const JPEG_QUALITY: u8 = 85;
+
+/// Remote images larger than this are refused rather than buffered.
+const MAX_REMOTE_BYTES: usize = 20 * 1024 * 1024;
+
+/// Covers the whole download. Reports and the cover writer fetch one image
+/// at a time, so a stalled request must not hold them up for long.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Source hashes remembered before the oldest are forgotten. Every retag or
+/// new artwork URL adds a key, so the map would otherwise grow for as long
+/// as the app runs.
+const MAX_KNOWN_HASHES: usize = 2048;
+
+pub fn thumbnail_dir() -> PathBuf {
+    config::cache_dir().join("thumbnails")
+}
+
+/// The smallest standard size that is at least `requested`, or the largest
+/// one for anything bigger.
+pub fn standard_size(requested: u32) -> u32 {
+    SIZES
+        .into_iter()
+        .find(|size| *size >= requested)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+/// Source key (file path with size and mtime, or URL) to the content hash
+/// of its bytes, so repeat requests skip reading and hashing the source.
+/// Oldest entries are evicted first.
+#[derive(Default)]
+struct KnownHashes {
+    hashes: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+pub struct ThumbnailCache {
+    known: Mutex<KnownHashes>,
+    http: reqwest::Client,
+}
+
+impl ThumbnailCache {
+    pub fn new() -> Self {
+        ThumbnailCache {
+            known: Mutex::new(KnownHashes::default()),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    fn known_hash(&self, key: &str) -> Option<String> {
+        self.known.lock().ok()?.hashes.get(key).cloned()
+    }
+
+    fn remember(&self, key: String, hash: String) {
+        let Ok(mut known) = self.known.lock() else {
+            return;
+        };
+        if known.hashes.insert(key.clone(), hash).is_none() {
+            known.order.push_back(key);
+        }
+        while known.order.len() > MAX_KNOWN_HASHES {
+            if let Some(oldest) = known.order.pop_front() {
+                known.hashes.remove(&oldest);
+            }
+        }
+    }
+
+    /// Thumbnail of a local image, generated on first use.
+    pub fn for_file(&self, path: &Path, size: u32) -> Result<PathBuf, String> {
+        let size = standard_size(size);
+        let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let key = format!("{}:{}:{}", path.display(), metadata.len(), modified);
+
+        if let Some(hash) = self.known_hash(&key) {
+            let thumbnail = thumbnail_path(&hash, size);
+            if thumbnail.is_file() {
+                return Ok(thumbnail);
+            }
+        }
+
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        let hash = content_hash(&bytes);
+        let thumbnail = render(&hash, &bytes, size)?;
+        self.remember(key, hash);
+        Ok(thumbnail)
+    }
+
+    /// Thumbnail of a remote image, downloaded on first use.
+    pub async fn for_url(&self, url: &str, size: u32) -> Result<PathBuf, String> {
+        let size = standard_size(size);
+
+        if let Some(hash) = self.known_hash(url) {
+            let thumbnail = thumbnail_path(&hash, size);
+            if thumbnail.is_file() {
+                return Ok(thumbnail);
+            }
+        }
+
+        let response = self
+            .http
+            .get(url)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+        if response.content_length().unwrap_or(0) as usize > MAX_REMOTE_BYTES {
+            return Err(format!("Image too large: {}", url));
+        }
+        let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+        if bytes.len() > MAX_REMOTE_BYTES {
+            return Err(format!("Image too large: {}", url));
+        }
+
+        let hash = content_hash(&bytes);
+        let thumbnail = {
+            let hash = hash.clone();
+            tauri::async_runtime::spawn_blocking(move || render(&hash, &bytes, size))
+                .await
+                .map_err(|e| e.to_string())??
+        };
+        self.remember(url.to_string(), hash);
+        Ok(thumbnail)
+    }
+}
+
+impl Default for ThumbnailCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn thumbnail_path(hash: &str, size: u32) -> PathBuf {
+    thumbnail_dir().join(format!("{}-{}.jpg", hash, size))
+}
+
+/// Decodes `bytes` and writes the `size` thumbnail unless it already exists.
+/// Images already smaller than `size` are re-encoded but never upscaled.
+fn render(hash: &str, bytes: &[u8], size: u32) -> Result<PathBuf, String> {
+    let path = thumbnail_path(hash, size);
+    if path.is_file() {
+        return Ok(path);
+    }
+
+    let image = image::load_from_memory(bytes).map_err(|e| e.to_string())?;
+    let resized = if image.width() > size || image.height() > size {
+        image.thumbnail(size, size)
+    } else {
+        image
+    };
+
+    let mut encoded = Vec::new();
+    DynamicImage::ImageRgb8(resized.to_rgb8())
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY))
+        .map_err(|e| e.to_string())?;
+
+    config::write_atomic(&path, &encoded)?;
+    Ok(path)
+}
+
+/// Thumbnail of `source`, which may be a local image or audio file path, or
+/// an `http(s)` URL. `size` is rounded up to one of [`SIZES`]. Local files
+/// must live under a library root.
+pub async fn thumbnail_for_source(
+    thumbnails: Arc<ThumbnailCache>,
+    config: &SharedConfig,
+    source: &str,
+    size: u32,
+) -> Result<PathBuf, String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        return thumbnails.for_url(source, size).await;
+    }
+
+    let source = art_protocol::resolve_allowed(config, Path::new(source))
+        .ok_or_else(|| format!("Not in a library folder: {}", source))?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let image = if tags::is_audio_file(&source) {
+            artwork::extract_embedded(&source)
+                .ok_or_else(|| format!("No embedded art in {}", source.display()))?
+        } else {
+            source
+        };
+        thumbnails.for_file(&image, size)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Returns an `albumart://` URL for a thumbnail of `source`; see
+/// [`thumbnail_for_source`].
+#[tauri::command]
+pub async fn get_thumbnail(
+    source: String,
+    size: u32,
+    config: tauri::State<'_, SharedConfig>,
+    thumbnails: tauri::State<'_, Arc<ThumbnailCache>>,
+) -> Result<String, String> {
+    let path =
+        thumbnail_for_source(thumbnails.inner().clone(), config.inner(), &source, size).await?;
+    Ok(art_protocol::url_for(&path))
+}
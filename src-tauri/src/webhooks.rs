@@ -0,0 +1,665 @@
+//! Outgoing webhooks on playback events. Each enabled webhook gets a JSON
+//! POST for the events it subscribes to, signed when it has a secret, and
+//! retried with backoff on network errors, rate limits and server errors.
+//! Each webhook has its own queue, so it receives events in the order they
+//! happened even while an earlier one is being retried. Recent attempts are
+//! kept in a delivery log.
+//!
+//! Every request carries `X-Widget-Timestamp`, in Unix seconds, set afresh
+//! for each attempt. Signed requests also carry `X-Widget-Signature:
+//! sha256=<hex>`, the HMAC-SHA256 of `<timestamp>.<raw body>` keyed with the
+//! secret, so receivers can reject replays by checking the timestamp is
+//! recent.
+
+mod payload;
+
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Serialize;
+use sha2::Sha256;
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    config::{SharedConfig, Webhook, WebhookSettings},
+    playback::{PlaybackEvent, PlaybackHub, PlaybackSnapshot},
+};
+
+pub const EVENTS: &[&str] = &[
+    "track-changed",
+    "playback-paused",
+    "playback-resumed",
+    "playback-stopped",
+];
+
+/// Sent only by `test_webhook`.
+const TEST_EVENT: &str = "test";
+
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const LOG_SIZE: usize = 200;
+
+/// Events waiting per webhook before the oldest are dropped. A receiver
+/// that is down can hold its queue for over a minute per event.
+const MAX_QUEUED: usize = 50;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// One attempt at delivering an event to a webhook.
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    /// Shared by every attempt of the same delivery.
+    pub id: u64,
+    pub webhook_id: String,
+    pub url: String,
+    pub event: String,
+    pub attempt: u32,
+    /// Unix milliseconds.
+    pub timestamp: u64,
+    pub duration_ms: u64,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub delivered: bool,
+}
+
+/// An event waiting in a webhook's queue. The hook is as configured when
+/// the event happened.
+struct Queued {
+    hook: Webhook,
+    event: &'static str,
+    snapshot: Option<PlaybackSnapshot>,
+}
+
+enum AttemptError {
+    Retry(String),
+    Failed(String),
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn signature(secret: &str, timestamp: u64, body: &[u8]) -> Result<String, String> {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).map_err(|e| e.to_string())?;
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    Ok(format!(
+        "sha256={}",
+        digest
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    ))
+}
+
+pub struct Webhooks {
+    config: SharedConfig,
+    http: reqwest::Client,
+    log: Mutex<VecDeque<Delivery>>,
+    next_id: AtomicU64,
+    /// Wait before the first retry; it doubles with each one after.
+    first_retry: Duration,
+    /// Pending events by webhook id. An entry exists exactly while that
+    /// webhook's delivery task is running.
+    queues: Mutex<HashMap<String, VecDeque<Queued>>>,
+}
+
+impl Webhooks {
+    pub fn new(config: SharedConfig) -> Self {
+        Webhooks {
+            config,
+            http: reqwest::Client::new(),
+            log: Mutex::new(VecDeque::new()),
+            next_id: AtomicU64::new(1),
+            first_retry: FIRST_RETRY,
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn settings(&self) -> WebhookSettings {
+        self.config
+            .lock()
+            .map(|config| config.webhooks.clone())
+            .unwrap_or_default()
+    }
+
+    fn record(&self, delivery: Delivery) {
+        if let Ok(mut log) = self.log.lock() {
+            if log.len() == LOG_SIZE {
+                log.pop_front();
+            }
+            log.push_back(delivery);
+        }
+    }
+
+    async fn attempt(
+        &self,
+        hook: &Webhook,
+        event: &str,
+        body: &[u8],
+        id: u64,
+    ) -> Result<u16, AttemptError> {
+        let timestamp = now_ms() / 1000;
+        let mut request = self
+            .http
+            .post(&hook.url)
+            .timeout(REQUEST_TIMEOUT)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Widget-Event", event)
+            .header("X-Widget-Delivery", id.to_string())
+            .header("X-Widget-Timestamp", timestamp.to_string())
+            .body(body.to_vec());
+        if !hook.secret.is_empty() {
+            let signature =
+                signature(&hook.secret, timestamp, body).map_err(AttemptError::Failed)?;
+            request = request.header("X-Widget-Signature", signature);
+        }
+
+        let status = request
+            .send()
+            .await
+            .map_err(|e| AttemptError::Retry(e.to_string()))?
+            .status();
+        if status.is_success() {
+            Ok(status.as_u16())
+        } else if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Err(AttemptError::Retry(format!("Receiver returned {}", status)))
+        } else {
+            Err(AttemptError::Failed(format!(
+                "Receiver returned {}",
+                status
+            )))
+        }
+    }
+
+    /// Sends one event to one webhook, making up to `max_attempts`
+    /// attempts. Returns the last one.
+    async fn deliver(
+        &self,
+        hook: &Webhook,
+        event: &str,
+        snapshot: Option<&PlaybackSnapshot>,
+        max_attempts: u32,
+    ) -> Delivery {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let timestamp = now_ms();
+        let body = payload::build(hook.template.as_deref(), event, timestamp, snapshot)
+            .and_then(|payload| serde_json::to_vec(&payload).map_err(|e| e.to_string()));
+
+        let mut backoff = self.first_retry;
+        let mut attempt = 1;
+        loop {
+            let started = Instant::now();
+            let result = match &body {
+                Ok(body) => self.attempt(hook, event, body, id).await,
+                Err(e) => Err(AttemptError::Failed(e.clone())),
+            };
+            let retry = matches!(result, Err(AttemptError::Retry(_))) && attempt < max_attempts;
+
+            let delivery = Delivery {
+                id,
+                webhook_id: hook.id.clone(),
+                url: hook.url.clone(),
+                event: event.to_string(),
+                attempt,
+                timestamp: now_ms(),
+                duration_ms: started.elapsed().as_millis() as u64,
+                status: result.as_ref().ok().copied(),
+                error: match &result {
+                    Ok(_) => None,
+                    Err(AttemptError::Retry(e)) | Err(AttemptError::Failed(e)) => Some(e.clone()),
+                },
+                delivered: result.is_ok(),
+            };
+            if let Some(e) = &delivery.error {
+                eprintln!(
+                    "Webhook {} attempt {} for {} failed: {}",
+                    hook.url, attempt, event, e
+                );
+            }
+            self.record(delivery.clone());
+
+            if !retry {
+                return delivery;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// Queues `event` for every webhook that wants it.
+    fn dispatch(self: &Arc<Self>, event: &'static str, snapshot: Option<&PlaybackSnapshot>) {
+        let settings = self.settings();
+        if !settings.enabled {
+            return;
+        }
+        for hook in settings.hooks {
+            let wanted = hook.events.is_empty() || hook.events.iter().any(|e| e == event);
+            if !hook.enabled || !wanted {
+                continue;
+            }
+            self.enqueue(Queued {
+                hook,
+                event,
+                snapshot: snapshot.cloned(),
+            });
+        }
+    }
+
+    /// Adds to the webhook's queue, starting its delivery task if it is not
+    /// already running.
+    fn enqueue(self: &Arc<Self>, queued: Queued) {
+        let Ok(mut queues) = self.queues.lock() else {
+            return;
+        };
+        match queues.entry(queued.hook.id.clone()) {
+            Entry::Occupied(mut entry) => {
+                let pending = entry.get_mut();
+                if pending.len() == MAX_QUEUED {
+                    if let Some(dropped) = pending.pop_front() {
+                        eprintln!(
+                            "Webhook {} queue full, dropping {}",
+                            dropped.hook.url, dropped.event
+                        );
+                    }
+                }
+                pending.push_back(queued);
+            }
+            Entry::Vacant(entry) => {
+                let id = entry.key().clone();
+                entry.insert(VecDeque::from([queued]));
+                let webhooks = self.clone();
+                tauri::async_runtime::spawn(async move { webhooks.drain(id).await });
+            }
+        }
+    }
+
+    /// Delivers a webhook's queued events one at a time, then exits. The
+    /// entry is removed under the same lock `enqueue` takes, so an event
+    /// is never left behind without a task.
+    async fn drain(&self, id: String) {
+        loop {
+            let next = {
+                let Ok(mut queues) = self.queues.lock() else {
+                    return;
+                };
+                match queues.get_mut(&id).and_then(VecDeque::pop_front) {
+                    Some(next) => next,
+                    None => {
+                        queues.remove(&id);
+                        return;
+                    }
+                }
+            };
+            self.deliver(&next.hook, next.event, next.snapshot.as_ref(), MAX_ATTEMPTS)
+                .await;
+        }
+    }
+
+    fn recent(&self, limit: usize) -> Vec<Delivery> {
+        self.log
+            .lock()
+            .map(|log| log.iter().rev().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Turns playback updates into webhook events.
+pub fn spawn_webhooks(hub: Arc<PlaybackHub>, webhooks: Arc<Webhooks>) {
+    let mut events = hub.subscribe();
+
+    tauri::async_runtime::spawn(async move {
+        let mut playing: Option<bool> = None;
+        loop {
+            match events.recv().await {
+                Ok(PlaybackEvent::TrackChanged(snapshot)) => {
+                    playing = Some(snapshot.is_playing);
+                    webhooks.dispatch("track-changed", Some(&snapshot));
+                }
+                Ok(PlaybackEvent::Progress(snapshot)) => {
+                    if playing.is_some_and(|was| was != snapshot.is_playing) {
+                        let event = if snapshot.is_playing {
+                            "playback-resumed"
+                        } else {
+                            "playback-paused"
+                        };
+                        webhooks.dispatch(event, Some(&snapshot));
+                    }
+                    playing = Some(snapshot.is_playing);
+                }
+                Ok(PlaybackEvent::Stopped) => {
+                    playing = None;
+                    webhooks.dispatch("playback-stopped", None);
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+fn validate(hook: &Webhook) -> Result<(), String> {
+    let url = reqwest::Url::parse(&hook.url).map_err(|e| format!("{}: {}", hook.url, e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Webhook URLs must be http or https: {}", hook.url));
+    }
+    if let Some(event) = hook.events.iter().find(|e| !EVENTS.contains(&e.as_str())) {
+        return Err(format!("Unknown webhook event: {}", event));
+    }
+    if let Some(template) = &hook.template {
+        payload::validate(template)?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_webhook_settings(
+    config: tauri::State<'_, SharedConfig>,
+) -> Result<WebhookSettings, String> {
+    let config = config.lock().map_err(|e| e.to_string())?;
+    Ok(config.webhooks.clone())
+}
+
+/// Validates and saves the webhooks, giving new ones an id.
+#[tauri::command]
+pub fn set_webhook_settings(
+    mut settings: WebhookSettings,
+    config: tauri::State<'_, SharedConfig>,
+) -> Result<WebhookSettings, String> {
+    for hook in &mut settings.hooks {
+        validate(hook)?;
+        if hook.id.is_empty() {
+            hook.id = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(12)
+                .map(char::from)
+                .collect();
+        }
+    }
+
+    let mut config = config.lock().map_err(|e| e.to_string())?;
+    config.webhooks = settings.clone();
+    config.save()?;
+    Ok(settings)
+}
+
+/// Most recent attempts first.
+#[tauri::command]
+pub fn get_webhook_log(
+    limit: Option<usize>,
+    webhooks: tauri::State<'_, Arc<Webhooks>>,
+) -> Vec<Delivery> {
+    webhooks.recent(limit.unwrap_or(50).min(LOG_SIZE))
+}
+
+/// Sends a `test` event with the current track to one webhook, even when
+/// webhooks are disabled, and returns the outcome. Not retried, so a local
+/// receiver can be checked quickly.
+#[tauri::command]
+pub async fn test_webhook(
+    id: String,
+    hub: tauri::State<'_, Arc<PlaybackHub>>,
+    webhooks: tauri::State<'_, Arc<Webhooks>>,
+) -> Result<Delivery, String> {
+    let hook = webhooks
+        .settings()
+        .hooks
+        .into_iter()
+        .find(|hook| hook.id == id)
+        .ok_or_else(|| format!("No webhook with id {}", id))?;
+    Ok(webhooks
+        .deliver(&hook, TEST_EVENT, hub.current().as_ref(), 1)
+        .await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::{
+        body::Bytes,
+        extract::{Path, State},
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+
+    const SECRET: &str = "hunter2";
+
+    #[derive(Debug, Clone)]
+    struct Received {
+        hook: String,
+        event: String,
+        delivery: String,
+        timestamp: String,
+        signature: Option<String>,
+        body: Bytes,
+    }
+
+    #[derive(Clone, Default)]
+    struct Receiver {
+        received: Arc<Mutex<Vec<Received>>>,
+        /// Statuses to answer with, per hook, before answering 204.
+        script: Arc<Mutex<HashMap<String, VecDeque<u16>>>>,
+    }
+
+    impl Receiver {
+        fn fail(&self, hook: &str, statuses: &[u16]) {
+            self.script
+                .lock()
+                .unwrap()
+                .insert(hook.to_string(), statuses.iter().copied().collect());
+        }
+
+        fn received(&self, hook: &str) -> Vec<Received> {
+            let received = self.received.lock().unwrap();
+            received
+                .iter()
+                .filter(|r| r.hook == hook)
+                .cloned()
+                .collect()
+        }
+    }
+
+    /// Stands in for a webhook receiver: records every request and answers
+    /// as scripted.
+    async fn receive(
+        State(receiver): State<Receiver>,
+        Path(hook): Path<String>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> StatusCode {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        receiver.received.lock().unwrap().push(Received {
+            hook: hook.clone(),
+            event: header("x-widget-event").unwrap_or_default(),
+            delivery: header("x-widget-delivery").unwrap_or_default(),
+            timestamp: header("x-widget-timestamp").unwrap_or_default(),
+            signature: header("x-widget-signature"),
+            body,
+        });
+        let scripted = receiver
+            .script
+            .lock()
+            .unwrap()
+            .get_mut(&hook)
+            .and_then(VecDeque::pop_front);
+        scripted
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .unwrap_or(StatusCode::NO_CONTENT)
+    }
+
+    async fn stand_in() -> (String, Receiver) {
+        let receiver = Receiver::default();
+        let app = Router::new()
+            .route("/:hook", post(receive))
+            .with_state(receiver.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, receiver)
+    }
+
+    fn webhooks() -> Arc<Webhooks> {
+        Arc::new(Webhooks {
+            first_retry: Duration::from_millis(10),
+            ..Webhooks::new(Arc::new(Mutex::new(Config::default())))
+        })
+    }
+
+    fn hook(base_url: &str, id: &str, secret: &str) -> Webhook {
+        Webhook {
+            id: id.to_string(),
+            url: format!("{}/{}", base_url, id),
+            secret: secret.to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn wait_for(receiver: &Receiver, hook: &str, count: usize) -> Vec<Received> {
+        for _ in 0..200 {
+            let received = receiver.received(hook);
+            if received.len() >= count {
+                return received;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{} did not receive {} requests", hook, count);
+    }
+
+    #[test]
+    fn signs_timestamp_and_body() {
+        assert_eq!(
+            signature(SECRET, 1_700_000_000, br#"{"event":"test"}"#).unwrap(),
+            "sha256=6f3e649509d14bd8ef6f209e445700fc03e1b945f57698228aeb6a0d64979dc1"
+        );
+        assert_ne!(
+            signature(SECRET, 1_700_000_001, br#"{"event":"test"}"#).unwrap(),
+            signature(SECRET, 1_700_000_000, br#"{"event":"test"}"#).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_with_fresh_signatures() {
+        let (url, receiver) = stand_in().await;
+        receiver.fail("a", &[503, 429]);
+        let webhooks = webhooks();
+
+        let delivery = webhooks
+            .deliver(
+                &hook(&url, "a", SECRET),
+                "track-changed",
+                None,
+                MAX_ATTEMPTS,
+            )
+            .await;
+        assert!(delivery.delivered);
+        assert_eq!(delivery.attempt, 3);
+        assert_eq!(delivery.status, Some(204));
+
+        let received = receiver.received("a");
+        assert_eq!(received.len(), 3);
+        for request in &received {
+            assert_eq!(request.event, "track-changed");
+            assert_eq!(request.delivery, delivery.id.to_string());
+            let timestamp: u64 = request.timestamp.parse().unwrap();
+            assert_eq!(
+                request.signature.as_deref(),
+                Some(
+                    signature(SECRET, timestamp, &request.body)
+                        .unwrap()
+                        .as_str()
+                )
+            );
+        }
+
+        let log = webhooks.recent(10);
+        assert_eq!(log.iter().map(|d| d.attempt).collect::<Vec<_>>(), [3, 2, 1]);
+        assert!(!log[1].delivered);
+    }
+
+    #[tokio::test]
+    async fn client_errors_and_exhausted_retries_give_up() {
+        let (url, receiver) = stand_in().await;
+        receiver.fail("refused", &[400]);
+        receiver.fail("down", &[503; MAX_ATTEMPTS as usize]);
+        let webhooks = webhooks();
+
+        let refused = webhooks
+            .deliver(&hook(&url, "refused", ""), "test", None, MAX_ATTEMPTS)
+            .await;
+        assert!(!refused.delivered);
+        assert_eq!(refused.attempt, 1);
+        assert_eq!(receiver.received("refused")[0].signature, None);
+
+        let down = webhooks
+            .deliver(&hook(&url, "down", ""), "test", None, MAX_ATTEMPTS)
+            .await;
+        assert!(!down.delivered);
+        assert_eq!(down.attempt, MAX_ATTEMPTS);
+        assert_eq!(receiver.received("down").len(), MAX_ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn each_webhook_gets_its_events_in_order() {
+        let (url, receiver) = stand_in().await;
+        receiver.fail("slow", &[503, 503]);
+        let webhooks = webhooks();
+
+        for event in ["track-changed", "playback-paused", "playback-resumed"] {
+            for id in ["slow", "fast"] {
+                webhooks.enqueue(Queued {
+                    hook: hook(&url, id, SECRET),
+                    event,
+                    snapshot: None,
+                });
+            }
+        }
+
+        let events = |received: Vec<Received>| -> Vec<String> {
+            received.into_iter().map(|r| r.event).collect()
+        };
+        assert_eq!(
+            events(wait_for(&receiver, "fast", 3).await),
+            ["track-changed", "playback-paused", "playback-resumed"]
+        );
+        assert_eq!(
+            events(wait_for(&receiver, "slow", 5).await),
+            [
+                "track-changed",
+                "track-changed",
+                "track-changed",
+                "playback-paused",
+                "playback-resumed"
+            ]
+        );
+
+        // Each queue's task exits once it is drained.
+        for _ in 0..200 {
+            if webhooks.queues.lock().unwrap().is_empty() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("webhook queues were not drained");
+    }
+}
@@ -0,0 +1,70 @@
+//! Request bodies. Without a template the payload is
+//! `{"event", "timestamp", "track"}` with the full playback snapshot.
+//!
+//! A template is a JSON document whose strings may use the now-playing
+//! placeholders plus `{event}` and `{timestamp}`. A string that is exactly
+//! `"{track}"` becomes the snapshot object. Placeholders are substituted
+//! into parsed strings, so values never break the JSON.
+
+use serde_json::{json, Value};
+
+use crate::{
+    now_playing::{self, PLACEHOLDERS},
+    playback::PlaybackSnapshot,
+};
+
+const TRACK: &str = "{track}";
+
+fn fill(
+    value: &mut Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    track: &Value,
+) -> Result<(), String> {
+    match value {
+        Value::String(text) if text == TRACK => *value = track.clone(),
+        Value::String(text) => *text = now_playing::render(text, lookup)?,
+        Value::Array(items) => {
+            for item in items {
+                fill(item, lookup, track)?;
+            }
+        }
+        Value::Object(fields) => {
+            for field in fields.values_mut() {
+                fill(field, lookup, track)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn build(
+    template: Option<&str>,
+    event: &str,
+    timestamp: u64,
+    snapshot: Option<&PlaybackSnapshot>,
+) -> Result<Value, String> {
+    let track = serde_json::to_value(snapshot).map_err(|e| e.to_string())?;
+    let Some(template) = template else {
+        return Ok(json!({ "event": event, "timestamp": timestamp, "track": track }));
+    };
+
+    let mut payload: Value =
+        serde_json::from_str(template).map_err(|e| format!("Invalid payload template: {}", e))?;
+    let lookup = |name: &str| match name {
+        "event" => Some(event.to_string()),
+        "timestamp" => Some(timestamp.to_string()),
+        // Known placeholders are empty when nothing is playing.
+        _ => match snapshot {
+            Some(snapshot) => now_playing::placeholder(name, snapshot),
+            None => PLACEHOLDERS.contains(&name).then(String::new),
+        },
+    };
+    fill(&mut payload, &lookup, &track)?;
+    Ok(payload)
+}
+
+/// Checks a template by rendering it with nothing playing.
+pub fn validate(template: &str) -> Result<(), String> {
+    build(Some(template), "", 0, None).map(|_| ())
+}
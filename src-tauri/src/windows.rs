@@ -0,0 +1,194 @@
+use tauri::{AppHandle, Manager, WebviewUrl, WebviewWindow, WebviewWindowBuilder, WindowEvent};
+
+use crate::config::{SharedConfig, WindowGeometry};
+
+/// Views that can be torn off the main widget into their own window. The
+/// label doubles as the frontend route the window loads.
+#[derive(Debug, Clone, Copy)]
+enum DetachedView {
+    Lyrics,
+    Queue,
+    History,
+}
+
+const DETACHED_VIEWS: [DetachedView; 3] = [
+    DetachedView::Lyrics,
+    DetachedView::Queue,
+    DetachedView::History,
+];
+
+impl DetachedView {
+    fn label(self) -> &'static str {
+        match self {
+            DetachedView::Lyrics => "lyrics",
+            DetachedView::Queue => "queue",
+            DetachedView::History => "history",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            DetachedView::Lyrics => "Lyrics",
+            DetachedView::Queue => "Queue",
+            DetachedView::History => "Listening History",
+        }
+    }
+
+    fn default_size(self) -> (f64, f64) {
+        match self {
+            DetachedView::Lyrics => (420.0, 560.0),
+            DetachedView::Queue => (380.0, 520.0),
+            DetachedView::History => (480.0, 600.0),
+        }
+    }
+}
+
+fn open_detached_window(
+    app_handle: &AppHandle,
+    config: &SharedConfig,
+    view: DetachedView,
+) -> Result<(), String> {
+    let label = view.label();
+
+    if let Some(window) = app_handle.get_webview_window(label) {
+        let _ = window.unminimize();
+        window.show().map_err(|e| e.to_string())?;
+        return window.set_focus().map_err(|e| e.to_string());
+    }
+
+    let saved = config
+        .lock()
+        .ok()
+        .and_then(|c| c.window_geometry.get(label).copied())
+        .filter(|g| is_on_screen(app_handle, g));
+
+    let (width, height) = saved
+        .map(|g| (g.width, g.height))
+        .unwrap_or_else(|| view.default_size());
+
+    let mut builder = WebviewWindowBuilder::new(app_handle, label, WebviewUrl::App(label.into()))
+        .title(format!("Spotify Widget - {}", view.title()))
+        .inner_size(width, height)
+        .min_inner_size(280.0, 200.0)
+        .resizable(true)
+        .always_on_top(true);
+
+    builder = match saved {
+        Some(geometry) => builder.position(geometry.x, geometry.y),
+        None => builder.center(),
+    };
+
+    let window = builder.build().map_err(|e| {
+        eprintln!("Failed to open {} window: {}", label, e);
+        e.to_string()
+    })?;
+
+    println!("Opened detached {} window", label);
+    track_geometry(&window, config.clone());
+    Ok(())
+}
+
+/// Saved positions can point at a monitor that has since been unplugged;
+/// windows that would not overlap any monitor are centred instead of
+/// opening off-screen.
+fn is_on_screen(app_handle: &AppHandle, geometry: &WindowGeometry) -> bool {
+    let monitors = match app_handle.available_monitors() {
+        Ok(monitors) if !monitors.is_empty() => monitors,
+        _ => return true,
+    };
+
+    monitors.iter().any(|monitor| {
+        let scale = monitor.scale_factor();
+        let position = monitor.position().to_logical::<f64>(scale);
+        let size = monitor.size().to_logical::<f64>(scale);
+        geometry.x < position.x + size.width
+            && geometry.x + geometry.width > position.x
+            && geometry.y < position.y + size.height
+            && geometry.y + geometry.height > position.y
+    })
+}
+
+fn current_geometry(window: &WebviewWindow) -> Option<WindowGeometry> {
+    if window.is_minimized().unwrap_or(false) {
+        return None;
+    }
+
+    let scale = window.scale_factor().ok()?;
+    let position = window.outer_position().ok()?.to_logical::<f64>(scale);
+    let size = window.inner_size().ok()?.to_logical::<f64>(scale);
+
+    Some(WindowGeometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    })
+}
+
+/// Keeps the in-memory geometry current while the window is dragged and
+/// writes it to disk once the window closes.
+fn track_geometry(window: &WebviewWindow, config: SharedConfig) {
+    let tracked = window.clone();
+
+    window.on_window_event(move |event| {
+        let persist = match event {
+            WindowEvent::Moved(_) | WindowEvent::Resized(_) => false,
+            WindowEvent::CloseRequested { .. } => true,
+            _ => return,
+        };
+
+        let Some(geometry) = current_geometry(&tracked) else {
+            return;
+        };
+
+        // Saved under the lock, like every other writer, so an older copy
+        // never overwrites a newer save.
+        let Ok(mut config) = config.lock() else {
+            return;
+        };
+        config
+            .window_geometry
+            .insert(tracked.label().to_string(), geometry);
+        if persist {
+            if let Err(e) = config.save() {
+                eprintln!("Failed to save window geometry: {}", e);
+            }
+        }
+    });
+}
+
+/// Detached windows follow the main widget; without this the app keeps
+/// running headless once the main window is closed.
+pub fn close_detached_windows(app_handle: &AppHandle) {
+    for view in DETACHED_VIEWS {
+        if let Some(window) = app_handle.get_webview_window(view.label()) {
+            if let Err(e) = window.close() {
+                eprintln!("Failed to close {} window: {}", view.label(), e);
+            }
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn open_lyrics_window(
+    app_handle: AppHandle,
+    config: tauri::State<'_, SharedConfig>,
+) -> Result<(), String> {
+    open_detached_window(&app_handle, config.inner(), DetachedView::Lyrics)
+}
+
+#[tauri::command]
+pub async fn open_queue_window(
+    app_handle: AppHandle,
+    config: tauri::State<'_, SharedConfig>,
+) -> Result<(), String> {
+    open_detached_window(&app_handle, config.inner(), DetachedView::Queue)
+}
+
+#[tauri::command]
+pub async fn open_history_window(
+    app_handle: AppHandle,
+    config: tauri::State<'_, SharedConfig>,
+) -> Result<(), String> {
+    open_detached_window(&app_handle, config.inner(), DetachedView::History)
+}
@@ -0,0 +1,406 @@
+//! WebSocket event stream at `/ws`, pushing the events the webview gets to
+//! overlays and dashboards. Needs the local API key or the read-only overlay
+//! key, as a header or a `key` query parameter. Either only subscribes;
+//! `hello` says which one was used.
+//!
+//! Every message is a JSON object with the schema version in `v` and a
+//! `type`. Events arrive as `{"v":1,"type":"event","event":"progress",
+//! "timestamp":..,"data":{..}}`; `data` matches the webview event payload
+//! of the same name. `auth` is the exception: the webview is handed the
+//! token itself as `spotify-auth-token`, while clients here only get
+//! `{"authenticated":bool}`. A client that falls behind is sent the current
+//! state again, as on connect, instead of the events it missed.
+//! `?events=track-changed,progress` limits the stream from the start, and
+//! clients can send `{"type":"subscribe","events":[..]}`,
+//! `{"type":"unsubscribe","events":[..]}` and `{"type":"ping"}` later.
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeSet,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    api::{self, Access},
+    config::SharedConfig,
+    lyrics::LyricsEngine,
+    playback::{PlaybackEvent, PlaybackHub, ProgressUpdate},
+    spotify::SpotifyClient,
+};
+
+/// Bumped whenever a message changes shape incompatibly.
+pub const SCHEMA_VERSION: u32 = 1;
+
+pub const EVENTS: &[&str] = &[
+    "track-changed",
+    "progress",
+    "playback-stopped",
+    "lyrics-line",
+    "auth",
+];
+
+/// How often the server sends a heartbeat message and a ping frame.
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// Connections that send nothing, not even a pong, for this long are
+/// closed.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Tokens also expire without any event, so auth is rechecked this often.
+const AUTH_CHECK: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize)]
+struct AuthState {
+    authenticated: bool,
+}
+
+#[derive(Clone)]
+struct StreamEvent {
+    name: &'static str,
+    message: Arc<str>,
+}
+
+/// Fans events out to every connected client. Messages are serialized
+/// once, however many clients there are.
+pub struct EventStream {
+    events: broadcast::Sender<StreamEvent>,
+}
+
+impl EventStream {
+    pub fn new() -> Self {
+        EventStream {
+            events: broadcast::channel(64).0,
+        }
+    }
+
+    fn publish<T: Serialize>(&self, name: &'static str, data: &T) {
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        match event_message(name, data) {
+            Ok(message) => {
+                let _ = self.events.send(StreamEvent {
+                    name,
+                    message: message.into(),
+                });
+            }
+            Err(e) => eprintln!("Failed to serialize {} for WebSocket clients: {}", name, e),
+        }
+    }
+}
+
+impl Default for EventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn event_message<T: Serialize>(name: &str, data: &T) -> Result<String, String> {
+    serde_json::to_string(&json!({
+        "v": SCHEMA_VERSION,
+        "type": "event",
+        "event": name,
+        "timestamp": now_ms(),
+        "data": data,
+    }))
+    .map_err(|e| e.to_string())
+}
+
+fn control_message(kind: &str, fields: Value) -> String {
+    let mut message = json!({ "v": SCHEMA_VERSION, "type": kind });
+    if let (Some(message), Value::Object(fields)) = (message.as_object_mut(), fields) {
+        message.extend(fields);
+    }
+    message.to_string()
+}
+
+/// Feeds playback, lyrics and auth changes into the stream.
+pub fn spawn_forwarders(
+    stream: Arc<EventStream>,
+    hub: Arc<PlaybackHub>,
+    lyrics: Arc<LyricsEngine>,
+    spotify: Arc<SpotifyClient>,
+) {
+    let mut playback = hub.subscribe();
+    let playback_stream = stream.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match playback.recv().await {
+                Ok(PlaybackEvent::TrackChanged(snapshot)) => {
+                    playback_stream.publish("track-changed", &snapshot)
+                }
+                Ok(PlaybackEvent::Progress(snapshot)) => {
+                    playback_stream.publish("progress", &ProgressUpdate::from(&snapshot))
+                }
+                Ok(PlaybackEvent::Stopped) => playback_stream.publish("playback-stopped", &()),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let mut lines = lyrics.subscribe_lines();
+    let lyrics_stream = stream.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match lines.recv().await {
+                Ok(position) => lyrics_stream.publish("lyrics-line", &position),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    tauri::async_runtime::spawn(async move {
+        let mut last: Option<bool> = None;
+        loop {
+            let authenticated = spotify.is_authenticated().await;
+            if last != Some(authenticated) {
+                last = Some(authenticated);
+                stream.publish("auth", &AuthState { authenticated });
+            }
+            tokio::select! {
+                _ = spotify.token_stored() => {}
+                _ = tokio::time::sleep(AUTH_CHECK) => {}
+            }
+        }
+    });
+}
+
+#[derive(Clone)]
+struct WsState {
+    config: SharedConfig,
+    stream: Arc<EventStream>,
+    hub: Arc<PlaybackHub>,
+    spotify: Arc<SpotifyClient>,
+}
+
+#[derive(Deserialize)]
+struct WsQuery {
+    key: Option<String>,
+    /// Comma-separated event names; all events when absent.
+    events: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe { events: Vec<String> },
+    Unsubscribe { events: Vec<String> },
+    Ping,
+}
+
+fn parse_events<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+) -> Result<BTreeSet<&'static str>, String> {
+    names
+        .into_iter()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            EVENTS
+                .iter()
+                .copied()
+                .find(|known| *known == name)
+                .ok_or_else(|| format!("Unknown event: {}", name))
+        })
+        .collect()
+}
+
+/// Applies a client message, returning the reply.
+fn handle_client_message(text: &str, subscribed: &mut BTreeSet<&'static str>) -> String {
+    let result = serde_json::from_str::<ClientMessage>(text)
+        .map_err(|e| e.to_string())
+        .and_then(|message| match message {
+            ClientMessage::Subscribe { events } => {
+                subscribed.extend(parse_events(events.iter().map(String::as_str))?);
+                Ok(control_message(
+                    "subscribed",
+                    json!({ "events": subscribed }),
+                ))
+            }
+            ClientMessage::Unsubscribe { events } => {
+                for name in parse_events(events.iter().map(String::as_str))? {
+                    subscribed.remove(name);
+                }
+                Ok(control_message(
+                    "subscribed",
+                    json!({ "events": subscribed }),
+                ))
+            }
+            ClientMessage::Ping => Ok(control_message("pong", json!({}))),
+        });
+    result.unwrap_or_else(|e| control_message("error", json!({ "message": e })))
+}
+
+/// What a client needs to render right away, before the next change.
+async fn initial_messages(state: &WsState, subscribed: &BTreeSet<&'static str>) -> Vec<String> {
+    let mut messages = Vec::new();
+    if subscribed.contains("auth") {
+        let authenticated = state.spotify.is_authenticated().await;
+        messages.extend(event_message("auth", &AuthState { authenticated }).ok());
+    }
+    if let Some(snapshot) = state.hub.current() {
+        if subscribed.contains("track-changed") {
+            messages.extend(event_message("track-changed", &snapshot).ok());
+        }
+        if subscribed.contains("progress") {
+            messages.extend(event_message("progress", &ProgressUpdate::from(&snapshot)).ok());
+        }
+    }
+    messages
+}
+
+/// The current state for a client that missed events. Unlike on connect,
+/// an idle player is reported, since the stop may be what was missed.
+async fn resync_messages(state: &WsState, subscribed: &BTreeSet<&'static str>) -> Vec<String> {
+    let mut messages = initial_messages(state, subscribed).await;
+    if state.hub.current().is_none() && subscribed.contains("playback-stopped") {
+        messages.extend(event_message("playback-stopped", &()).ok());
+    }
+    messages
+}
+
+/// False once the client is gone.
+async fn send_all(socket: &mut WebSocket, messages: Vec<String>) -> bool {
+    for message in messages {
+        if socket.send(Message::Text(message)).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+async fn serve(
+    mut socket: WebSocket,
+    state: WsState,
+    access: Access,
+    mut subscribed: BTreeSet<&'static str>,
+) {
+    let mut events = state.stream.events.subscribe();
+
+    let hello = control_message(
+        "hello",
+        json!({
+            "schema_version": SCHEMA_VERSION,
+            "events": EVENTS,
+            "subscribed": subscribed,
+            "heartbeat_ms": HEARTBEAT.as_millis() as u64,
+            "read_only": access == Access::ReadOnly,
+        }),
+    );
+    let mut greeting = vec![hello];
+    greeting.extend(initial_messages(&state, &subscribed).await);
+    if !send_all(&mut socket, greeting).await {
+        return;
+    }
+
+    let mut heartbeat = tokio::time::interval(HEARTBEAT);
+    heartbeat.tick().await;
+    let mut last_seen = Instant::now();
+
+    loop {
+        let outgoing = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if subscribed.contains(event.name) => {
+                    Some(Message::Text(event.message.to_string()))
+                }
+                Ok(_) => None,
+                Err(RecvError::Lagged(_)) => {
+                    let resync = resync_messages(&state, &subscribed).await;
+                    if !send_all(&mut socket, resync).await {
+                        break;
+                    }
+                    None
+                }
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(Message::Text(text))) => {
+                    last_seen = Instant::now();
+                    Some(Message::Text(handle_client_message(&text, &mut subscribed)))
+                }
+                Some(Ok(_)) => {
+                    last_seen = Instant::now();
+                    None
+                }
+            },
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > CLIENT_TIMEOUT {
+                    break;
+                }
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+                Some(Message::Text(control_message(
+                    "heartbeat",
+                    json!({ "timestamp": now_ms() }),
+                )))
+            }
+        };
+
+        if let Some(message) = outgoing {
+            if socket.send(message).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+async fn upgrade(
+    State(state): State<WsState>,
+    Query(query): Query<WsQuery>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let access = match api::check_key(&state.config, &headers, query.key.as_deref()) {
+        Ok(access) => access,
+        Err(e) => return e.into_response(),
+    };
+    let subscribed = match &query.events {
+        Some(names) => match parse_events(names.split(',')) {
+            Ok(subscribed) => subscribed,
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        },
+        None => EVENTS.iter().copied().collect(),
+    };
+    ws.on_upgrade(move |socket| serve(socket, state, access, subscribed))
+}
+
+/// The `/ws` route, to merge into the embedded server.
+pub fn router(
+    config: SharedConfig,
+    stream: Arc<EventStream>,
+    hub: Arc<PlaybackHub>,
+    spotify: Arc<SpotifyClient>,
+) -> Router {
+    Router::new()
+        .route("/ws", get(upgrade))
+        .with_state(WsState {
+            config,
+            stream,
+            hub,
+            spotify,
+        })
+}
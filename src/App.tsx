@@ -3,6 +3,9 @@
 import { BrowserRouter as Router, Routes, Route } from 'react-router-dom';
 import Player from './components/Player';
 import Settings from './components/Settings';
+import LyricsWindow from './components/LyricsWindow';
+import QueueWindow from './components/QueueWindow';
+import HistoryWindow from './components/HistoryWindow';
 import { NotificationProvider } from './contexts/NotificationContext';
 import './App.css';
 
@@ -15,6 +18,9 @@ export default function App() {
             <Route path="/" element={<Player />} />
             <Route path="/player" element={<Player />} />
             <Route path="/settings" element={<Settings />} />
+            <Route path="/lyrics" element={<LyricsWindow />} />
+            <Route path="/queue" element={<QueueWindow />} />
+            <Route path="/history" element={<HistoryWindow />} />
           </Routes>
         </Router>
       </div>
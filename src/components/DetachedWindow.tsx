@@ -0,0 +1,47 @@
+/**
+ * Detached Window
+ * Shared frame for views torn off the main widget into their own window
+ */
+
+import { ReactNode } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { useTheme } from '../hooks/useTheme';
+
+interface DetachedWindowProps {
+  title: string;
+  children: ReactNode;
+}
+
+export type DetachedView = 'lyrics' | 'queue' | 'history';
+
+/** Opens (or focuses) the detached window for a view. */
+export async function openDetachedWindow(view: DetachedView) {
+  try {
+    await invoke(`open_${view}_window`);
+  } catch (error) {
+    console.error(`Failed to open ${view} window:`, error);
+  }
+}
+
+export default function DetachedWindow({ title, children }: DetachedWindowProps) {
+  const { currentTheme } = useTheme();
+
+  return (
+    <div
+      className="w-full h-full flex flex-col overflow-hidden"
+      style={{
+        background: `linear-gradient(135deg, ${currentTheme.background} 0%, ${currentTheme.backgroundSecondary} 100%)`,
+        color: currentTheme.text,
+      }}
+    >
+      <div
+        data-tauri-drag-region
+        className="px-4 py-2 text-sm font-semibold border-b"
+        style={{ borderColor: currentTheme.border, color: currentTheme.textSecondary }}
+      >
+        {title}
+      </div>
+      <div className="flex-1 overflow-y-auto">{children}</div>
+    </div>
+  );
+}
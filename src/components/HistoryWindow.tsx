@@ -0,0 +1,66 @@
+/**
+ * History Window
+ * Recently played tracks from the backend history, in a detached window
+ */
+
+import { useEffect } from 'react';
+import { listen } from '@tauri-apps/api/event';
+import { useTheme } from '../hooks/useTheme';
+import { useTrackHistory } from '../hooks/useTrackHistory';
+import DetachedWindow from './DetachedWindow';
+
+function formatPlayedAt(playedAt: string) {
+  return new Date(playedAt).toLocaleString(undefined, {
+    month: 'short',
+    day: 'numeric',
+    hour: '2-digit',
+    minute: '2-digit',
+  });
+}
+
+export default function HistoryWindow() {
+  const { currentTheme } = useTheme();
+  const { history, addTrack } = useTrackHistory();
+
+  useEffect(() => {
+    const unlisten = listen<any>('track-changed', event => addTrack(event.payload));
+    return () => {
+      unlisten.then(unlisten => unlisten());
+    };
+  }, [addTrack]);
+
+  return (
+    <DetachedWindow title="Listening History">
+      {history.length === 0 ? (
+        <p className="p-6 text-center text-sm" style={{ color: currentTheme.textMuted }}>
+          No plays recorded yet
+        </p>
+      ) : (
+        <div className="p-2 space-y-1">
+          {history.map(item => (
+            <div
+              key={`${item.track.id}-${item.playedAt}`}
+              className="flex items-center p-2 rounded"
+              style={{ backgroundColor: `${currentTheme.backgroundSecondary}20` }}
+            >
+              {item.track.album.images[0] && (
+                <img src={item.track.album.images[0].url} alt="" className="w-8 h-8 rounded mr-2" />
+              )}
+              <div className="flex-1 min-w-0">
+                <p className="text-xs font-medium truncate" style={{ color: currentTheme.text }}>
+                  {item.track.name}
+                </p>
+                <p className="text-xs truncate" style={{ color: currentTheme.textSecondary }}>
+                  {item.track.artists.map(a => a.name).join(', ')}
+                </p>
+              </div>
+              <span className="text-xs ml-2 whitespace-nowrap" style={{ color: currentTheme.textMuted }}>
+                {formatPlayedAt(item.playedAt)}
+              </span>
+            </div>
+          ))}
+        </div>
+      )}
+    </DetachedWindow>
+  );
+}
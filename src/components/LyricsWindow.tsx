@@ -0,0 +1,88 @@
+/**
+ * Lyrics Window
+ * Synced lyrics from the backend sync engine, in a detached window
+ */
+
+import { useState, useEffect, useRef } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+import { useTheme } from '../hooks/useTheme';
+import DetachedWindow from './DetachedWindow';
+
+interface LyricLine {
+  time_ms: number;
+  text: string;
+}
+
+interface CurrentLyrics {
+  uri: string;
+  lyrics: { synced: boolean; lines: LyricLine[] } | null;
+  offset_ms: number;
+}
+
+interface LyricsPosition {
+  uri: string;
+  line_index: number | null;
+}
+
+export default function LyricsWindow() {
+  const { currentTheme } = useTheme();
+  const [current, setCurrent] = useState<CurrentLyrics | null>(null);
+  const [lineIndex, setLineIndex] = useState<number | null>(null);
+  const lineRefs = useRef<(HTMLParagraphElement | null)[]>([]);
+
+  useEffect(() => {
+    invoke<CurrentLyrics | null>('get_current_lyrics').then(setCurrent).catch(error => {
+      console.error('Failed to load lyrics:', error);
+    });
+    invoke<LyricsPosition | null>('get_lyrics_position')
+      .then(position => setLineIndex(position?.line_index ?? null))
+      .catch(error => console.error('Failed to load lyrics position:', error));
+
+    const unlistenLyrics = listen<CurrentLyrics>('lyrics-changed', event => {
+      setCurrent(event.payload);
+      setLineIndex(null);
+    });
+    const unlistenLine = listen<LyricsPosition>('lyrics-line', event => {
+      setLineIndex(event.payload.line_index);
+    });
+    return () => {
+      unlistenLyrics.then(unlisten => unlisten());
+      unlistenLine.then(unlisten => unlisten());
+    };
+  }, []);
+
+  useEffect(() => {
+    if (lineIndex !== null) {
+      lineRefs.current[lineIndex]?.scrollIntoView({ behavior: 'smooth', block: 'center' });
+    }
+  }, [lineIndex]);
+
+  const lyrics = current?.lyrics;
+
+  return (
+    <DetachedWindow title="Lyrics">
+      {!lyrics ? (
+        <p className="p-6 text-center text-sm" style={{ color: currentTheme.textMuted }}>
+          {current ? 'No lyrics found for this track' : 'Nothing playing'}
+        </p>
+      ) : (
+        <div className="px-6 py-[40vh] space-y-3">
+          {lyrics.lines.map((line, index) => {
+            const active = lyrics.synced && index === lineIndex;
+            return (
+              <p
+                key={index}
+                ref={el => { lineRefs.current[index] = el; }}
+                className={`text-lg transition-all ${active ? 'font-bold scale-105' : ''}`}
+                style={{ color: active || !lyrics.synced ? currentTheme.text : currentTheme.textMuted }}
+              >
+                {line.text || '♪'}
+              </p>
+            );
+          })}
+        </div>
+      )}
+    </DetachedWindow>
+  );
+}
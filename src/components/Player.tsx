@@ -18,7 +18,8 @@ import {
   ComputerDesktopIcon,
   PlusIcon,
   ChevronUpIcon,
-    ChevronDownIcon
+    ChevronDownIcon,
+  ArrowTopRightOnSquareIcon
 } from '@heroicons/react/24/solid';
 import { SpeakerXMarkIcon } from '@heroicons/react/24/outline';
 import ProgressBar from './ProgressBar';
@@ -31,6 +32,7 @@ import { useNotifications } from '../contexts/NotificationContext';
 import KeyboardShortcuts from './KeyboardShortcuts';
 import AudioSettings from './AudioSettings';
 import Lyrics from './Lyrics';
+import { openDetachedWindow } from './DetachedWindow';
 import '../api/spotify';
 
 type TabType = 'recent' | 'playlists' | 'search' | 'devices' | 'stats' | 'queue' | 'discover';
@@ -747,6 +749,16 @@ export default function Player() {
             )}
           </div>
 
+          {/* Lyrics Window */}
+          <button
+            onClick={() => openDetachedWindow('lyrics')}
+            className="p-2 rounded-lg transition-colors hover:bg-opacity-10"
+            style={{ color: currentTheme.textSecondary }}
+            title="Open lyrics window"
+          >
+            <MusicalNoteIcon className="w-4 h-4" />
+          </button>
+
           {/* Show/Hide Tabs */}
           <button
             onClick={() => setShowTabs(!showTabs)}
@@ -1094,6 +1106,15 @@ export default function Player() {
 
                   return (
                     <div className="space-y-4">
+                      <button
+                        onClick={() => openDetachedWindow('history')}
+                        className="flex items-center text-xs transition-colors hover:opacity-80"
+                        style={{ color: currentTheme.textSecondary }}
+                        title="Open history window"
+                      >
+                        <ArrowTopRightOnSquareIcon className="w-3.5 h-3.5 mr-1" />
+                        Listening history
+                      </button>
                       <div className="grid grid-cols-2 gap-3">
                         <div 
                           className="p-3 rounded-lg"
@@ -1191,9 +1212,19 @@ export default function Player() {
               <div className="p-2">
                 {queue?.queue?.length > 0 ? (
                   <div className="space-y-1">
-                    <p className="text-xs font-semibold mb-2" style={{ color: currentTheme.textSecondary }}>
-                      Up Next
-                    </p>
+                    <div className="flex items-center justify-between mb-2">
+                      <p className="text-xs font-semibold" style={{ color: currentTheme.textSecondary }}>
+                        Up Next
+                      </p>
+                      <button
+                        onClick={() => openDetachedWindow('queue')}
+                        className="p-1 rounded transition-colors hover:bg-opacity-10"
+                        style={{ color: currentTheme.textSecondary }}
+                        title="Open queue window"
+                      >
+                        <ArrowTopRightOnSquareIcon className="w-3.5 h-3.5" />
+                      </button>
+                    </div>
                     {queue.queue.slice(0, 20).map((track: any, index: number) => (
                       <div
                         key={`${track.id}-${index}`}
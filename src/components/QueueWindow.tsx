@@ -0,0 +1,96 @@
+/**
+ * Queue Window
+ * The upcoming Spotify queue in a detached window
+ */
+
+import { useState, useEffect, useCallback } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+import { useTheme } from '../hooks/useTheme';
+import DetachedWindow from './DetachedWindow';
+
+interface QueueItem {
+  id?: string;
+  name: string;
+  duration_ms: number;
+  artists?: { name: string }[];
+  album?: { images?: { url: string }[] };
+}
+
+interface Queue {
+  currently_playing: QueueItem | null;
+  queue: QueueItem[];
+}
+
+// Queue edits made in other Spotify clients are not announced, so poll too.
+const REFRESH_INTERVAL_MS = 15000;
+
+function formatDuration(ms: number) {
+  const seconds = Math.floor(ms / 1000);
+  return `${Math.floor(seconds / 60)}:${(seconds % 60).toString().padStart(2, '0')}`;
+}
+
+export default function QueueWindow() {
+  const { currentTheme } = useTheme();
+  const [queue, setQueue] = useState<Queue | null>(null);
+
+  const refresh = useCallback(async () => {
+    try {
+      setQueue(await invoke<Queue>('get_queue'));
+    } catch (error) {
+      console.error('Failed to load queue:', error);
+    }
+  }, []);
+
+  useEffect(() => {
+    refresh();
+    const interval = setInterval(refresh, REFRESH_INTERVAL_MS);
+    const unlisten = listen('track-changed', refresh);
+    return () => {
+      clearInterval(interval);
+      unlisten.then(unlisten => unlisten());
+    };
+  }, [refresh]);
+
+  const items = queue?.queue ?? [];
+
+  return (
+    <DetachedWindow title="Up Next">
+      {items.length === 0 ? (
+        <p className="p-6 text-center text-sm" style={{ color: currentTheme.textMuted }}>
+          The queue is empty
+        </p>
+      ) : (
+        <div className="p-2 space-y-1">
+          {items.map((track, index) => (
+            <div
+              key={`${track.id}-${index}`}
+              className="flex items-center p-2 rounded"
+              style={{ backgroundColor: `${currentTheme.backgroundSecondary}20` }}
+            >
+              <span className="text-xs w-6" style={{ color: currentTheme.textMuted }}>
+                {index + 1}
+              </span>
+              <img
+                src={track.album?.images?.[2]?.url || track.album?.images?.[0]?.url}
+                alt=""
+                className="w-8 h-8 rounded mx-2"
+              />
+              <div className="flex-1 min-w-0">
+                <p className="text-xs font-medium truncate" style={{ color: currentTheme.text }}>
+                  {track.name}
+                </p>
+                <p className="text-xs truncate" style={{ color: currentTheme.textSecondary }}>
+                  {track.artists?.map(a => a.name).join(', ')}
+                </p>
+              </div>
+              <span className="text-xs" style={{ color: currentTheme.textMuted }}>
+                {formatDuration(track.duration_ms)}
+              </span>
+            </div>
+          ))}
+        </div>
+      )}
+    </DetachedWindow>
+  );
+}
@@ -1,9 +1,10 @@
 ﻿/**
  * Track History Hook
- * Manages local track history with persistence
+ * Reads listening history recorded by the backend
  */
 
 import { useState, useEffect, useCallback } from 'react';
+import { invoke } from '@tauri-apps/api/core';
 import { SpotifyTrack } from '../api/spotify';
 
 interface TrackHistoryItem {
@@ -12,49 +13,109 @@ interface TrackHistoryItem {
   duration: number;
 }
 
-const MAX_HISTORY_ITEMS = 100;
-const STORAGE_KEY = 'spotify-track-history';
+/** A play as recorded by the backend history database. */
+interface Play {
+  id: number;
+  track_id: string | null;
+  uri: string;
+  title: string;
+  artists: string[];
+  album: string | null;
+  image_url: string | null;
+  duration_ms: number;
+  is_local: boolean;
+  started_at: number;
+  ms_played: number;
+  skipped: boolean;
+  completed: boolean;
+}
 
-export function useTrackHistory() {
-  const [history, setHistory] = useState<TrackHistoryItem[]>(() => {
-    try {
-      const stored = localStorage.getItem(STORAGE_KEY);
-      return stored ? JSON.parse(stored) : [];
-    } catch {
-      return [];
-    }
+interface HistoryPage {
+  items: Play[];
+  total: number;
+  page: number;
+  page_size: number;
+}
+
+const PAGE_SIZE = 100;
+
+// The backend records plays itself; give it a moment after a track change.
+const REFRESH_DELAY_MS = 3000;
+
+// Where the history lived before the backend recorded it.
+const LEGACY_STORAGE_KEY = 'spotify-track-history';
+
+/** Hands any history left in localStorage to the backend, once. */
+async function migrateLegacyHistory() {
+  const stored = localStorage.getItem(LEGACY_STORAGE_KEY);
+  if (!stored) return;
+
+  let entries: unknown;
+  try {
+    entries = JSON.parse(stored);
+  } catch {
+    localStorage.removeItem(LEGACY_STORAGE_KEY);
+    return;
+  }
+
+  // The backend skips entries it already has, so a retry after a failure,
+  // or two windows racing here, cannot duplicate plays.
+  await invoke<number>('import_local_history', {
+    entries: Array.isArray(entries) ? entries : [],
   });
+  localStorage.removeItem(LEGACY_STORAGE_KEY);
+}
 
-  useEffect(() => {
+function toHistoryItem(play: Play): TrackHistoryItem {
+  return {
+    track: {
+      id: play.track_id ?? play.uri,
+      name: play.title,
+      artists: play.artists.map(name => ({ name })),
+      album: {
+        name: play.album ?? '',
+        images: play.image_url ? [{ url: play.image_url, width: 0, height: 0 }] : [],
+      },
+      duration_ms: play.duration_ms,
+      uri: play.uri,
+      is_local: play.is_local,
+    },
+    playedAt: new Date(play.started_at).toISOString(),
+    duration: play.ms_played,
+  };
+}
+
+export function useTrackHistory() {
+  const [history, setHistory] = useState<TrackHistoryItem[]>([]);
+
+  const refresh = useCallback(async () => {
     try {
-      localStorage.setItem(STORAGE_KEY, JSON.stringify(history));
+      const page = await invoke<HistoryPage>('get_history', {
+        page: { page: 0, page_size: PAGE_SIZE },
+      });
+      setHistory(page.items.map(toHistoryItem));
     } catch (error) {
-      console.error('Failed to save track history:', error);
+      console.error('Failed to load track history:', error);
     }
-  }, [history]);
+  }, []);
 
-  const addTrack = useCallback((track: SpotifyTrack) => {
-    setHistory(prev => {
-      const lastTrack = prev[0];
-      if (lastTrack && lastTrack.track.id === track.id) {
-        const timeDiff = Date.now() - new Date(lastTrack.playedAt).getTime();
-        if (timeDiff < 60000) { // 1 minute
-          return prev;
-        }
-      }
+  useEffect(() => {
+    migrateLegacyHistory()
+      .catch(error => console.error('Failed to import old track history:', error))
+      .finally(refresh);
+  }, [refresh]);
 
-      const newItem: TrackHistoryItem = {
-        track,
-        playedAt: new Date().toISOString(),
-        duration: track.duration_ms,
-      };
-      const newHistory = [newItem, ...prev].slice(0, MAX_HISTORY_ITEMS);
-      return newHistory;
-    });
-  }, []);
+  const addTrack = useCallback((_track: SpotifyTrack) => {
+    setTimeout(refresh, REFRESH_DELAY_MS);
+  }, [refresh]);
 
-  const clearHistory = useCallback(() => {
-    setHistory([]);
+  const clearHistory = useCallback(async () => {
+    try {
+      await invoke('clear_history');
+      setHistory([]);
+    } catch (error) {
+      console.error('Failed to clear track history:', error);
+    }
   }, []);
 
   const getStats = useCallback(() => {